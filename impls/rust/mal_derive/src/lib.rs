@@ -0,0 +1,95 @@
+//! Derives `mal::convert::FromMal` and `IntoMal` for structs with named
+//! fields, mapping each field to a keyword key. Underscores in field names
+//! become dashes, so `max_depth` is read from and written to `:max-depth`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident};
+
+fn named_fields(input: &DeriveInput) -> Result<Vec<Ident>, Error> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(fields
+                .named
+                .iter()
+                .filter_map(|field| field.ident.clone())
+                .collect()),
+            _ => Err(Error::new_spanned(
+                &input.ident,
+                "mal conversions can only be derived for structs with named fields",
+            )),
+        },
+        _ => Err(Error::new_spanned(
+            &input.ident,
+            "mal conversions can only be derived for structs",
+        )),
+    }
+}
+
+fn key_name(field: &Ident) -> String {
+    field.to_string().trim_start_matches("r#").replace('_', "-")
+}
+
+fn expand_from_mal(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = named_fields(input)?;
+    let keys = fields.iter().map(key_name);
+    let type_name = name.to_string();
+    Ok(quote! {
+        impl #impl_generics ::mal::convert::FromMal for #name #ty_generics #where_clause {
+            fn from_mal(
+                value: ::mal::types::MalType,
+            ) -> ::std::result::Result<Self, ::mal::types::MalError> {
+                match value {
+                    ::mal::types::MalType::Map(map) => ::std::result::Result::Ok(#name {
+                        #(#fields: ::mal::convert::map_field(&map, #keys)?,)*
+                    }),
+                    other => ::std::result::Result::Err(::mal::types::MalError::Normal(
+                        ::std::format!("`{:?}` is not a map of {}", other, #type_name),
+                    )),
+                }
+            }
+        }
+    })
+}
+
+fn expand_into_mal(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = named_fields(input)?;
+    let keys = fields.iter().map(key_name);
+    Ok(quote! {
+        impl #impl_generics ::mal::convert::IntoMal for #name #ty_generics #where_clause {
+            fn into_mal(self) -> ::mal::types::MalType {
+                let mut map = ::mal::types::Map::new();
+                #(
+                    map.insert(
+                        ::mal::types::MalAtom::Keyword(::mal::keyword::Keyword::new(
+                            ::std::string::String::from(#keys),
+                        )),
+                        ::mal::convert::IntoMal::into_mal(self.#fields),
+                    );
+                )*
+                ::mal::types::MalType::Map(map.into())
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(FromMal)]
+pub fn derive_from_mal(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_mal(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(IntoMal)]
+pub fn derive_into_mal(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_into_mal(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,159 @@
+//! Atoms, mutable references to a value. `(atom x)` makes one holding `x`,
+//! `(deref a)` or `@a` returns what it holds, `(reset! a x)` replaces it and
+//! `(swap! a f args...)` replaces it with `(f @a args...)`. An atom is only
+//! equal to itself.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    eval::apply,
+    symbol::Symbol,
+    types::{MalAtom, MalError, MalFunction, MalType},
+};
+
+#[derive(Clone)]
+pub struct Ref(Rc<RefCell<MalType>>);
+
+impl Ref {
+    pub fn new(value: MalType) -> Self {
+        Ref(Rc::new(RefCell::new(value)))
+    }
+
+    pub fn get(&self) -> MalType {
+        self.0.borrow().clone()
+    }
+
+    pub fn set(&self, value: MalType) -> MalType {
+        *self.0.borrow_mut() = value.clone();
+        value
+    }
+}
+
+impl PartialEq for Ref {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+fn as_ref(name: &str, value: &MalType) -> Result<Ref, MalError> {
+    match value {
+        MalType::Ref(r) => Ok(r.clone()),
+        other => Err(MalError::Normal(format!(
+            "{} needs an atom. Given {}",
+            name, other
+        ))),
+    }
+}
+
+fn wrong_args(name: &str, need: &str, args: &[MalType]) -> MalError {
+    MalError::Normal(format!(
+        "Wrong number of args for {}. Need {}, received {}",
+        name,
+        need,
+        args.len(),
+    ))
+}
+
+fn atom(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [value] => Ok(MalType::Ref(Ref::new(value.clone()))),
+        _ => Err(wrong_args("atom", "1", &args)),
+    }
+}
+
+pub fn atom_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("atom"), atom)
+        .with_arglists("([x])")
+        .with_doc("Returns a new atom holding x.")
+}
+
+fn is_atom(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [value] => Ok(MalType::Atom(MalAtom::Boolean(matches!(
+            value,
+            MalType::Ref(_)
+        )))),
+        _ => Err(wrong_args("atom?", "1", &args)),
+    }
+}
+
+pub fn is_atom_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("atom?"), is_atom)
+        .with_arglists("([x])")
+        .with_doc("Returns true if x is an atom.")
+}
+
+fn deref(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [r] => Ok(as_ref("deref", r)?.get()),
+        _ => Err(wrong_args("deref", "1", &args)),
+    }
+}
+
+pub fn deref_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("deref"), deref)
+        .with_arglists("([a])")
+        .with_doc("Returns the value a holds. @a reads as (deref a).")
+}
+
+fn reset(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [r, value] => Ok(as_ref("reset!", r)?.set(value.clone())),
+        _ => Err(wrong_args("reset!", "2", &args)),
+    }
+}
+
+pub fn reset_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("reset!"), reset)
+        .with_arglists("([a x])")
+        .with_doc("Sets the value of a to x, returning x.")
+}
+
+fn swap(args: Vec<MalType>) -> Result<MalType, MalError> {
+    if args.len() < 2 {
+        return Err(wrong_args("swap!", "2 or more", &args));
+    }
+    let mut args = args.into_iter();
+    let r = as_ref("swap!", &args.next().unwrap())?;
+    let f = args.next().unwrap();
+    let value = apply(&f, std::iter::once(r.get()).chain(args).collect())?;
+    Ok(r.set(value))
+}
+
+pub fn swap_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("swap!"), swap)
+        .with_arglists("([a f & args])")
+        .with_doc("Sets the value of a to (f old args...), returning the new value.")
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![atom_fn(), is_atom_fn(), deref_fn(), reset_fn(), swap_fn()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn atoms() {
+        assert_eq!(
+            "[(atom 12) 1 2 12 12 true false]",
+            rep(
+                "(let* [a (atom 1)] [a @a (reset! a 2) (swap! a + 4 6) (deref a) \
+                 (atom? a) (atom? 1)])"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[true false]",
+            rep("(let* [a (atom 1)] [(= a a) (= a (atom 1))])").unwrap()
+        );
+        assert_eq!(
+            "[(deref a) (with-meta [1] {:a 1})]",
+            rep("(quote [@a ^{:a 1} [1]])").unwrap()
+        );
+        assert!(rep("(deref 1)").is_err());
+        assert!(rep("(swap! (atom 1) 2)").is_err());
+        assert!(rep("(swap! (atom 1))").is_err());
+    }
+}
@@ -1,18 +1,15 @@
 use crate::{
+    convert::FromMal,
+    dynamic,
+    lazy::{self, LazySeq},
+    printer::{pr_str, print_str},
+    record::Record,
     symbol::Symbol,
-    types::{MalAtom, MalError, MalFunction, MalType},
+    types::{List, MalAtom, MalError, MalFunction, MalType, WithMeta},
 };
 
 fn parse_numbers(args: Vec<MalType>) -> Result<Vec<isize>, MalError> {
-    let mut new_args = Vec::with_capacity(args.len());
-    for arg in args.iter() {
-        if let MalType::Atom(MalAtom::Integer(num)) = arg {
-            new_args.push(*num);
-        } else {
-            return Err(MalError::Normal(format!("`{:?}` is not a number", arg)));
-        }
-    }
-    Ok(new_args)
+    args.into_iter().map(isize::from_mal).collect()
 }
 
 fn plus(args: Vec<MalType>) -> Result<MalType, MalError> {
@@ -26,6 +23,8 @@ fn plus(args: Vec<MalType>) -> Result<MalType, MalError> {
 
 pub fn plus_fn() -> MalFunction {
     MalFunction::new(Symbol::new("+"), plus)
+        .with_arglists("([& xs])")
+        .with_doc("Returns the sum of xs, or 0 if there are none.")
 }
 
 fn minus(args: Vec<MalType>) -> Result<MalType, MalError> {
@@ -43,6 +42,8 @@ fn minus(args: Vec<MalType>) -> Result<MalType, MalError> {
 
 pub fn minus_fn() -> MalFunction {
     MalFunction::new(Symbol::new("-"), minus)
+        .with_arglists("([x] [x & ys])")
+        .with_doc("Returns x minus the sum of ys, or the negation of x if there are no ys.")
 }
 
 fn multiply(args: Vec<MalType>) -> Result<MalType, MalError> {
@@ -58,20 +59,417 @@ fn multiply(args: Vec<MalType>) -> Result<MalType, MalError> {
 
 pub fn multiply_fn() -> MalFunction {
     MalFunction::new(Symbol::new("*"), multiply)
+        .with_arglists("([& xs])")
+        .with_doc("Returns the product of xs, or 1 if there are none.")
 }
 
 fn divide(args: Vec<MalType>) -> Result<MalType, MalError> {
     let parsed_numbers = parse_numbers(args)?;
+    let quotient = |acc: isize, cur: isize| match acc.checked_div(cur) {
+        Some(quotient) => Ok(quotient),
+        None if cur == 0 => Err(MalError::Normal("Divide by zero".to_string())),
+        None => Err(MalError::Normal(format!("{} / {} overflows", acc, cur))),
+    };
     match parsed_numbers.len() {
         0 => Err(MalError::Normal("/ requires at least 1 arg".to_string())),
-        1 => Ok(MalType::Atom(MalAtom::Integer(1 / parsed_numbers[0]))),
+        1 => Ok(MalType::Atom(MalAtom::Integer(quotient(
+            1,
+            parsed_numbers[0],
+        )?))),
         _ => Ok(MalType::Atom(MalAtom::Integer({
             let base = parsed_numbers[0];
-            parsed_numbers[1..].iter().fold(base, |acc, cur| acc / cur)
+            parsed_numbers[1..]
+                .iter()
+                .try_fold(base, |acc, &cur| quotient(acc, cur))?
         }))),
     }
 }
 
 pub fn divide_fn() -> MalFunction {
     MalFunction::new(Symbol::new("/"), divide)
+        .with_arglists("([x] [x & ys])")
+        .with_doc("Returns x divided by each of ys in turn, rounding towards zero, or 1 divided by x if there are no ys.")
+}
+
+fn check_arity(name: &str, args: &[MalType], arity: usize) -> Result<(), MalError> {
+    if args.len() == arity {
+        Ok(())
+    } else {
+        Err(MalError::Normal(format!(
+            "Wrong number of args for {}. Need {}, received {}",
+            name,
+            arity,
+            args.len(),
+        )))
+    }
+}
+
+/// Returns the elements of a list, vector, map, string, lazy seq or nil,
+/// which all behave as sequences for the collection built-ins. A map's
+/// elements are `[key value]` vectors and a string's are one char strings.
+/// A lazy seq is realised in full; `lazy::iter` realises only what's used.
+pub fn as_seq(arg: &MalType) -> Result<Vec<MalType>, MalError> {
+    match arg {
+        MalType::List(l) => Ok(l.to_vec()),
+        MalType::Vector(v) => Ok(v.iter().cloned().collect()),
+        MalType::LazySeq(seq) => seq.to_vec(),
+        MalType::Map(WithMeta { value: m, .. }) | MalType::Record(Record { entries: m, .. }) => {
+            Ok(m.iter()
+                .map(|(k, v)| MalType::Vector(vec![MalType::Atom(k.clone()), v.clone()].into()))
+                .collect())
+        }
+        MalType::Atom(MalAtom::Str(s)) => Ok(s
+            .chars()
+            .map(|c| MalType::Atom(MalAtom::Str(c.to_string())))
+            .collect()),
+        MalType::Atom(MalAtom::Nil) => Ok(Vec::new()),
+        _ => Err(MalError::Normal(format!("`{:?}` is not a sequence", arg))),
+    }
+}
+
+fn compare_numbers(
+    name: &str,
+    args: Vec<MalType>,
+    cmp: fn(&isize, &isize) -> bool,
+) -> Result<MalType, MalError> {
+    check_arity(name, &args, 2)?;
+    let parsed_numbers = parse_numbers(args)?;
+    Ok(MalType::Atom(MalAtom::Boolean(cmp(
+        &parsed_numbers[0],
+        &parsed_numbers[1],
+    ))))
+}
+
+fn less_than(args: Vec<MalType>) -> Result<MalType, MalError> {
+    compare_numbers("<", args, isize::lt)
+}
+
+pub fn less_than_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("<"), less_than)
+        .with_arglists("([x y])")
+        .with_doc("Returns true if x is less than y.")
+}
+
+fn less_than_or_equal(args: Vec<MalType>) -> Result<MalType, MalError> {
+    compare_numbers("<=", args, isize::le)
+}
+
+pub fn less_than_or_equal_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("<="), less_than_or_equal)
+        .with_arglists("([x y])")
+        .with_doc("Returns true if x is less than or equal to y.")
+}
+
+fn greater_than(args: Vec<MalType>) -> Result<MalType, MalError> {
+    compare_numbers(">", args, isize::gt)
+}
+
+pub fn greater_than_fn() -> MalFunction {
+    MalFunction::new(Symbol::new(">"), greater_than)
+        .with_arglists("([x y])")
+        .with_doc("Returns true if x is greater than y.")
+}
+
+fn greater_than_or_equal(args: Vec<MalType>) -> Result<MalType, MalError> {
+    compare_numbers(">=", args, isize::ge)
+}
+
+pub fn greater_than_or_equal_fn() -> MalFunction {
+    MalFunction::new(Symbol::new(">="), greater_than_or_equal)
+        .with_arglists("([x y])")
+        .with_doc("Returns true if x is greater than or equal to y.")
+}
+
+fn equal(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("=", &args, 2)?;
+    Ok(MalType::Atom(MalAtom::Boolean(args[0] == args[1])))
+}
+
+pub fn equal_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("="), equal)
+        .with_arglists("([x y])")
+        .with_doc("Returns true if x and y are equal values. Lists and vectors with equal elements are equal.")
+}
+
+fn list(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::List(args.into()))
+}
+
+pub fn list_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("list"), list)
+        .with_arglists("([& items])")
+        .with_doc("Returns a list of items.")
+}
+
+fn is_list(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("list?", &args, 1)?;
+    Ok(MalType::Atom(MalAtom::Boolean(matches!(
+        args[0],
+        MalType::List(_)
+    ))))
+}
+
+pub fn is_list_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("list?"), is_list)
+        .with_arglists("([x])")
+        .with_doc("Returns true if x is a list.")
+}
+
+fn is_vector(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("vector?", &args, 1)?;
+    Ok(MalType::Atom(MalAtom::Boolean(matches!(
+        args[0],
+        MalType::Vector(_)
+    ))))
+}
+
+pub fn is_vector_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("vector?"), is_vector)
+        .with_arglists("([x])")
+        .with_doc("Returns true if x is a vector.")
+}
+
+fn is_map(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("map?", &args, 1)?;
+    Ok(MalType::Atom(MalAtom::Boolean(matches!(
+        args[0],
+        MalType::Map(_) | MalType::Record(_)
+    ))))
+}
+
+pub fn is_map_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("map?"), is_map)
+        .with_arglists("([x])")
+        .with_doc("Returns true if x is a map or record.")
+}
+
+fn count(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("count", &args, 1)?;
+    let len = match &args[0] {
+        MalType::Map(WithMeta { value: m, .. }) | MalType::Record(Record { entries: m, .. }) => {
+            m.len()
+        }
+        MalType::Atom(MalAtom::Str(s)) => s.chars().count(),
+        seq => as_seq(seq)?.len(),
+    };
+    Ok(MalType::Atom(MalAtom::Integer(len as isize)))
+}
+
+pub fn count_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("count"), count)
+        .with_arglists("([coll])")
+        .with_doc("Returns the number of elements of coll, entries of a map, or chars of a string. (count nil) is 0.")
+}
+
+fn is_empty(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("empty?", &args, 1)?;
+    let empty = match &args[0] {
+        MalType::Map(WithMeta { value: m, .. }) | MalType::Record(Record { entries: m, .. }) => {
+            m.is_empty()
+        }
+        MalType::Atom(MalAtom::Str(s)) => s.is_empty(),
+        seq => lazy::iter(seq)?.next().transpose()?.is_none(),
+    };
+    Ok(MalType::Atom(MalAtom::Boolean(empty)))
+}
+
+pub fn is_empty_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("empty?"), is_empty)
+        .with_arglists("([coll])")
+        .with_doc("Returns true if coll has no elements.")
+}
+
+fn first(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("first", &args, 1)?;
+    Ok(lazy::iter(&args[0])?
+        .next()
+        .transpose()?
+        .unwrap_or(MalType::Atom(MalAtom::Nil)))
+}
+
+pub fn first_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("first"), first)
+        .with_arglists("([coll])")
+        .with_doc("Returns the first element of coll, or nil if it is empty.")
+}
+
+fn rest(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("rest", &args, 1)?;
+    match &args[0] {
+        MalType::LazySeq(seq) => Ok(MalType::LazySeq(
+            seq.step()?.map_or_else(LazySeq::empty, |(_, rest)| rest),
+        )),
+        MalType::List(l) => Ok(MalType::List(l.rest())),
+        seq => Ok(MalType::List(as_seq(seq)?.into_iter().skip(1).collect())),
+    }
+}
+
+pub fn rest_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("rest"), rest)
+        .with_arglists("([coll])")
+        .with_doc(
+            "Returns the elements of coll after the first, or an empty list if there are none.",
+        )
+}
+
+fn nth(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("nth", &args, 2)?;
+    match &args[1] {
+        MalType::Atom(MalAtom::Integer(idx)) => {
+            if *idx < 0 {
+                return Err(MalError::Normal(format!("nth index {} is negative", idx)));
+            }
+            if let MalType::Vector(v) = &args[0] {
+                return v.get(*idx as usize).cloned().ok_or_else(|| {
+                    MalError::Normal(format!(
+                        "nth index {} out of bounds for sequence of length {}",
+                        idx,
+                        v.len(),
+                    ))
+                });
+            }
+            // Lazy seqs are only realised as far as `idx`.
+            let mut len = 0;
+            for item in lazy::iter(&args[0])? {
+                let item = item?;
+                if len == *idx as usize {
+                    return Ok(item);
+                }
+                len += 1;
+            }
+            Err(MalError::Normal(format!(
+                "nth index {} out of bounds for sequence of length {}",
+                idx, len,
+            )))
+        }
+        non_num => Err(MalError::Normal(format!("`{:?}` is not a number", non_num))),
+    }
+}
+
+pub fn nth_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("nth"), nth)
+        .with_arglists("([coll index])")
+        .with_doc("Returns the element of coll at index, counting from 0. Fails if there is none.")
+}
+
+fn cons(args: Vec<MalType>) -> Result<MalType, MalError> {
+    check_arity("cons", &args, 2)?;
+    if let MalType::LazySeq(rest) = &args[1] {
+        return Ok(MalType::LazySeq(LazySeq::cons(
+            args[0].clone(),
+            rest.clone(),
+        )));
+    }
+    let rest = match &args[1] {
+        MalType::List(l) => l.clone(),
+        seq => List::from(as_seq(seq)?),
+    };
+    Ok(MalType::List(rest.cons(args[0].clone())))
+}
+
+pub fn cons_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("cons"), cons)
+        .with_arglists("([x coll])")
+        .with_doc("Returns a list of x followed by the elements of coll.")
+}
+
+/// `(concat & colls)` lazily walks each of `colls` in turn, realising a
+/// lazy one only as far as it's used.
+fn concat(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let colls = args.iter().map(lazy::iter).collect::<Result<Vec<_>, _>>()?;
+    Ok(MalType::LazySeq(LazySeq::from_iter(
+        colls.into_iter().flatten(),
+    )))
+}
+
+pub fn concat_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("concat"), concat)
+        .with_arglists("([& colls])")
+        .with_doc("Returns a lazy seq of the elements of each of colls in turn.")
+}
+
+fn str(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Str(
+        args.iter().map(print_str).collect::<Vec<String>>().join(""),
+    )))
+}
+
+pub fn str_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("str"), str)
+        .with_arglists("([& xs])")
+        .with_doc("Returns the printed forms of xs joined together, with strings unquoted.")
+}
+
+fn pr_str_native(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Str(
+        args.iter().map(pr_str).collect::<Vec<String>>().join(" "),
+    )))
+}
+
+pub fn pr_str_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("pr-str"), pr_str_native)
+        .with_arglists("([& xs])")
+        .with_doc("Returns the readable printed forms of xs, separated by spaces.")
+}
+
+fn println(args: Vec<MalType>) -> Result<MalType, MalError> {
+    dynamic::write_out(&format!(
+        "{}\n",
+        args.iter()
+            .map(print_str)
+            .collect::<Vec<String>>()
+            .join(" ")
+    ))?;
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+pub fn println_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("println"), println)
+        .with_arglists("([& xs])")
+        .with_doc("Writes the printed forms of xs to *out*, separated by spaces and followed by a newline. Returns nil.")
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        plus_fn(),
+        minus_fn(),
+        multiply_fn(),
+        divide_fn(),
+        less_than_fn(),
+        less_than_or_equal_fn(),
+        greater_than_fn(),
+        greater_than_or_equal_fn(),
+        equal_fn(),
+        list_fn(),
+        is_list_fn(),
+        is_vector_fn(),
+        is_map_fn(),
+        count_fn(),
+        is_empty_fn(),
+        first_fn(),
+        rest_fn(),
+        nth_fn(),
+        cons_fn(),
+        concat_fn(),
+        str_fn(),
+        pr_str_fn(),
+        println_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn divide() {
+        assert_eq!(
+            "[2 -3 0 1]",
+            rep("[(/ 7 3) (/ 12 -2 2) (/ 2) (/ 1)]").unwrap()
+        );
+        for input in ["(/ 1 0)", "(/ 0)", "(/ 8 2 0)"] {
+            assert_eq!("Divide by zero", rep(input).unwrap_err().to_string());
+        }
+        assert!(rep("(/ -9223372036854775808 -1)").is_err());
+    }
 }
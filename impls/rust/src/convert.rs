@@ -0,0 +1,463 @@
+//! Conversions between `MalType` and Rust types, so native functions can
+//! take and return plain Rust values. `#[derive(FromMal, IntoMal)]` maps a
+//! struct with named fields to a map with keyword keys.
+
+use std::{collections::HashMap, convert::TryFrom, hash::Hash};
+
+pub use mal_derive::{FromMal, IntoMal};
+
+use crate::{
+    built_ins::as_seq,
+    keyword::Keyword,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType, Map},
+};
+
+pub trait FromMal: Sized {
+    fn from_mal(value: MalType) -> Result<Self, MalError>;
+}
+
+pub trait IntoMal {
+    fn into_mal(self) -> MalType;
+}
+
+/// What a typed native function may return: any `IntoMal` value, or a
+/// `Result` of one to report errors.
+pub trait IntoMalResult {
+    fn into_mal_result(self) -> Result<MalType, MalError>;
+}
+
+impl<T: IntoMal> IntoMalResult for T {
+    fn into_mal_result(self) -> Result<MalType, MalError> {
+        Ok(self.into_mal())
+    }
+}
+
+impl<T: IntoMal> IntoMalResult for Result<T, MalError> {
+    fn into_mal_result(self) -> Result<MalType, MalError> {
+        self.map(IntoMal::into_mal)
+    }
+}
+
+impl FromMal for MalType {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        Ok(value)
+    }
+}
+
+impl IntoMal for MalType {
+    fn into_mal(self) -> MalType {
+        self
+    }
+}
+
+impl IntoMal for MalAtom {
+    fn into_mal(self) -> MalType {
+        MalType::Atom(self)
+    }
+}
+
+/// Mal integers are `isize`s, so `into_mal` can't represent every `usize`,
+/// or on 32-bit targets every `i64` or `u32`. It saturates those out of
+/// range, making `usize::MAX` `isize::MAX`, rather than wrapping them.
+macro_rules! integer_conversions {
+    ($($int:ty),*) => {$(
+        impl FromMal for $int {
+            fn from_mal(value: MalType) -> Result<Self, MalError> {
+                match value {
+                    MalType::Atom(MalAtom::Integer(num)) => <$int>::try_from(num).map_err(|_| {
+                        MalError::Normal(format!(
+                            "{} is out of range for {}",
+                            num,
+                            stringify!($int),
+                        ))
+                    }),
+                    other => Err(MalError::Normal(format!("`{:?}` is not a number", other))),
+                }
+            }
+        }
+
+        impl IntoMal for $int {
+            fn into_mal(self) -> MalType {
+                let num = isize::try_from(self)
+                    .unwrap_or(if self > 0 { isize::MAX } else { isize::MIN });
+                MalType::Atom(MalAtom::Integer(num))
+            }
+        }
+    )*};
+}
+
+integer_conversions!(isize, i64, i32, usize, u32);
+
+impl FromMal for bool {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        match value {
+            MalType::Atom(MalAtom::Boolean(b)) => Ok(b),
+            other => Err(MalError::Normal(format!("`{:?}` is not a boolean", other))),
+        }
+    }
+}
+
+impl IntoMal for bool {
+    fn into_mal(self) -> MalType {
+        MalType::Atom(MalAtom::Boolean(self))
+    }
+}
+
+impl FromMal for String {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        match value {
+            MalType::Atom(MalAtom::Str(s)) => Ok(s),
+            other => Err(MalError::Normal(format!("`{:?}` is not a string", other))),
+        }
+    }
+}
+
+impl IntoMal for String {
+    fn into_mal(self) -> MalType {
+        MalType::Atom(MalAtom::Str(self))
+    }
+}
+
+impl IntoMal for &str {
+    fn into_mal(self) -> MalType {
+        MalType::Atom(MalAtom::Str(self.to_string()))
+    }
+}
+
+impl FromMal for Keyword {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        match value {
+            MalType::Atom(MalAtom::Keyword(k)) => Ok(k),
+            other => Err(MalError::Normal(format!("`{:?}` is not a keyword", other))),
+        }
+    }
+}
+
+impl IntoMal for Keyword {
+    fn into_mal(self) -> MalType {
+        MalType::Atom(MalAtom::Keyword(self))
+    }
+}
+
+impl FromMal for Symbol {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        match value {
+            MalType::Atom(MalAtom::Symbol(s)) => Ok(s),
+            other => Err(MalError::Normal(format!("`{:?}` is not a symbol", other))),
+        }
+    }
+}
+
+impl IntoMal for Symbol {
+    fn into_mal(self) -> MalType {
+        MalType::Atom(MalAtom::Symbol(self))
+    }
+}
+
+impl FromMal for () {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        match value {
+            MalType::Atom(MalAtom::Nil) => Ok(()),
+            other => Err(MalError::Normal(format!("`{:?}` is not nil", other))),
+        }
+    }
+}
+
+impl IntoMal for () {
+    fn into_mal(self) -> MalType {
+        MalType::Atom(MalAtom::Nil)
+    }
+}
+
+/// Nil is `None`, anything else must convert to `T`.
+impl<T: FromMal> FromMal for Option<T> {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        match value {
+            MalType::Atom(MalAtom::Nil) => Ok(None),
+            other => T::from_mal(other).map(Some),
+        }
+    }
+}
+
+impl<T: IntoMal> IntoMal for Option<T> {
+    fn into_mal(self) -> MalType {
+        match self {
+            Some(value) => value.into_mal(),
+            None => MalType::Atom(MalAtom::Nil),
+        }
+    }
+}
+
+/// Accepts a list, vector or nil, like the sequence built-ins.
+impl<T: FromMal> FromMal for Vec<T> {
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        as_seq(&value)?.into_iter().map(T::from_mal).collect()
+    }
+}
+
+impl<T: IntoMal> IntoMal for Vec<T> {
+    fn into_mal(self) -> MalType {
+        MalType::Vector(self.into_iter().map(IntoMal::into_mal).collect())
+    }
+}
+
+impl<K, V> FromMal for HashMap<K, V>
+where
+    K: FromMal + Eq + Hash,
+    V: FromMal,
+{
+    fn from_mal(value: MalType) -> Result<Self, MalError> {
+        match value {
+            MalType::Map(map) => map
+                .into_iter()
+                .map(|(k, v)| Ok((K::from_mal(MalType::Atom(k))?, V::from_mal(v)?)))
+                .collect(),
+            MalType::Atom(MalAtom::Nil) => Ok(HashMap::new()),
+            other => Err(MalError::Normal(format!("`{:?}` is not a map", other))),
+        }
+    }
+}
+
+/// Map keys must be atoms, so only key types converting to one are allowed.
+impl<K, V> IntoMal for HashMap<K, V>
+where
+    K: Into<MalAtom>,
+    V: IntoMal,
+{
+    fn into_mal(self) -> MalType {
+        MalType::Map(
+            self.into_iter()
+                .map(|(k, v)| (k.into(), v.into_mal()))
+                .collect(),
+        )
+    }
+}
+
+impl From<String> for MalAtom {
+    fn from(s: String) -> Self {
+        MalAtom::Str(s)
+    }
+}
+
+impl From<&str> for MalAtom {
+    fn from(s: &str) -> Self {
+        MalAtom::Str(s.to_string())
+    }
+}
+
+impl From<Keyword> for MalAtom {
+    fn from(k: Keyword) -> Self {
+        MalAtom::Keyword(k)
+    }
+}
+
+impl From<Symbol> for MalAtom {
+    fn from(s: Symbol) -> Self {
+        MalAtom::Symbol(s)
+    }
+}
+
+impl From<isize> for MalAtom {
+    fn from(num: isize) -> Self {
+        MalAtom::Integer(num)
+    }
+}
+
+impl From<bool> for MalAtom {
+    fn from(b: bool) -> Self {
+        MalAtom::Boolean(b)
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($len:expr; $($t:ident),+) => {
+        /// Accepts a list or vector of exactly as many items.
+        impl<$($t: FromMal),+> FromMal for ($($t,)+) {
+            fn from_mal(value: MalType) -> Result<Self, MalError> {
+                let items = as_seq(&value)?;
+                if items.len() != $len {
+                    return Err(MalError::Normal(format!(
+                        "`{:?}` does not have {} items",
+                        value, $len,
+                    )));
+                }
+                let mut items = items.into_iter();
+                Ok(($($t::from_mal(items.next().unwrap())?,)+))
+            }
+        }
+
+        impl<$($t: IntoMal),+> IntoMal for ($($t,)+) {
+            #[allow(non_snake_case)]
+            fn into_mal(self) -> MalType {
+                let ($($t,)+) = self;
+                MalType::Vector(::im_rc::vector![$($t.into_mal()),+].into())
+            }
+        }
+    };
+}
+
+tuple_conversions!(1; A);
+tuple_conversions!(2; A, B);
+tuple_conversions!(3; A, B, C);
+tuple_conversions!(4; A, B, C, D);
+
+/// Reads the field `key` of a derived struct from a map with keyword keys. A
+/// missing key reads as nil, so `Option` fields may be left out.
+pub fn map_field<T: FromMal>(map: &Map, key: &str) -> Result<T, MalError> {
+    match map.get(&MalAtom::Keyword(Keyword::new(key.to_string()))) {
+        Some(value) => T::from_mal(value.clone()),
+        None => T::from_mal(MalType::Atom(MalAtom::Nil))
+            .map_err(|_| MalError::Normal(format!("Missing key :{}", key))),
+    }
+}
+
+/// A Rust function whose args and result convert to and from mal values,
+/// e.g. `fn add(a: i64, b: i64) -> i64`.
+pub trait TypedFn<Args> {
+    fn into_native(self, name: Symbol) -> MalFunction;
+}
+
+macro_rules! typed_fn {
+    ($len:expr; $($t:ident),*) => {
+        impl<F, R, $($t),*> TypedFn<($($t,)*)> for F
+        where
+            F: Fn($($t),*) -> R + 'static,
+            R: IntoMalResult,
+            $($t: FromMal,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_native(self, name: Symbol) -> MalFunction {
+                MalFunction::new(name, move |args| {
+                    let mut args = args.into_iter();
+                    $(let $t = $t::from_mal(args.next().unwrap())?;)*
+                    self($($t),*).into_mal_result()
+                })
+                .with_arity(Arity::Fixed($len))
+            }
+        }
+    };
+}
+
+typed_fn!(0;);
+typed_fn!(1; A);
+typed_fn!(2; A, B);
+typed_fn!(3; A, B, C);
+typed_fn!(4; A, B, C, D);
+typed_fn!(5; A, B, C, D, E);
+
+impl MalFunction {
+    /// Wraps a typed Rust function, checking the arity and converting each
+    /// arg before it's called.
+    pub fn typed<Args, F: TypedFn<Args>>(name: Symbol, f: F) -> Self {
+        f.into_native(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        convert::{FromMal, IntoMal},
+        keyword::Keyword,
+        reader::read_str,
+        types::{MalError, MalType},
+        Interpreter,
+    };
+
+    #[derive(Debug, PartialEq, FromMal, IntoMal)]
+    struct Config {
+        name: String,
+        max_depth: i64,
+        tags: Vec<Keyword>,
+        parent: Option<String>,
+    }
+
+    fn read(input: &str) -> MalType {
+        read_str(input).unwrap().remove(0)
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(3, i64::from_mal(read("3")).unwrap());
+        assert_eq!("a", String::from_mal(read("\"a\"")).unwrap());
+        assert_eq!(None, Option::<bool>::from_mal(read("nil")).unwrap());
+        assert_eq!(vec![1, 2], Vec::<i32>::from_mal(read("(1 2)")).unwrap());
+        assert_eq!(
+            (1, String::from("b")),
+            <(i64, String)>::from_mal(read("[1 \"b\"]")).unwrap()
+        );
+        assert_eq!(read("[1 [true nil]]"), (1, (true, ())).into_mal());
+        assert_eq!(read(&isize::MAX.to_string()), usize::MAX.into_mal());
+        let mut map = HashMap::new();
+        map.insert(Keyword::new(String::from("a")), 1);
+        assert_eq!(map, HashMap::from_mal(read("{:a 1}")).unwrap());
+        assert_eq!(read("{:a 1}"), map.into_mal());
+        match i64::from_mal(read("\"1\"")) {
+            Err(MalError::Normal(err)) => assert_eq!("`Atom(Str(\"1\"))` is not a number", err),
+            other => panic!("expected a type error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn derive() {
+        let config = Config {
+            name: String::from("mal"),
+            max_depth: 3,
+            tags: vec![Keyword::new(String::from("lisp"))],
+            parent: None,
+        };
+        let value = read("{:name \"mal\" :max-depth 3 :tags [:lisp] :parent nil}");
+        assert_eq!(value, config.into_mal());
+        assert_eq!(
+            Config {
+                name: String::from("mal"),
+                max_depth: 3,
+                tags: vec![Keyword::new(String::from("lisp"))],
+                parent: None,
+            },
+            Config::from_mal(read("{:name \"mal\" :max-depth 3 :tags [:lisp]}")).unwrap()
+        );
+        match Config::from_mal(read("{:name \"mal\"}")) {
+            Err(MalError::Normal(err)) => assert_eq!("Missing key :max-depth", err),
+            other => panic!("expected a missing key error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn typed_functions() {
+        fn add(a: i64, b: i64) -> i64 {
+            a + b
+        }
+        let mut interpreter = Interpreter::new();
+        interpreter.register_typed("add", add);
+        interpreter.register_typed("greet", |name: String| format!("hi {}", name));
+        interpreter.register_typed("checked-div", |a: i64, b: i64| {
+            a.checked_div(b)
+                .ok_or_else(|| MalError::Normal(String::from("Divide by zero")))
+        });
+        assert_eq!(read("3"), interpreter.eval_str("(add 1 2)").unwrap());
+        assert_eq!(
+            read("\"hi mal\""),
+            interpreter.eval_str("(greet \"mal\")").unwrap()
+        );
+        match interpreter.eval_str("(add 1)") {
+            Err(MalError::Normal(err)) => {
+                assert_eq!("Wrong number of args for add. Need 2, received 1", err)
+            }
+            other => panic!("expected an arity error, got {:?}", other),
+        }
+        match interpreter.eval_str("(add 1 :b)") {
+            Err(MalError::Normal(err)) => assert_eq!(
+                "`Atom(Keyword(Keyword { name: \"b\" }))` is not a number",
+                err
+            ),
+            other => panic!("expected a type error, got {:?}", other),
+        }
+        match interpreter.eval_str("(checked-div 1 0)") {
+            Err(MalError::Normal(err)) => assert_eq!("Divide by zero", err),
+            other => panic!("expected a divide error, got {:?}", other),
+        }
+    }
+}
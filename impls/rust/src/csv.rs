@@ -0,0 +1,458 @@
+//! CSV built-ins, quoting per RFC 4180, so a misplaced or unclosed quote
+//! fails to read. Rows are read as vectors of strings, or with `{:header
+//! true}` as maps from the header row's names, as keywords, to each cell.
+//! Every function takes an optional map of options: `:delimiter`, a one
+//! character string, and `:header`. Any other option is an error.
+//!
+//! - `(csv-read s opts)` reads CSV text and `(csv-read-file path opts)` a
+//!   file, returning a vector of every row.
+//! - `(csv-each path f opts)` calls `f` with each row of a file as it's read,
+//!   so large files needn't fit in memory.
+//! - `(csv-write rows opts)` returns CSV text and `(csv-write-file path rows
+//!   opts)` writes it to a file row by row. Rows may be sequences or, given
+//!   `:header` as a vector of keys, maps; the header is written first.
+
+use std::{fs::File, io};
+
+use crate::{
+    eval::apply,
+    keyword::Keyword,
+    lazy,
+    printer::{pr_str, print_str},
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType},
+};
+
+struct Options {
+    delimiter: u8,
+    header: Option<MalType>,
+}
+
+fn options(options: Option<&MalType>) -> Result<Options, MalError> {
+    let mut parsed = Options {
+        delimiter: b',',
+        header: None,
+    };
+    let options = match options {
+        None | Some(MalType::Atom(MalAtom::Nil)) => return Ok(parsed),
+        Some(MalType::Map(options)) => options,
+        Some(other) => {
+            return Err(MalError::Normal(format!(
+                "`{:?}` is not a map of options",
+                other
+            )))
+        }
+    };
+    if let Some(key) = options.keys().find(
+        |key| !matches!(key, MalAtom::Keyword(k) if k.name == "delimiter" || k.name == "header"),
+    ) {
+        return Err(MalError::Normal(format!(
+            "{} isn't a CSV option. Use :delimiter or :header",
+            pr_str(&MalType::Atom(key.clone())),
+        )));
+    }
+    let get = |name: &str| options.get(&MalAtom::Keyword(Keyword::new(name.to_string())));
+    match get("delimiter") {
+        None => {}
+        Some(MalType::Atom(MalAtom::Str(s))) if s.len() == 1 && s.is_ascii() => {
+            parsed.delimiter = s.as_bytes()[0]
+        }
+        Some(other) => {
+            return Err(MalError::Normal(format!(
+                "`{:?}` is not a one character delimiter",
+                other
+            )))
+        }
+    }
+    parsed.header = get("header").filter(|header| header.is_truthy()).cloned();
+    Ok(parsed)
+}
+
+fn string_arg(arg: &MalType) -> Result<&str, MalError> {
+    match arg {
+        MalType::Atom(MalAtom::Str(s)) => Ok(s),
+        other => Err(MalError::Normal(format!("`{:?}` is not a string", other))),
+    }
+}
+
+fn csv_error(err: ::csv::Error) -> MalError {
+    MalError::Normal(format!("Invalid CSV: {}", err))
+}
+
+fn file_error(path: &str, err: io::Error) -> MalError {
+    MalError::Normal(format!("Can't open {}: {}", path, err))
+}
+
+/// Where a `QuoteCheck` is in the CSV it has passed on.
+#[derive(Clone, Copy, PartialEq)]
+enum Quoting {
+    FieldStart,
+    Unquoted,
+    Quoted,
+    /// A quote in a quoted field, which either closes it or, if another
+    /// follows, is an escaped quote.
+    QuoteInQuoted,
+}
+
+/// Passes CSV through unchanged, failing on quotes RFC 4180 doesn't allow,
+/// which the csv crate reads as best it can: a quote in an unquoted field,
+/// anything but a delimiter or line break after a closing quote, and a
+/// quoted field that's never closed.
+struct QuoteCheck<R> {
+    source: R,
+    delimiter: u8,
+    quoting: Quoting,
+    line: usize,
+}
+
+impl<R: io::Read> io::Read for QuoteCheck<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let invalid = |msg: &str, line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} on line {}", msg, line),
+            )
+        };
+        let len = self.source.read(buf)?;
+        if len == 0 && self.quoting == Quoting::Quoted {
+            return Err(invalid("quoted field not closed", self.line));
+        }
+        for &byte in &buf[..len] {
+            self.quoting = match (self.quoting, byte) {
+                (Quoting::Quoted, b'"') => Quoting::QuoteInQuoted,
+                (Quoting::Quoted, _) => Quoting::Quoted,
+                (Quoting::QuoteInQuoted, b'"') => Quoting::Quoted,
+                (_, b'\n' | b'\r') => Quoting::FieldStart,
+                (_, byte) if byte == self.delimiter => Quoting::FieldStart,
+                (Quoting::FieldStart, b'"') => Quoting::Quoted,
+                (Quoting::Unquoted, b'"') => {
+                    return Err(invalid("quote in an unquoted field", self.line))
+                }
+                (Quoting::QuoteInQuoted, _) => {
+                    return Err(invalid("text after a closing quote", self.line))
+                }
+                (Quoting::FieldStart | Quoting::Unquoted, _) => Quoting::Unquoted,
+            };
+            if byte == b'\n' {
+                self.line += 1;
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// Calls `each` with every row read from `source`, as a vector or, with
+/// the `:header` option, a map.
+fn read_rows<R: io::Read>(
+    source: R,
+    options: &Options,
+    mut each: impl FnMut(MalType) -> Result<(), MalError>,
+) -> Result<(), MalError> {
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.header.is_some())
+        .flexible(true)
+        .from_reader(QuoteCheck {
+            source,
+            delimiter: options.delimiter,
+            quoting: Quoting::FieldStart,
+            line: 1,
+        });
+    let header: Option<Vec<MalAtom>> = match options.header {
+        Some(_) => Some(
+            reader
+                .headers()
+                .map_err(csv_error)?
+                .iter()
+                .map(|name| MalAtom::Keyword(Keyword::new(name.to_string())))
+                .collect(),
+        ),
+        None => None,
+    };
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let cells = record
+            .iter()
+            .map(|cell| MalType::Atom(MalAtom::Str(cell.to_string())));
+        each(match &header {
+            Some(header) => MalType::Map(header.iter().cloned().zip(cells).collect()),
+            None => MalType::Vector(cells.collect()),
+        })?;
+    }
+    Ok(())
+}
+
+fn read_all<R: io::Read>(source: R, options: &Options) -> Result<MalType, MalError> {
+    let mut rows = Vec::new();
+    read_rows(source, options, |row| {
+        rows.push(row);
+        Ok(())
+    })?;
+    Ok(MalType::Vector(rows.into()))
+}
+
+fn csv_read(args: Vec<MalType>) -> Result<MalType, MalError> {
+    read_all(string_arg(&args[0])?.as_bytes(), &options(args.get(1))?)
+}
+
+pub fn csv_read_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("csv-read"), csv_read)
+        .with_arglists("([s] [s opts])")
+        .with_doc("Returns a vector of the rows of the CSV s, as vectors or, if opts has :header true, maps. opts may set :delimiter.")
+        .with_arity(Arity::Range(1, 2))
+}
+
+fn csv_read_file(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let path = string_arg(&args[0])?;
+    let file = File::open(path).map_err(|err| file_error(path, err))?;
+    read_all(io::BufReader::new(file), &options(args.get(1))?)
+}
+
+pub fn csv_read_file_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("csv-read-file"), csv_read_file)
+        .with_arglists("([path] [path opts])")
+        .with_doc("Returns a vector of the rows of the CSV file at path, as csv-read does.")
+        .with_arity(Arity::Range(1, 2))
+}
+
+fn csv_each(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let path = string_arg(&args[0])?;
+    let file = File::open(path).map_err(|err| file_error(path, err))?;
+    read_rows(io::BufReader::new(file), &options(args.get(2))?, |row| {
+        apply(&args[1], vec![row]).map(|_| ())
+    })?;
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+pub fn csv_each_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("csv-each"), csv_each)
+        .with_arglists("([path f] [path f opts])")
+        .with_doc(
+            "Calls f with each row of the CSV file at path as it's read, as csv-read reads them.",
+        )
+        .with_arity(Arity::Range(2, 3))
+}
+
+fn cell(value: &MalType) -> String {
+    match value {
+        MalType::Atom(MalAtom::Nil) => String::new(),
+        other => print_str(other),
+    }
+}
+
+/// The cells of a row, looking up each `:header` key in a map row.
+fn cells(row: &MalType, keys: Option<&[MalType]>) -> Result<Vec<String>, MalError> {
+    match (row, keys) {
+        (MalType::List(_) | MalType::Vector(_) | MalType::LazySeq(_), _) => lazy::iter(row)?
+            .map(|value| value.map(|value| cell(&value)))
+            .collect(),
+        (MalType::Map(map), Some(keys)) => keys
+            .iter()
+            .map(|key| match key {
+                MalType::Atom(key) => Ok(map.get(key).map(cell).unwrap_or_default()),
+                other => Err(MalError::Normal(format!(
+                    "`{:?}` can't be a map key",
+                    other
+                ))),
+            })
+            .collect(),
+        (MalType::Map(_), None) => Err(MalError::Normal(
+            "Writing map rows needs a :header vector of keys".to_string(),
+        )),
+        (other, _) => Err(MalError::Normal(format!("`{:?}` is not a row", other))),
+    }
+}
+
+/// Writes the header, if any, then each row of `rows` to `sink`. A lazy seq
+/// of rows is realised a row at a time as it's written.
+fn write_rows<W: io::Write>(sink: W, rows: &MalType, options: &Options) -> Result<(), MalError> {
+    let rows = match rows {
+        MalType::Atom(MalAtom::Nil)
+        | MalType::List(_)
+        | MalType::Vector(_)
+        | MalType::LazySeq(_) => lazy::iter(rows)?,
+        other => return Err(MalError::Normal(format!("`{:?}` is not a sequence", other))),
+    };
+    let mut writer = ::csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .flexible(true)
+        .from_writer(sink);
+    let keys = match options
+        .header
+        .as_ref()
+        .map(|header| (header, header.items()))
+    {
+        Some((_, Some(keys))) => {
+            let keys: Vec<MalType> = keys.cloned().collect();
+            let names = keys.iter().map(|key| match key {
+                MalType::Atom(MalAtom::Keyword(k)) => k.name.clone(),
+                other => cell(other),
+            });
+            writer.write_record(names).map_err(csv_error)?;
+            Some(keys)
+        }
+        Some((other, None)) => {
+            return Err(MalError::Normal(format!(
+                "`{:?}` is not a vector of header keys",
+                other
+            )))
+        }
+        None => None,
+    };
+    for row in rows {
+        writer
+            .write_record(cells(&row?, keys.as_deref())?)
+            .map_err(csv_error)?;
+    }
+    writer
+        .flush()
+        .map_err(|err| MalError::Normal(format!("Can't write CSV: {}", err)))
+}
+
+fn csv_write(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut csv = Vec::new();
+    write_rows(&mut csv, &args[0], &options(args.get(1))?)?;
+    Ok(MalType::Atom(MalAtom::Str(
+        String::from_utf8(csv).expect("CSV written from strings is UTF-8"),
+    )))
+}
+
+pub fn csv_write_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("csv-write"), csv_write)
+        .with_arglists("([rows] [rows opts])")
+        .with_doc("Returns rows as CSV. Rows may be sequences or, if opts has :header as a vector of keys, maps. opts may set :delimiter.")
+        .with_arity(Arity::Range(1, 2))
+}
+
+fn csv_write_file(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let path = string_arg(&args[0])?;
+    let file = File::create(path).map_err(|err| file_error(path, err))?;
+    write_rows(io::BufWriter::new(file), &args[1], &options(args.get(2))?)?;
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+pub fn csv_write_file_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("csv-write-file"), csv_write_file)
+        .with_arglists("([path rows] [path rows opts])")
+        .with_doc("Writes rows to the file at path as csv-write does.")
+        .with_arity(Arity::Range(2, 3))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        csv_read_fn(),
+        csv_read_file_fn(),
+        csv_each_fn(),
+        csv_write_fn(),
+        csv_write_file_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::{interpreter::Interpreter, test_util::rep_in};
+
+    #[test]
+    fn read_and_write() {
+        let mut mal = Interpreter::new();
+        assert_eq!(
+            "[[\"a\" \"b,c\"] [\"1\" \"say \\\"hi\\\"\"]]",
+            rep_in(
+                r#"(csv-read "a,\"b,c\"\n1,\"say \"\"hi\"\"\"\n")"#,
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "true",
+            rep_in(
+                r#"(= (csv-read "x;y\n1;2" {:delimiter ";" :header true}) [{:x "1" :y "2"}])"#,
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "\"x;y\\n1;\\\"a;b\\\"\\n\"",
+            rep_in(
+                r#"(csv-write [{:x 1 :y "a;b"}] {:delimiter ";" :header [:x :y]})"#,
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "\"1,,:k\\n\"",
+            rep_in("(csv-write [[1 nil :k]])", &mut mal).unwrap()
+        );
+        assert_eq!(
+            "\"1,2\\n2,4\\n3,6\\n\"",
+            rep_in(
+                "(csv-write (map (fn* [x] (map (fn* [y] (* x y)) [1 2])) (range 1 4)))",
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "\"a\\n1\\n\"",
+            rep_in(
+                "(csv-write (filter map? [{:a 1} 2]) {:header [:a]})",
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert!(rep_in("(csv-write [{:x 1}])", &mut mal).is_err());
+        assert!(rep_in("(csv-write \"ab\")", &mut mal).is_err());
+        assert!(rep_in("(csv-read \"a\" {:delimiter \";;\"})", &mut mal).is_err());
+        assert_eq!(
+            ":separator isn't a CSV option. Use :delimiter or :header",
+            rep_in(r#"(csv-read "a;b" {:separator ";"})"#, &mut mal)
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(rep_in("(csv-write [[1]] {:headers [:a]})", &mut mal).is_err());
+        for (input, err) in [
+            (r#""a,\"b\n1,2""#, "quoted field not closed on line 2"),
+            (r#""a,b\"c\n""#, "quote in an unquoted field on line 1"),
+            (r#""a\n\"b\"c,d""#, "text after a closing quote on line 2"),
+        ] {
+            assert_eq!(
+                format!("Invalid CSV: {}", err),
+                rep_in(&format!("(csv-read {})", input), &mut mal)
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn files() {
+        let mut mal = Interpreter::new();
+        let path = env::temp_dir().join(format!("mal-csv-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap().replace('\\', "/");
+        rep_in(
+            &format!(
+                "(csv-write-file \"{}\" [[1 2] [3 4]] {{:header [:a :b]}})",
+                path
+            ),
+            &mut mal,
+        )
+        .unwrap();
+        assert_eq!(
+            "[[\"a\" \"b\"] [\"1\" \"2\"] [\"3\" \"4\"]]",
+            rep_in(&format!("(csv-read-file \"{}\")", path), &mut mal).unwrap()
+        );
+        rep_in("(def! total (atom 0))", &mut mal).unwrap();
+        rep_in(
+            &format!(
+                "(csv-each \"{}\" (fn* [row] (if (map? row) (swap! total + 1))) \
+                 {{:header true}})",
+                path
+            ),
+            &mut mal,
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!("2", rep_in("@total", &mut mal).unwrap());
+    }
+}
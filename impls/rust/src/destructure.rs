@@ -0,0 +1,274 @@
+//! Destructuring, binding the parts of a value to the symbols of a binding
+//! form, as `let*`, `fn*` params and `loop` do. A binding form is
+//!
+//! - a symbol, bound to the whole value,
+//! - a vector `[a b & rest :as all]`, binding each form to the element in
+//!   the same place of a sequence, the form after `&` to the elements left
+//!   over, and the symbol after `:as` to the whole sequence, or
+//! - a map `{:keys [x y] :or {y 0} :as m a :a}`, binding the symbols in
+//!   `:keys`, `:strs` and `:syms` to the values of the keyword, string and
+//!   symbol keys of the same name, each other symbol to the value of its
+//!   key, and the symbol after `:as` to the whole map. A missing key binds its
+//!   default from `:or` if there is one, or nil.
+//!
+//! Forms nest, so `[[a b] {:keys [c]}]` takes apart a sequence of a pair and
+//! a map. Missing elements bind nil, like missing keys.
+
+use crate::{
+    built_ins::as_seq,
+    env::Env,
+    eval::eval_form,
+    keyword::Keyword,
+    record::Record,
+    symbol::Symbol,
+    types::{MalAtom, MalError, MalType, Map, WithMeta},
+};
+
+fn nil() -> MalType {
+    MalType::Atom(MalAtom::Nil)
+}
+
+fn invalid(form: &MalType) -> MalError {
+    MalError::Normal(format!("`{}` is not a valid binding form", form))
+}
+
+fn keyword(name: &str) -> MalAtom {
+    MalAtom::Keyword(Keyword::new(name.to_string()))
+}
+
+/// Binds the symbols of `form` in the innermost layer of `env` to the parts
+/// of `value`.
+pub fn bind(env: &mut Env, form: &MalType, value: MalType) -> Result<(), MalError> {
+    match form {
+        MalType::Atom(MalAtom::Symbol(s)) => {
+            env.set(s.clone(), value);
+            Ok(())
+        }
+        MalType::Vector(forms) => bind_seq(env, &forms.iter().cloned().collect::<Vec<_>>(), value),
+        MalType::Map(forms) => bind_map(env, forms, value),
+        other => Err(invalid(other)),
+    }
+}
+
+/// The first element of a sequence and the sequence of the rest, or `None`
+/// and nil once it's empty.
+fn split_first(value: MalType) -> Result<(Option<MalType>, MalType), MalError> {
+    Ok(match value {
+        MalType::Atom(MalAtom::Nil) => (None, nil()),
+        MalType::List(l) => (l.first().cloned(), MalType::List(l.rest())),
+        MalType::Vector(v) if v.is_empty() => (None, nil()),
+        MalType::Vector(v) => (v.front().cloned(), MalType::Vector(v.skip(1).into())),
+        MalType::LazySeq(seq) => match seq.step()? {
+            Some((first, rest)) => (Some(first), MalType::LazySeq(rest)),
+            None => (None, nil()),
+        },
+        MalType::Atom(MalAtom::Str(_)) | MalType::Map(_) => {
+            split_first(MalType::List(as_seq(&value)?.into()))?
+        }
+        other => {
+            return Err(MalError::Normal(format!(
+                "Can't destructure `{}` as a sequence",
+                other
+            )))
+        }
+    })
+}
+
+/// What's left of a sequence after the bound elements, as a list or lazy
+/// seq, or nil if nothing is.
+fn rest_value(rest: MalType) -> Result<MalType, MalError> {
+    Ok(match rest {
+        MalType::List(l) if l.is_empty() => nil(),
+        MalType::List(l) => MalType::List(l),
+        MalType::Vector(v) if v.is_empty() => nil(),
+        MalType::Vector(v) => MalType::List(v.into_iter().collect()),
+        MalType::LazySeq(seq) => match seq.step()? {
+            Some(_) => MalType::LazySeq(seq),
+            None => nil(),
+        },
+        other => other,
+    })
+}
+
+/// Binds the forms of a vector form to the elements of `value`. `fn*`
+/// binds its params this way, with a list of its args as `value`.
+pub fn bind_seq(env: &mut Env, forms: &[MalType], value: MalType) -> Result<(), MalError> {
+    let invalid_seq = || invalid(&MalType::Vector(forms.iter().cloned().collect()));
+    let mut rest = value.clone();
+    let mut forms = forms.iter();
+    while let Some(elem_form) = forms.next() {
+        match elem_form {
+            MalType::Atom(MalAtom::Symbol(s)) if s.name == "&" => {
+                let rest_form = forms.next().ok_or_else(invalid_seq)?;
+                bind(env, rest_form, rest_value(rest)?)?;
+                rest = nil();
+            }
+            MalType::Atom(MalAtom::Keyword(k)) if k.name == "as" => {
+                match (forms.next(), forms.next()) {
+                    (Some(MalType::Atom(MalAtom::Symbol(s))), None) => {
+                        env.set(s.clone(), value);
+                        return Ok(());
+                    }
+                    _ => return Err(invalid_seq()),
+                }
+            }
+            elem_form => {
+                let (first, next) = split_first(rest)?;
+                bind(env, elem_form, first.unwrap_or_else(nil))?;
+                rest = next;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The entries of a map, nil as none, or of a list of alternating keys and
+/// values as `& {:keys [...]}` params receive.
+fn as_map(value: &MalType) -> Result<Map, MalError> {
+    match value {
+        MalType::Map(WithMeta { value: m, .. }) | MalType::Record(Record { entries: m, .. }) => {
+            Ok(m.clone())
+        }
+        MalType::Atom(MalAtom::Nil) => Ok(Map::new()),
+        MalType::List(_) | MalType::LazySeq(_) => {
+            let items = as_seq(value)?;
+            if !items.len().is_multiple_of(2) {
+                return Err(MalError::Normal(format!(
+                    "Can't destructure `{}` as a map: it has an odd number of items",
+                    value
+                )));
+            }
+            items
+                .chunks(2)
+                .map(|kv| match &kv[0] {
+                    MalType::Atom(key) => Ok((key.clone(), kv[1].clone())),
+                    other => Err(MalError::Normal(format!("`{}` can't be a map key", other))),
+                })
+                .collect()
+        }
+        other => Err(MalError::Normal(format!(
+            "Can't destructure `{}` as a map",
+            other
+        ))),
+    }
+}
+
+/// Binds the forms of the map form `forms` to the values of `value`.
+fn bind_map(env: &mut Env, forms: &Map, value: MalType) -> Result<(), MalError> {
+    let form = MalType::Map(forms.clone().into());
+    let map = as_map(&value)?;
+    let defaults = match forms.get(&keyword("or")) {
+        Some(MalType::Map(defaults)) => defaults.value.clone(),
+        Some(_) => return Err(invalid(&form)),
+        None => Map::new(),
+    };
+    let bind_key = |env: &mut Env, target: &MalType, key: MalAtom| -> Result<(), MalError> {
+        let value = match map.get(&key) {
+            Some(value) => value.clone(),
+            None => match target {
+                MalType::Atom(sym @ MalAtom::Symbol(_)) => match defaults.get(sym) {
+                    Some(default) => eval_form(default.clone(), env)?,
+                    None => nil(),
+                },
+                _ => nil(),
+            },
+        };
+        bind(env, target, value)
+    };
+    for (key, target) in forms {
+        match (key, target) {
+            (MalAtom::Keyword(k), names) if ["keys", "strs", "syms"].contains(&k.name.as_str()) => {
+                let names = names.items().ok_or_else(|| invalid(&form))?;
+                for name in names {
+                    let sym = match name {
+                        MalType::Atom(MalAtom::Symbol(sym)) => sym,
+                        _ => return Err(invalid(&form)),
+                    };
+                    let key = match k.name.as_str() {
+                        "keys" => keyword(&sym.name),
+                        "strs" => MalAtom::Str(sym.name.clone()),
+                        _ => MalAtom::Symbol(Symbol::new(&sym.name)),
+                    };
+                    bind_key(env, name, key)?;
+                }
+            }
+            (MalAtom::Keyword(k), MalType::Atom(MalAtom::Symbol(s))) if k.name == "as" => {
+                env.set(s.clone(), value.clone());
+            }
+            (MalAtom::Keyword(k), _) if k.name == "or" => {}
+            (MalAtom::Symbol(sym), MalType::Atom(key)) => {
+                bind_key(
+                    env,
+                    &MalType::Atom(MalAtom::Symbol(sym.clone())),
+                    key.clone(),
+                )?;
+            }
+            _ => return Err(invalid(&form)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn sequential() {
+        assert_eq!(
+            "[1 2 (3 4) [1 2 3 4] nil nil]",
+            rep("(let* [[a b & more :as all] [1 2 3 4] [x y] [nil]] [a b more all x y])").unwrap()
+        );
+        assert_eq!(
+            "[1 3 (4 5) \"a\" (\"b\")]",
+            rep(
+                "(let* [[[a] [_ b & c]] (list [1 2] (range 2 6)) [s & t] \"ab\"] \
+                 [a b (take 2 c) s t])"
+            )
+            .unwrap()
+        );
+        assert_eq!("[0 1]", rep("(let* [[a b & r] (range)] [a b])").unwrap());
+    }
+
+    #[test]
+    fn associative() {
+        assert_eq!(
+            "[1 0 {:x 1} 2 \"s\" 3 nil]",
+            rep("(let* [{:keys [x y] :or {y (- 1 1)} :as m} {:x 1} \
+                        {a :a :strs [s] :syms [q] n :n} {:a 2 \"s\" \"s\" q 3}] \
+                 [x y m a s q n])")
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn fn_params() {
+        assert_eq!(
+            "[3 (2 3) 6 7]",
+            rep("(def! f (fn* [[a b] {:keys [c]}] (+ a b c))) \
+                 (def! g (fn* [_ & {:keys [k]}] k)) \
+                 (def! h (fn* [[{x :x} y] z] (list x y z))) \
+                 [(f [1 2] {:c 0}) (rest (h [{:x 1} 2] 3)) (f [1 2] {:c 3}) (g 0 :k 7)]")
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "Can't destructure `1` as a sequence",
+            rep("(let* [[a] 1] a)").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Can't destructure `[1]` as a map",
+            rep("(let* [{:keys [a]} [1]] a)").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "`1` is not a valid binding form",
+            rep("(let* [1 1] 1)").unwrap_err().to_string()
+        );
+        assert!(rep("(let* [[a &] [1]] a)").is_err());
+        assert!(rep("(let* [{:keys [1]} {}] 1)").is_err());
+        assert!(rep("((fn* [& {:keys [a]}] a) :a)").is_err());
+    }
+}
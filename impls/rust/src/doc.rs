@@ -0,0 +1,208 @@
+//! Documentation of what's defined, for the REPL:
+//!
+//! - `(doc name)` writes the arglists and docstring of `name` to `*out*`,
+//! - `(find-doc pattern)` does so for everything whose name or docstring
+//!   matches the regex `pattern`, and
+//! - `(apropos pattern)` returns the names matching `pattern`, where a string
+//!   matches the names containing it.
+//!
+//! A var's documentation is the `:arglists` and `:doc` that `def!` and
+//! `defn` gave it or, failing those, the native function's own.
+
+use std::fmt;
+
+use regex::Regex;
+
+use crate::{
+    dynamic,
+    env::Env,
+    keyword::Keyword,
+    pattern,
+    printer::pr_str,
+    symbol::Symbol,
+    types::{MalAtom, MalError, MalType},
+};
+
+struct Doc {
+    name: Symbol,
+    arglists: Option<String>,
+    doc: Option<String>,
+}
+
+impl Doc {
+    /// The documentation of `name`, or `None` if it isn't defined.
+    fn of(name: &Symbol, env: &Env) -> Option<Self> {
+        let (mut arglists, mut doc) = match env.find(name)? {
+            MalType::Function(f) => (
+                f.arglists.map(|a| a.to_string()),
+                f.doc.map(|d| d.to_string()),
+            ),
+            _ => (None, None),
+        };
+        let meta = env.var_meta(name).unwrap_or_default();
+        let key = |name: &str| MalAtom::Keyword(Keyword::new(name.to_string()));
+        if let Some(var_arglists) = meta.get(&key("arglists")) {
+            arglists = Some(pr_str(var_arglists));
+        }
+        if let Some(MalType::Atom(MalAtom::Str(var_doc))) = meta.get(&key("doc")) {
+            doc = Some(var_doc.clone());
+        }
+        Some(Doc {
+            name: name.clone(),
+            arglists,
+            doc,
+        })
+    }
+}
+
+impl fmt::Display for Doc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "-------------------------")?;
+        writeln!(f, "{}", self.name)?;
+        if let Some(arglists) = &self.arglists {
+            writeln!(f, "{}", arglists)?;
+        }
+        if let Some(doc) = &self.doc {
+            writeln!(f, "  {}", doc)?;
+        }
+        Ok(())
+    }
+}
+
+/// `(doc name)`, with `name` unevaluated.
+pub fn doc(args: Vec<MalType>, env: &Env) -> Result<MalType, MalError> {
+    let name = match args.as_slice() {
+        [MalType::Atom(MalAtom::Symbol(name))] => name,
+        _ => {
+            return Err(MalError::Normal(format!(
+                "doc needs a name. Given {:?}",
+                args
+            )))
+        }
+    };
+    let doc = Doc::of(name, env).ok_or_else(|| MalError::Resolve(name.get_name()))?;
+    dynamic::write_out(&doc.to_string())?;
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+/// `(find-doc pattern)`, with `pattern` a regex or a string of one.
+pub fn find_doc(pattern: &MalType, env: &Env) -> Result<MalType, MalError> {
+    let re = pattern::as_pattern(pattern)?;
+    for name in env.names() {
+        let doc = match Doc::of(&name, env) {
+            Some(doc) => doc,
+            None => continue,
+        };
+        if re.is_match(&name.name) || doc.doc.as_deref().is_some_and(|d| re.is_match(d)) {
+            dynamic::write_out(&doc.to_string())?;
+        }
+    }
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+/// `(apropos pattern)`, with `pattern` a regex or a string to search for.
+pub fn apropos(pattern: &MalType, env: &Env) -> Result<MalType, MalError> {
+    let re = match pattern {
+        MalType::Atom(MalAtom::Str(s)) => Regex::new(&regex::escape(s)).unwrap(),
+        other => pattern::as_pattern(other)?,
+    };
+    Ok(MalType::List(
+        env.names()
+            .into_iter()
+            .filter(|name| re.is_match(&name.name))
+            .map(|name| MalType::Atom(MalAtom::Symbol(name)))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        env::Env,
+        test_util::{out, rep},
+        types::MalType,
+    };
+
+    #[test]
+    fn doc() {
+        assert_eq!(
+            "-------------------------\n\
+             cons\n\
+             ([x coll])\n  \
+             Returns a list of x followed by the elements of coll.\n",
+            out("(doc cons)").unwrap()
+        );
+        assert_eq!(
+            "-------------------------\n\
+             add\n\
+             ([x] [x y])\n  \
+             Adds numbers.\n\
+             -------------------------\n\
+             plus\n\
+             ([& xs])\n  \
+             Returns the sum of xs, or 0 if there are none.\n",
+            out("(defn add \"Adds numbers.\" ([x] x) ([x y] (+ x y))) \
+                 (def! plus +) \
+                 (doc add) (doc plus)")
+            .unwrap()
+        );
+        assert_eq!(
+            "-------------------------\nf\n([x])\n",
+            out("(defn f [x] x) (doc f)").unwrap()
+        );
+        assert_eq!(
+            "[3 nil]",
+            rep("(defn add [x y] (+ x y)) (binding [*out* (atom \"\")] [(add 1 2) (doc add)])")
+                .unwrap()
+        );
+        assert!(rep("(doc nonexistent)").is_err());
+        assert!(rep("(defn 1 [] 1)").is_err());
+    }
+
+    #[test]
+    fn find_doc() {
+        assert_eq!(
+            "-------------------------\n\
+             empty?\n\
+             ([coll])\n  \
+             Returns true if coll has no elements.\n\
+             -------------------------\n\
+             f\n\
+             ([])\n  \
+             Has no elements either.\n",
+            out("(defn f \"Has no elements either.\" [] nil) (find-doc \"no elements\")").unwrap()
+        );
+        assert_eq!(
+            "(list list?)",
+            rep("(apropos (re-pattern \"^list\"))").unwrap()
+        );
+        assert_eq!(
+            "[(my-list?) (my-list? nth)]",
+            rep(
+                "(def! my-list? list?) \
+                 [(apropos \"y-li\") \
+                  (filter (fn* [s] (re-find (re-pattern \"^my-|^nth$\") (str s))) (apropos \"\"))]"
+            )
+            .unwrap()
+        );
+        assert!(rep("(find-doc 1)").is_err());
+    }
+
+    #[test]
+    fn natives_documented() {
+        let env = Env::repl();
+        for name in env.names() {
+            if let Some(MalType::Function(f)) = env.find(&name) {
+                assert!(f.arglists.is_some(), "{} has no arglists", name);
+                assert!(f.doc.is_some(), "{} has no docstring", name);
+            }
+        }
+        assert_eq!(
+            "-------------------------\n\
+             map\n\
+             ([f coll & colls])\n  \
+             Returns a lazy seq of f called with an element of each coll in turn, until the shortest runs out.\n",
+            out("(doc map)").unwrap()
+        );
+    }
+}
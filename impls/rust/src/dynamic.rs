@@ -0,0 +1,190 @@
+//! Dynamic vars, which `binding` gives new values for the dynamic extent of
+//! its body. A var is dynamic if it's defined with `(def! ^:dynamic *x* 1)`
+//! or `(def-dynamic *x* 1)`.
+//!
+//! Four dynamic vars configure the host, which reads them from thread-local
+//! state kept in step with their values:
+//!
+//! - `*out*`, where `println` and `pprint` write: `:stdout`, `:stderr`, or an
+//!   atom holding a string that output is appended to,
+//! - `*print-length*`, how many elements of a lazy seq are printed, or nil
+//!   for all of them,
+//! - `*print-readably*`, whether `pr-str` and the REPL escape strings, and
+//! - `*trace-macros*`, whether each macro expansion `eval` performs is
+//!   written to `*out*` with where the expanded form was read from.
+//!
+//! That state is shared by every environment on the thread, so two
+//! `Interpreter`s on one thread see each other's settings.
+
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Write},
+};
+
+use crate::{
+    keyword::Keyword,
+    printer,
+    symbol::Symbol,
+    types::{MalAtom, MalError, MalType},
+};
+
+thread_local! {
+    static OUT: RefCell<MalType> = RefCell::new(keyword("stdout"));
+    static TRACE_MACROS: Cell<bool> = const { Cell::new(false) };
+}
+
+fn keyword(name: &str) -> MalType {
+    MalType::Atom(MalAtom::Keyword(Keyword::new(name.to_string())))
+}
+
+/// The dynamic vars every REPL environment defines, with their initial
+/// values.
+pub fn host_vars() -> Vec<(Symbol, MalType)> {
+    vec![
+        (Symbol::new("*out*"), keyword("stdout")),
+        (
+            Symbol::new("*print-length*"),
+            MalType::Atom(MalAtom::Integer(100)),
+        ),
+        (
+            Symbol::new("*print-readably*"),
+            MalType::Atom(MalAtom::Boolean(true)),
+        ),
+        (
+            Symbol::new("*trace-macros*"),
+            MalType::Atom(MalAtom::Boolean(false)),
+        ),
+    ]
+}
+
+/// Whether macro expansions are being traced, per `*trace-macros*`.
+pub fn tracing_macros() -> bool {
+    TRACE_MACROS.with(Cell::get)
+}
+
+/// Updates the host's state for the var `name`, if it's one of
+/// `host_vars`, to follow its new value. Fails without changing anything if
+/// the value isn't one the var can have.
+pub fn set_host(name: &Symbol, value: &MalType) -> Result<(), MalError> {
+    let invalid = || {
+        MalError::Normal(format!(
+            "`{}` is not a valid value for {}",
+            printer::pr_str(value),
+            name
+        ))
+    };
+    match name.name.as_str() {
+        "*out*" => {
+            let valid = match value {
+                MalType::Atom(MalAtom::Keyword(k)) => k.name == "stdout" || k.name == "stderr",
+                MalType::Ref(_) => true,
+                _ => false,
+            };
+            if !valid {
+                return Err(invalid());
+            }
+            OUT.with(|out| *out.borrow_mut() = value.clone());
+        }
+        "*print-length*" => printer::set_print_length(match value {
+            MalType::Atom(MalAtom::Nil) => None,
+            MalType::Atom(MalAtom::Integer(n)) if *n >= 0 => Some(*n as usize),
+            _ => return Err(invalid()),
+        }),
+        "*print-readably*" => printer::set_print_readably(value.is_truthy()),
+        "*trace-macros*" => TRACE_MACROS.with(|trace| trace.set(value.is_truthy())),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Writes `text` to `*out*`.
+pub fn write_out(text: &str) -> Result<(), MalError> {
+    let out = OUT.with(|out| out.borrow().clone());
+    let written = match &out {
+        MalType::Ref(r) => {
+            let mut buffer = match r.get() {
+                MalType::Atom(MalAtom::Str(s)) => s,
+                MalType::Atom(MalAtom::Nil) => String::new(),
+                other => {
+                    return Err(MalError::Normal(format!(
+                        "*out* is an atom holding `{}`, not a string",
+                        other
+                    )))
+                }
+            };
+            buffer.push_str(text);
+            r.set(MalType::Atom(MalAtom::Str(buffer)));
+            Ok(())
+        }
+        MalType::Atom(MalAtom::Keyword(k)) if k.name == "stderr" => {
+            io::stderr().write_all(text.as_bytes())
+        }
+        _ => io::stdout().write_all(text.as_bytes()),
+    };
+    written.map_err(|err| MalError::Normal(format!("Couldn't write to *out*: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        interpreter::Interpreter,
+        test_util::{rep, rep_in},
+    };
+
+    #[test]
+    fn binding() {
+        assert_eq!(
+            "[2 [3 1] 1 1]",
+            rep("(def! ^:dynamic *x* 1) \
+                 (def-dynamic *y* 1) \
+                 (def! get-x (fn* [] *x*)) \
+                 [(binding [*x* 2] (get-x)) \
+                  (binding [*x* 2 *y* *x*] (binding [*x* 3] [(get-x) *y*])) \
+                  (get-x) *y*]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[2 2 [9 2] 1]",
+            rep("(def! ^:dynamic *x* 1) \
+                 (def! f (fn* [] *x*)) \
+                 [(let* [*x* 9] (binding [*x* 2] (f))) \
+                  ((fn* [*x*] (binding [*x* 2] (f))) 5) \
+                  (let* [*x* 9] (binding [*x* 2] [*x* (f)])) \
+                  (f)]")
+            .unwrap()
+        );
+        let mut mal = Interpreter::new();
+        rep_in("(def! ^{:dynamic true} *x* 1)", &mut mal).unwrap();
+        assert!(rep_in("(binding [*x* 2 *print-length* 3] (nth [] *x*))", &mut mal).is_err());
+        assert_eq!("[1 100]", rep_in("[*x* *print-length*]", &mut mal).unwrap());
+        assert_eq!("(0 1 2 3)", rep_in("(take 4 (range))", &mut mal).unwrap());
+        assert_eq!(
+            "Can't dynamically bind non-dynamic var x",
+            rep("(def! x 1) (binding [x 2] x)").unwrap_err().to_string()
+        );
+        assert!(rep("(binding [*x* 2] 1)").is_err());
+        assert!(rep("(def-dynamic *x* 1) (binding [*x*] 1)").is_err());
+    }
+
+    #[test]
+    fn host_vars() {
+        assert_eq!(
+            "[\"a 1\\n\\\"b\\\"\\n\" \"(0 1 ...)\" \"(0 1 2 3 4)\" \"\\\"s\\\"\" \"s\" \"\\\"s\\\"\"]",
+            rep("(def! out (atom \"\")) \
+                 (binding [*out* out] (println \"a\" 1) (pprint \"b\")) \
+                 [@out \
+                  (binding [*print-length* 2] (pr-str (range))) \
+                  (pr-str (take 5 (range))) \
+                  (pr-str \"s\") \
+                  (binding [*print-readably* false] (pr-str \"s\")) \
+                  (pr-str \"s\")]")
+            .unwrap()
+        );
+        assert!(rep("(binding [*print-length* -1] 1)").is_err());
+        assert!(rep("(binding [*out* 1] 1)").is_err());
+        assert_eq!(
+            "100",
+            rep("(binding [*print-length* 2] 1) *print-length*").unwrap()
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! An EDN reader and writer. Unlike `reader::read_str`, which reads code,
+//! this reads exactly the EDN grammar, expands no reader macros and never
+//! evaluates anything, so it's safe to use on untrusted input.
+//!
+//! EDN values without a mal counterpart are read as a map
+//! `{:edn/tag tag :edn/value value}`, which `write-edn` writes back out as
+//! the original EDN:
+//!
+//! - a set `#{1 2}` has the tag `:set` and its items as a vector,
+//! - a character `\a` the tag `:char` and a one character string,
+//! - a float `1.5` the tag `:float` and its text as a string,
+//! - a map with keys other than atoms the tag `:map` and a vector of
+//!   `[key value]` pairs, and
+//! - a tagged element `#inst "..."` its tag as a symbol and the element.
+
+use std::collections::HashSet;
+
+use pest::{iterators::Pair, Parser};
+
+use crate::{
+    keyword::Keyword,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType, Map},
+};
+
+#[derive(Parser)]
+#[grammar = "parser/edn.pest"]
+pub struct EdnParser;
+
+fn keyword(name: &str) -> MalAtom {
+    MalAtom::Keyword(Keyword::new(name.to_string()))
+}
+
+fn tagged(tag: MalAtom, value: MalType) -> MalType {
+    let mut map = Map::new();
+    map.insert(keyword("edn/tag"), MalType::Atom(tag));
+    map.insert(keyword("edn/value"), value);
+    MalType::Map(map.into())
+}
+
+/// The tag and value of a map made by `tagged`.
+fn as_tagged(map: &Map) -> Option<(&MalAtom, &MalType)> {
+    if map.len() != 2 {
+        return None;
+    }
+    match (map.get(&keyword("edn/tag")), map.get(&keyword("edn/value"))) {
+        (Some(MalType::Atom(tag)), Some(value)) => Some((tag, value)),
+        _ => None,
+    }
+}
+
+fn unicode_escape(hex: &str) -> Result<char, MalError> {
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| MalError::Parsing(format!(": \\u{} is not a valid character", hex)))
+}
+
+fn unescape_str(s: &str) -> Result<String, MalError> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some('b') => unescaped.push('\u{8}'),
+            Some('f') => unescaped.push('\u{c}'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                unescaped.push(unicode_escape(&hex)?);
+            }
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    Ok(unescaped)
+}
+
+fn parse_character(s: &str) -> Result<char, MalError> {
+    match &s[1..] {
+        "newline" => Ok('\n'),
+        "return" => Ok('\r'),
+        "space" => Ok(' '),
+        "tab" => Ok('\t'),
+        hex if hex.len() == 5 && hex.starts_with('u') => unicode_escape(&hex[1..]),
+        c => Ok(c.chars().next().unwrap()),
+    }
+}
+
+fn parse_integer(s: &str) -> Result<isize, MalError> {
+    s.trim_end_matches('N')
+        .parse()
+        .map_err(|_| MalError::Parsing(format!(": {} is out of range for a mal integer", s)))
+}
+
+fn parse_elements(pair: Pair<Rule>) -> Result<Vec<MalType>, MalError> {
+    pair.into_inner()
+        .filter(|p| p.as_rule() != Rule::discard)
+        .map(parse_value)
+        .collect()
+}
+
+/// An EDN value as a set member. EDN values are plain data, so `==` is an
+/// equivalence on them.
+#[derive(PartialEq, Hash)]
+struct Distinct<'a>(&'a MalType);
+
+impl Eq for Distinct<'_> {}
+
+fn check_distinct(kind: &str, items: &[MalType]) -> Result<(), MalError> {
+    let mut seen = HashSet::with_capacity(items.len());
+    for item in items {
+        if !seen.insert(Distinct(item)) {
+            return Err(MalError::Parsing(format!(
+                ": Duplicate {} {}",
+                kind,
+                write_edn(item)?
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn parse_map(items: Vec<MalType>) -> Result<MalType, MalError> {
+    if !items.len().is_multiple_of(2) {
+        return Err(MalError::Parsing(
+            ": A map needs an even number of forms".to_string(),
+        ));
+    }
+    let keys: Vec<MalType> = items.iter().step_by(2).cloned().collect();
+    check_distinct("map key", &keys)?;
+    if keys.iter().all(|k| matches!(k, MalType::Atom(_))) {
+        let mut map = Map::new();
+        let mut items = items.into_iter();
+        while let (Some(MalType::Atom(k)), Some(v)) = (items.next(), items.next()) {
+            map.insert(k, v);
+        }
+        Ok(MalType::Map(map.into()))
+    } else {
+        let entries = items
+            .chunks(2)
+            .map(|entry| MalType::Vector(entry.iter().cloned().collect()))
+            .collect();
+        Ok(tagged(keyword("map"), MalType::Vector(entries)))
+    }
+}
+
+fn parse_value(pair: Pair<Rule>) -> Result<MalType, MalError> {
+    let text = pair.as_str();
+    Ok(match pair.as_rule() {
+        Rule::list => MalType::List(parse_elements(pair)?.into()),
+        Rule::vector => MalType::Vector(parse_elements(pair)?.into()),
+        Rule::map => parse_map(parse_elements(pair)?)?,
+        Rule::set => {
+            let items = parse_elements(pair)?;
+            check_distinct("set item", &items)?;
+            tagged(keyword("set"), MalType::Vector(items.into()))
+        }
+        Rule::tagged => {
+            let mut inner = pair.into_inner().filter(|p| p.as_rule() != Rule::discard);
+            let tag = Symbol::new(&inner.next().unwrap().as_str()[1..]);
+            tagged(MalAtom::Symbol(tag), parse_value(inner.next().unwrap())?)
+        }
+        Rule::string => MalType::Atom(MalAtom::Str(unescape_str(text)?)),
+        Rule::character => tagged(
+            keyword("char"),
+            MalType::Atom(MalAtom::Str(parse_character(text)?.to_string())),
+        ),
+        Rule::float => tagged(
+            keyword("float"),
+            MalType::Atom(MalAtom::Str(text.to_string())),
+        ),
+        Rule::integer => MalType::Atom(MalAtom::Integer(parse_integer(text)?)),
+        Rule::nil => MalType::Atom(MalAtom::Nil),
+        Rule::boolean => MalType::Atom(MalAtom::Boolean(text == "true")),
+        Rule::keyword => MalType::Atom(keyword(&text[1..])),
+        Rule::symbol => MalType::Atom(MalAtom::Symbol(Symbol::new(text))),
+        _ => unreachable!("edn value? {:?}", pair.as_rule()),
+    })
+}
+
+/// Reads every EDN value in `input`.
+pub fn read_edn(input: &str) -> Result<Vec<MalType>, MalError> {
+    let pairs = EdnParser::parse(Rule::edn, input)
+        .map_err(|err| MalError::Parsing(format!("\n{}", err)))?;
+    pairs
+        .filter(|p| !matches!(p.as_rule(), Rule::discard | Rule::EOI))
+        .map(parse_value)
+        .collect()
+}
+
+fn escape_str(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn write_atom(atom: &MalAtom) -> String {
+    match atom {
+        MalAtom::Str(s) => escape_str(s),
+        other => other.to_string(),
+    }
+}
+
+fn write_seq<'a>(items: impl IntoIterator<Item = &'a MalType>) -> Result<String, MalError> {
+    Ok(items
+        .into_iter()
+        .map(write_edn)
+        .collect::<Result<Vec<String>, MalError>>()?
+        .join(" "))
+}
+
+fn write_tagged(tag: &MalAtom, value: &MalType) -> Result<String, MalError> {
+    let invalid = || {
+        MalError::Normal(format!(
+            "`{:?}` is not a valid EDN value for the tag {}",
+            value, tag
+        ))
+    };
+    let name = match tag {
+        MalAtom::Keyword(k) => k.name.as_str(),
+        MalAtom::Symbol(s) => return Ok(format!("#{} {}", s, write_edn(value)?)),
+        _ => return Err(invalid()),
+    };
+    match (name, value, value.items()) {
+        ("set", _, Some(items)) => Ok(format!("#{{{}}}", write_seq(items)?)),
+        ("map", _, Some(entries)) => {
+            let mut items = Vec::new();
+            for entry in entries {
+                match entry.items().map(Iterator::collect::<Vec<_>>) {
+                    Some(kv) if kv.len() == 2 => items.extend(kv),
+                    _ => return Err(invalid()),
+                }
+            }
+            Ok(format!("{{{}}}", write_seq(items)?))
+        }
+        ("char", MalType::Atom(MalAtom::Str(s)), _) if s.chars().count() == 1 => {
+            Ok(match s.as_str() {
+                "\n" => String::from("\\newline"),
+                "\r" => String::from("\\return"),
+                " " => String::from("\\space"),
+                "\t" => String::from("\\tab"),
+                c => format!("\\{}", c),
+            })
+        }
+        ("float", MalType::Atom(MalAtom::Str(s)), _) => match read_edn(s)?.as_slice() {
+            [MalType::Map(map)]
+                if as_tagged(map).map(|(tag, _)| tag) == Some(&keyword("float")) =>
+            {
+                Ok(s.clone())
+            }
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Writes `value` as EDN text. Functions, protocols and atoms have no EDN
+/// representation.
+pub fn write_edn(value: &MalType) -> Result<String, MalError> {
+    match value {
+        MalType::Atom(atom) => Ok(write_atom(atom)),
+        MalType::List(items) => Ok(format!("({})", write_seq(items)?)),
+        MalType::LazySeq(seq) => Ok(format!("({})", write_seq(&seq.to_vec_limited()?)?)),
+        MalType::Vector(items) => Ok(format!("[{}]", write_seq(items)?)),
+        MalType::Map(map) => match as_tagged(map) {
+            Some((tag, value)) => write_tagged(tag, value),
+            None => Ok(format!(
+                "{{{}}}",
+                map.iter()
+                    .map(|(k, v)| Ok(format!("{} {}", write_atom(k), write_edn(v)?)))
+                    .collect::<Result<Vec<String>, MalError>>()?
+                    .join(" ")
+            )),
+        },
+        MalType::Record(r) => write_edn(&MalType::Map(r.entries.clone().into())),
+        other => Err(MalError::Normal(format!(
+            "`{:?}` can't be written as EDN",
+            other
+        ))),
+    }
+}
+
+/// `(read-edn s)` reads the EDN value in `s`, or nil if there is none. More
+/// than one value is an error rather than the rest being ignored.
+fn read_edn_native(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let values = match &args[0] {
+        MalType::Atom(MalAtom::Str(s)) => read_edn(s)?,
+        other => return Err(MalError::Normal(format!("`{:?}` is not a string", other))),
+    };
+    if values.len() > 1 {
+        return Err(MalError::Parsing(format!(
+            ": Expected one EDN value, found {}",
+            values.len()
+        )));
+    }
+    Ok(values
+        .into_iter()
+        .next()
+        .unwrap_or(MalType::Atom(MalAtom::Nil)))
+}
+
+pub fn read_edn_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("read-edn"), read_edn_native)
+        .with_arglists("([s])")
+        .with_doc("Returns the EDN value in s, or nil if there is none.")
+        .with_arity(Arity::Fixed(1))
+}
+
+fn write_edn_native(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Str(write_edn(&args[0])?)))
+}
+
+pub fn write_edn_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("write-edn"), write_edn_native)
+        .with_arglists("([x])")
+        .with_doc("Returns x as EDN text.")
+        .with_arity(Arity::Fixed(1))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![read_edn_fn(), write_edn_fn()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        edn::{read_edn, write_edn},
+        printer::pr_str,
+        test_util::rep,
+        types::MalType,
+    };
+
+    /// Writes each value read from `input` back out.
+    fn round_trip(input: &str) -> String {
+        read_edn(input)
+            .unwrap()
+            .iter()
+            .map(|value| write_edn(value).unwrap())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn read() {
+        assert_eq!(
+            "(nil true -1 \"a\\nb\" :ns/kw sym/bol [1 (2)] {:a 1})",
+            pr_str(&MalType::List(
+                read_edn("nil true -1N \"a\\nb\" :ns/kw sym/bol [1, (2)] {:a 1}")
+                    .unwrap()
+                    .into()
+            ))
+        );
+        assert_eq!(
+            "[1 3]",
+            pr_str(&MalType::Vector(
+                read_edn("1 #_2 #_ #_ [4] 5 3 #_6").unwrap().into()
+            ))
+        );
+        assert_eq!(
+            "true",
+            rep("(= (read-edn \"#inst \\\"1985-04-12T23:20:50.52Z\\\"\")
+                    {:edn/tag 'inst :edn/value \"1985-04-12T23:20:50.52Z\"})")
+            .unwrap()
+        );
+        assert_eq!("nil", rep("(read-edn \" ; nothing\")").unwrap());
+        // Code isn't special: nothing is quoted, dereferenced or evaluated.
+        assert_eq!("(+ 1 2)", pr_str(&read_edn("(+ 1 2)").unwrap()[0]));
+        assert!(read_edn("'a").is_err());
+        assert!(read_edn("@a").is_err());
+        assert!(read_edn("#{1 1}").is_err());
+        assert!(read_edn("{:a 1 :a 2}").is_err());
+        assert!(read_edn("{:a}").is_err());
+        assert!(read_edn("::a").is_err());
+        assert!(rep("(read-edn \":a/b/c\")").is_err());
+        assert!(rep("(read-edn \"[:a/b/c foo/bar/baz]\")").is_err());
+        assert!(read_edn("foo/bar/baz").is_err());
+        assert!(read_edn("#a/b/c 1").is_err());
+        assert_eq!(
+            "[/ :a/b a/b]",
+            pr_str(&read_edn("[/ :a/b a/b]").unwrap()[0])
+        );
+        assert!(rep("(read-edn \"1 2\")").is_err());
+        let many_keys: String = (0..20000).map(|i| format!(":k{} {} ", i, i)).collect();
+        assert_eq!(
+            20000,
+            match &read_edn(&format!("{{{}}}", many_keys)).unwrap()[0] {
+                MalType::Map(m) => m.len(),
+                other => panic!("expected a map, got {}", other),
+            }
+        );
+        assert!(read_edn(&format!("{{{} :k0 0}}", many_keys)).is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(
+            "#{1 [2]} \\a \\newline \\é 1.5 -2.0e10 3M #myapp/Person {:name \"x\"}",
+            round_trip("#{1 [2]} \\a \\newline \\u00e9 1.5 -2.0e10 3M #myapp/Person {:name \"x\"}")
+        );
+        assert_eq!("{[1] #{}}", round_trip("{[1] #{}}"));
+        assert_eq!("\"\\\"\\\\\\n\"", round_trip("\"\\\"\\\\\\n\""));
+        assert_eq!(
+            "[1 #{:a}]",
+            rep("(write-edn (read-edn \"[1 #{:a}]\"))")
+                .unwrap()
+                .trim_matches('"')
+        );
+        assert!(rep("(write-edn +)").is_err());
+    }
+}
@@ -1,38 +1,100 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    iter,
+    rc::Rc,
+};
 
 use crate::{
-    built_ins::{divide_fn, minus_fn, multiply_fn, plus_fn},
+    atom, built_ins, csv, destructure, dynamic, edn, json, lazy, meta,
+    multi::{self, Hierarchy},
+    pattern, record, seq, stdlib, string,
     symbol::Symbol,
-    types::{MalError, MalType},
+    transient,
+    types::{MalError, MalType, Map},
 };
 
 type SymbolTable = HashMap<String, MalType>;
 
+/// The namespace of the built-ins. `mal.core/name` always names the
+/// built-in `name`, however `name` has been shadowed or redefined.
+pub const CORE_NS: &str = "mal.core";
+
+/// The namespace of global definitions. `user/name` names the global `name`
+/// even where a local shadows it.
+pub const USER_NS: &str = "user";
+
+/// Layers are shared between clones of an `Env`, so a lambda that captures
+/// its defining environment still sees later `def!`s in that environment.
 #[derive(Debug, Clone)]
 pub struct Env {
-    data: SymbolTable,
-    outer: Vec<SymbolTable>,
+    data: Rc<RefCell<SymbolTable>>,
+    outer: Vec<Rc<RefCell<SymbolTable>>>,
+    loaded_files: Rc<RefCell<HashSet<String>>>,
+    core: Rc<SymbolTable>,
+    dynamic_vars: Rc<RefCell<HashSet<String>>>,
+    var_meta: Rc<RefCell<HashMap<String, Map>>>,
+    hierarchy: Hierarchy,
+    has_core: bool,
+    nested_let: bool,
 }
 
 impl Env {
     pub fn new() -> Self {
         Env {
-            data: SymbolTable::new(),
+            data: Rc::new(RefCell::new(SymbolTable::new())),
             outer: Vec::new(),
+            loaded_files: Rc::new(RefCell::new(HashSet::new())),
+            core: Rc::new(SymbolTable::new()),
+            dynamic_vars: Rc::new(RefCell::new(HashSet::new())),
+            var_meta: Rc::new(RefCell::new(HashMap::new())),
+            hierarchy: Hierarchy::new(),
+            has_core: false,
+            nested_let: false,
         }
     }
 
     pub fn find(&self, key: &Symbol) -> Option<MalType> {
-        if let Some(val) = self.data.get(&key.get_name()) {
+        if let Some(val) = self.data.borrow().get(&key.name) {
             return Some(val.clone());
         } else if !self.outer.is_empty() {
             for symbol_table in self.outer.iter().rev() {
-                if let Some(val) = symbol_table.get(&key.get_name()) {
+                if let Some(val) = symbol_table.borrow().get(&key.name) {
                     return Some(val.clone());
                 }
             }
         }
-        None
+        self.find_qualified(key)
+    }
+
+    /// Looks up a namespace-qualified symbol like `mal.core/cons`.
+    fn find_qualified(&self, key: &Symbol) -> Option<MalType> {
+        match key.name.split_once('/')? {
+            (CORE_NS, name) => self.core.get(name).cloned(),
+            (USER_NS, name) => self.globals().borrow().get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    /// The outermost layer, holding global definitions.
+    fn globals(&self) -> &Rc<RefCell<SymbolTable>> {
+        self.outer.first().unwrap_or(&self.data)
+    }
+
+    /// The symbol a syntax-quoted template uses for `key`: qualified with
+    /// `mal.core` if it names a built-in, with `user` if it names a global
+    /// definition, and left as it is otherwise, as locals and special forms
+    /// are.
+    pub fn qualify(&self, key: &Symbol) -> Symbol {
+        if key.name.contains('/') {
+            key.clone()
+        } else if self.core.contains_key(&key.name) {
+            Symbol::new(&format!("{}/{}", CORE_NS, key.name))
+        } else if self.globals().borrow().contains_key(&key.name) {
+            Symbol::new(&format!("{}/{}", USER_NS, key.name))
+        } else {
+            key.clone()
+        }
     }
 
     pub fn get(&self, key: &Symbol) -> Result<MalType, MalError> {
@@ -41,13 +103,74 @@ impl Env {
     }
 
     pub fn set(&mut self, key: Symbol, val: MalType) -> MalType {
-        self.data.insert(key.get_name(), val.clone());
+        self.data.borrow_mut().insert(key.get_name(), val.clone());
         val
     }
 
+    /// Binds each of the binding forms `params` to the arg in the same
+    /// place. A `&` param binds the form after it to a list of the args left
+    /// over, or nil if there are none, as a call to a variadic function does.
+    /// The caller checks there are enough args.
+    pub fn bind(&mut self, params: &[MalType], args: Vec<MalType>) -> Result<(), MalError> {
+        destructure::bind_seq(self, params, MalType::List(args.into()))
+    }
+
+    /// Replaces the value of `key` in the innermost layer defining it,
+    /// returning the old value, or `None` if no layer does.
+    pub fn replace(&mut self, key: &Symbol, val: MalType) -> Option<MalType> {
+        iter::once(&self.data)
+            .chain(self.outer.iter().rev())
+            .find_map(|layer| {
+                let mut layer = layer.borrow_mut();
+                layer
+                    .get_mut(&key.name)
+                    .map(|old| std::mem::replace(old, val.clone()))
+            })
+    }
+
+    /// Replaces the global value of `key`, where `def!` defines vars,
+    /// whatever locals shadow it. Returns the old value, or `None` if `key`
+    /// isn't defined globally.
+    pub fn replace_global(&mut self, key: &Symbol, val: MalType) -> Option<MalType> {
+        self.globals()
+            .borrow_mut()
+            .get_mut(&key.name)
+            .map(|old| std::mem::replace(old, val))
+    }
+
+    /// Marks the var `key` as dynamic, so `binding` can rebind it.
+    pub fn mark_dynamic(&mut self, key: &Symbol) {
+        self.dynamic_vars.borrow_mut().insert(key.get_name());
+    }
+
+    pub fn is_dynamic(&self, key: &Symbol) -> bool {
+        self.dynamic_vars.borrow().contains(&key.name)
+    }
+
+    /// The names of everything defined, in every layer, sorted.
+    pub fn names(&self) -> Vec<Symbol> {
+        let mut names: Vec<String> = iter::once(&self.data)
+            .chain(self.outer.iter())
+            .flat_map(|layer| layer.borrow().keys().cloned().collect::<Vec<String>>())
+            .collect();
+        names.sort();
+        names.dedup();
+        names.iter().map(|name| Symbol::new(name)).collect()
+    }
+
+    /// Records the metadata `def!` gave the var `key`.
+    pub fn set_var_meta(&mut self, key: &Symbol, meta: Map) {
+        self.var_meta.borrow_mut().insert(key.get_name(), meta);
+    }
+
+    pub fn var_meta(&self, key: &Symbol) -> Option<Map> {
+        self.var_meta.borrow().get(&key.name).cloned()
+    }
+
     pub fn push_layer(&mut self) {
-        self.outer.push(self.data.clone());
-        self.data = SymbolTable::new();
+        let next_layer = Rc::new(RefCell::new(SymbolTable::new()));
+        self.outer
+            .push(std::mem::replace(&mut self.data, next_layer));
     }
 
     pub fn pop_layer(&mut self) {
@@ -56,16 +179,64 @@ impl Env {
         }
     }
 
+    /// Records `path` as loaded, returning `false` if it already was.
+    pub fn mark_loaded(&mut self, path: &str) -> bool {
+        self.loaded_files.borrow_mut().insert(path.to_string())
+    }
+
+    /// The hierarchy `derive` adds to, which this environment's multimethods
+    /// dispatch with.
+    pub fn hierarchy(&self) -> &Hierarchy {
+        &self.hierarchy
+    }
+
+    /// Whether this environment was made by `Env::repl`, with the built-ins.
+    pub fn has_core(&self) -> bool {
+        self.has_core
+    }
+
+    /// Also accepts `let*` bindings written as pairs, `(let* ((a 1) (b 2))
+    /// ...)`, as older code for this interpreter did. A binding vector of
+    /// nothing but pairs is then read that way rather than as flat bindings
+    /// destructuring each pair.
+    pub fn with_nested_let(mut self, nested: bool) -> Self {
+        self.nested_let = nested;
+        self
+    }
+
+    pub fn nested_let(&self) -> bool {
+        self.nested_let
+    }
+
     pub fn repl() -> Self {
-        let mut env = Env::new();
-        let plus = plus_fn();
-        env.set(plus.name.clone(), MalType::Function(plus));
-        let minus = minus_fn();
-        env.set(minus.name.clone(), MalType::Function(minus));
-        let multiply = multiply_fn();
-        env.set(multiply.name.clone(), MalType::Function(multiply));
-        let divide = divide_fn();
-        env.set(divide.name.clone(), MalType::Function(divide));
+        let mut env = Env {
+            has_core: true,
+            ..Env::new()
+        };
+        for func in built_ins::all()
+            .into_iter()
+            .chain(stdlib::all())
+            .chain(atom::all())
+            .chain(json::all())
+            .chain(edn::all())
+            .chain(csv::all())
+            .chain(pattern::all())
+            .chain(string::all())
+            .chain(seq::all())
+            .chain(lazy::all())
+            .chain(transient::all())
+            .chain(record::all())
+            .chain(multi::all())
+            .chain(meta::all())
+            .chain(multi::hierarchy_fns(&env.hierarchy))
+        {
+            env.set(func.name.clone(), MalType::Function(func));
+        }
+        env.core = Rc::new(env.data.borrow().clone());
+        for (name, value) in dynamic::host_vars() {
+            env.mark_dynamic(&name);
+            env.set(name, value);
+        }
         env
     }
 }
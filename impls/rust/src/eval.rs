@@ -1,143 +1,1828 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fs, iter, mem, rc::Rc};
 
 use crate::{
+    built_ins, destructure, doc, dynamic,
     env::Env,
-    types::{MalAtom, MalError, MalType},
+    keyword::Keyword,
+    lazy::LazySeq,
+    multi::MultiFn,
+    printer,
+    reader::{read_file_str, Location},
+    record::{self, RecordType},
+    stdlib,
+    symbol::Symbol,
+    types::{
+        Arity, List, MalAtom, MalError, MalLambda, MalType, Map, Method, Protocol, Vector, WithMeta,
+    },
 };
 
 fn resolve_ast(form: MalType, env: &mut Env) -> Result<MalType, MalError> {
     match form {
         MalType::Atom(MalAtom::Symbol(form1)) => env.get(&form1),
-        MalType::List(l) => {
-            let mut new_list = Vec::with_capacity(l.len());
-            for inner_form in l {
-                new_list.push(eval_form(inner_form.clone(), env)?);
+        MalType::List(l) => l
+            .iter()
+            .map(|inner_form| eval_form(inner_form.clone(), env))
+            .collect::<Result<List, _>>()
+            .map(MalType::List),
+        MalType::Vector(v) => v
+            .iter()
+            .map(|inner_form| eval_form(inner_form.clone(), env))
+            .collect::<Result<_, _>>()
+            .map(MalType::Vector),
+        MalType::Map(m) => m
+            .iter()
+            .map(|(key, val)| Ok((key.clone(), eval_form(val.clone(), env)?)))
+            .collect::<Result<_, _>>()
+            .map(MalType::Map),
+        _ => Ok(form.clone()),
+    }
+}
+
+/// Evaluates `form` without cloning it first if it's a symbol, as the
+/// function and args of most calls are.
+fn eval_arg(form: &MalType, env: &mut Env) -> Result<MalType, MalError> {
+    match form {
+        MalType::Atom(MalAtom::Symbol(sym)) => env.get(sym),
+        _ => eval_form(form.clone(), env),
+    }
+}
+
+fn keyword(name: &str) -> MalAtom {
+    MalAtom::Keyword(Keyword::new(name.to_string()))
+}
+
+/// The name of `(def! name value)`, and the form of the metadata given to
+/// it, as in `^:dynamic name` or `^{:doc "..."} name`.
+fn def_name(form: &MalType) -> Option<(Symbol, Option<MalType>)> {
+    match form {
+        MalType::Atom(MalAtom::Symbol(name)) => Some((name.clone(), None)),
+        MalType::List(l) if matches!(l.first(), Some(MalType::Atom(MalAtom::Symbol(s))) if s.name == "with-meta") => {
+            match l.rest().to_vec().as_slice() {
+                [MalType::Atom(MalAtom::Symbol(name)), meta] => {
+                    Some((name.clone(), Some(meta.clone())))
+                }
+                _ => None,
             }
-            Ok(MalType::List(new_list))
         }
-        MalType::Vector(v) => {
-            let mut new_vector = Vec::with_capacity(v.len());
-            for inner_form in v {
-                new_vector.push(eval_form(inner_form.clone(), env)?);
-            }
-            Ok(MalType::Vector(new_vector))
+        _ => None,
+    }
+}
+
+/// The param vectors of each arity of `lambda`, as `:arglists` lists them.
+fn arglists(lambda: &MalLambda) -> MalType {
+    MalType::List(
+        lambda
+            .methods
+            .iter()
+            .map(|method| MalType::Vector(method.params.clone().into()))
+            .collect(),
+    )
+}
+
+/// `(def! name value)`, or `(def! name "docstring" value)`, binds `name` to
+/// the value of `value`. The var is given the metadata of `name`, with
+/// `:doc`, `:arglists` if the value is a `fn*`, and the `:file` and `:line`
+/// the `def!` was read from, at `location`. `^:dynamic` makes it dynamic.
+fn execute_def(
+    args: Vec<MalType>,
+    location: Option<&Location>,
+    env: &mut Env,
+) -> Result<MalType, MalError> {
+    let (name, doc, arg) = match args.as_slice() {
+        [name, arg] => (name, None, arg),
+        [name, MalType::Atom(MalAtom::Str(doc)), arg] => (name, Some(doc), arg),
+        _ => {
+            return Err(MalError::Normal(format!(
+                "Wrong number of args for def!. Need 2, or 3 with a docstring, received {}",
+                args.len(),
+            )))
         }
-        MalType::Map(m) => {
-            let mut new_map = HashMap::with_capacity(m.len());
-            for (key, val) in m {
-                new_map.insert(key.clone(), eval_form(val.clone(), env)?);
+    };
+    let (new_symbol, meta) = def_name(name).ok_or_else(|| {
+        MalError::Normal(format!(
+            "First arg to def! must be a symbol. Given {:?}",
+            name,
+        ))
+    })?;
+    let mut meta = match meta.map(|meta| eval_form(meta, env)).transpose()? {
+        Some(MalType::Map(m)) => m.value,
+        None => Map::new(),
+        Some(other) => {
+            return Err(MalError::Normal(format!(
+                "Metadata of {} must be a map. Given {}",
+                new_symbol, other,
+            )))
+        }
+    };
+    let evaled_arg = match eval_form(arg.clone(), env)? {
+        MalType::Lambda(lambda) if lambda.name.is_none() => MalType::Lambda(MalLambda {
+            name: Some(new_symbol.clone()),
+            ..lambda
+        }),
+        evaled_arg => evaled_arg,
+    };
+    if meta
+        .get(&keyword("dynamic"))
+        .is_some_and(MalType::is_truthy)
+    {
+        env.mark_dynamic(&new_symbol);
+    }
+    if env.is_dynamic(&new_symbol) {
+        dynamic::set_host(&new_symbol, &evaled_arg)?;
+    }
+    if let Some(doc) = doc {
+        meta.insert(keyword("doc"), MalType::Atom(MalAtom::Str(doc.clone())));
+    }
+    if let MalType::Lambda(lambda) = &evaled_arg {
+        meta.entry(keyword("arglists"))
+            .or_insert_with(|| arglists(lambda));
+    }
+    if let Some(location) = location {
+        if let Some(file) = &location.file {
+            meta.insert(
+                keyword("file"),
+                MalType::Atom(MalAtom::Str(file.to_string())),
+            );
+        }
+        meta.insert(
+            keyword("line"),
+            MalType::Atom(MalAtom::Integer(location.line as isize)),
+        );
+    }
+    env.set_var_meta(&new_symbol, meta);
+    Ok(env.set(new_symbol, evaled_arg))
+}
+
+/// `(var-meta name)`, the metadata `def!` gave the var `name`, or nil if it
+/// wasn't defined by `def!`.
+fn execute_var_meta(args: Vec<MalType>, env: &Env) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [MalType::Atom(MalAtom::Symbol(name))] => Ok(env
+            .var_meta(name)
+            .map_or(MalType::Atom(MalAtom::Nil), |meta| {
+                MalType::Map(meta.into())
+            })),
+        _ => Err(MalError::Normal(format!(
+            "var-meta needs the name of a var. Given {:?}",
+            args,
+        ))),
+    }
+}
+
+/// `(def-dynamic name value)` defines `name` as a dynamic var, as
+/// `(def! ^:dynamic name value)` does.
+fn execute_def_dynamic(
+    args: Vec<MalType>,
+    location: Option<&Location>,
+    env: &mut Env,
+) -> Result<MalType, MalError> {
+    if let Some(MalType::Atom(MalAtom::Symbol(name))) = args.first() {
+        env.mark_dynamic(name);
+    }
+    execute_def(args, location, env)
+}
+
+/// `(binding [*x* 1 *y* 2] body...)` gives each dynamic var its value,
+/// evaluated before any is bound, while the body runs. The old values are
+/// restored afterwards, even if the body fails. It's the global var that's
+/// rebound, not a local of the same name.
+fn execute_binding(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    if args.is_empty() {
+        return Err(MalError::Normal(String::from(
+            "Wrong number of args for binding. Need 1 or more, received 0",
+        )));
+    }
+    let mut values = Vec::new();
+    for pair in binding_forms("binding", &args[0])?.chunks(2) {
+        let name = match &pair[0] {
+            MalType::Atom(MalAtom::Symbol(name)) if env.is_dynamic(name) => name.clone(),
+            MalType::Atom(MalAtom::Symbol(name)) => {
+                return Err(MalError::Normal(format!(
+                    "Can't dynamically bind non-dynamic var {}",
+                    name
+                )))
             }
-            Ok(MalType::Map(new_map))
+            other => {
+                return Err(MalError::Normal(format!(
+                    "binding needs symbols to bind. Given {:?}",
+                    other
+                )))
+            }
+        };
+        values.push((name, eval_form(pair[1].clone(), env)?));
+    }
+    let mut saved = Vec::new();
+    let result = values
+        .into_iter()
+        .try_for_each(|(name, value)| {
+            dynamic::set_host(&name, &value)?;
+            let old = env
+                .replace_global(&name, value)
+                .unwrap_or(MalType::Atom(MalAtom::Nil));
+            saved.push((name, old));
+            Ok(())
+        })
+        .and_then(|()| execute_do(args[1..].to_vec(), env, eval_form));
+    for (name, old) in saved.into_iter().rev() {
+        // The old value was accepted when it was set, so restoring it can't fail.
+        dynamic::set_host(&name, &old).ok();
+        env.replace_global(&name, old);
+    }
+    result
+}
+
+/// `(set-print-length! n)` sets `*print-length*` to `n`, as `def!` would,
+/// so the var and what the printer does agree. Inside a `binding` of it,
+/// the new value lasts until the binding ends.
+fn execute_set_print_length(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let arg = match args.as_slice() {
+        [arg] => arg,
+        _ => {
+            return Err(MalError::Normal(format!(
+                "Wrong number of args for set-print-length!. Need 1, received {}",
+                args.len(),
+            )))
         }
-        _ => Ok(form.clone()),
+    };
+    let name = Symbol::new("*print-length*");
+    let value = eval_form(arg.clone(), env)?;
+    dynamic::set_host(&name, &value)?;
+    env.replace_global(&name, value.clone());
+    Ok(value)
+}
+
+/// Whether `form` is a `(name value)` pair of the nested `let*` bindings
+/// `Env::with_nested_let` allows.
+fn is_binding_pair(form: &MalType) -> bool {
+    form.items().is_some_and(|items| items.count() == 2)
+}
+
+/// The forms of a binding vector of `special_form`, checked to be name and
+/// value pairs.
+fn binding_forms(special_form: &str, bindings: &MalType) -> Result<Vec<MalType>, MalError> {
+    let forms: Vec<MalType> = match bindings.items() {
+        Some(forms) => forms.cloned().collect(),
+        None => {
+            return Err(MalError::Normal(format!(
+                "{} bindings must be a list or vector. Given {:?}",
+                special_form, bindings,
+            )))
+        }
+    };
+    if !forms.len().is_multiple_of(2) {
+        return Err(MalError::Normal(format!(
+            "{} needs an even number of binding forms. Given {}",
+            special_form,
+            forms.len(),
+        )));
+    }
+    Ok(forms)
+}
+
+/// `(let* [a 1 b (+ a 1)] body...)`, where each binding sees those before
+/// it. `eval` evaluates the last form of the body.
+fn execute_let_star<T: From<MalType>>(
+    args: Vec<MalType>,
+    env: &mut Env,
+    eval: fn(MalType, &mut Env) -> Result<T, MalError>,
+) -> Result<T, MalError> {
+    if args.len() < 2 {
+        return Err(MalError::Normal(format!(
+            "Wrong number of args for let*. Need 2 or more, received {}",
+            args.len(),
+        )));
+    }
+    let pairs: Vec<MalType> = args[0]
+        .items()
+        .map(|forms| forms.cloned().collect())
+        .unwrap_or_default();
+    let bindings = if env.nested_let() && !pairs.is_empty() && pairs.iter().all(is_binding_pair) {
+        pairs
+            .iter()
+            .flat_map(|pair| pair.items().unwrap().cloned())
+            .collect()
+    } else {
+        binding_forms("let*", &args[0])?
+    };
+    env.push_layer();
+    let result = bind_all(&bindings, env).and_then(|()| execute_do(args[1..].to_vec(), env, eval));
+    env.pop_layer();
+    result
+}
+
+/// Evaluates the value of each name and value pair of `bindings` and binds
+/// it to the name, in order.
+fn bind_all(bindings: &[MalType], env: &mut Env) -> Result<(), MalError> {
+    bindings.chunks(2).try_for_each(|binding| {
+        let value = eval_form(binding[1].clone(), env)?;
+        destructure::bind(env, &binding[0], value)
+    })
+}
+
+/// Evaluates each form in order, the last with `eval`.
+fn execute_do<T: From<MalType>>(
+    args: Vec<MalType>,
+    env: &mut Env,
+    eval: fn(MalType, &mut Env) -> Result<T, MalError>,
+) -> Result<T, MalError> {
+    let mut args = args.into_iter();
+    let last = match args.next_back() {
+        Some(last) => last,
+        None => return Ok(T::from(MalType::Atom(MalAtom::Nil))),
+    };
+    for form in args {
+        eval_form(form, env)?;
+    }
+    eval(last, env)
+}
+
+/// Evaluates the condition, then the chosen branch with `eval`.
+fn execute_if<T: From<MalType>>(
+    args: Vec<MalType>,
+    env: &mut Env,
+    eval: fn(MalType, &mut Env) -> Result<T, MalError>,
+) -> Result<T, MalError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(MalError::Normal(format!(
+            "Wrong number of args for if. Need 2 or 3, received {}",
+            args.len(),
+        )));
+    }
+    let mut args = args.into_iter();
+    let condition = eval_form(args.next().unwrap(), env)?;
+    let then_form = args.next().unwrap();
+    if condition.is_truthy() {
+        eval(then_form, env)
+    } else if let Some(else_form) = args.next() {
+        eval(else_form, env)
+    } else {
+        Ok(T::from(MalType::Atom(MalAtom::Nil)))
+    }
+}
+
+fn execute_quote(args: Vec<MalType>) -> Result<MalType, MalError> {
+    if args.len() != 1 {
+        return Err(MalError::Normal(format!(
+            "Wrong number of args for quote. Need 1, received {}",
+            args.len(),
+        )));
     }
+    Ok(args.into_iter().next().unwrap())
 }
 
-fn execute_def(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
-    if args.len() != 2 {
+/// `(quasiquote form)`, read from `` `form ``, quotes `form` except for
+/// `~x`, which is replaced by the value of `x`, and `~@xs`, whose elements
+/// are spliced into the enclosing list or vector. Symbols are qualified by
+/// the namespace defining them, so a template means the same wherever it's
+/// used, and `x#` becomes a gensym shared by every `x#` in the template.
+fn execute_quasiquote(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    if args.len() != 1 {
         return Err(MalError::Normal(format!(
-            "Wrong number of args for def!. Need 2, received {}",
+            "Wrong number of args for quasiquote. Need 1, received {}",
             args.len(),
         )));
     }
-    match (args.get(0), args.get(1)) {
-        (Some(&MalType::Atom(MalAtom::Symbol(ref new_symbol))), Some(arg)) => {
-            let evaled_arg = eval_form(arg.clone(), env)?;
-            Ok(env.set(new_symbol.clone(), evaled_arg))
+    syntax_quote(&args[0], env, &mut HashMap::new())
+}
+
+/// The argument of `form` if it's a call to `name`, like `(unquote x)`.
+fn unquoted<'a>(form: &'a MalType, name: &str) -> Option<&'a MalType> {
+    match form {
+        MalType::List(l) if l.len() == 2 => match l.first() {
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == name => l.get(1),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn syntax_quote_items<'a>(
+    items: impl Iterator<Item = &'a MalType>,
+    env: &mut Env,
+    gensyms: &mut HashMap<String, Symbol>,
+) -> Result<Vec<MalType>, MalError> {
+    let mut quoted = Vec::new();
+    for item in items {
+        match unquoted(item, "splice-unquote") {
+            Some(xs) => quoted.extend(built_ins::as_seq(&eval_form(xs.clone(), env)?)?),
+            None => quoted.push(syntax_quote(item, env, gensyms)?),
         }
-        (Some(non_sym), None) => Err(MalError::Normal(format!(
-            "First arg to def! must be a symbol. Given {:?}",
-            non_sym,
+    }
+    Ok(quoted)
+}
+
+fn syntax_quote(
+    form: &MalType,
+    env: &mut Env,
+    gensyms: &mut HashMap<String, Symbol>,
+) -> Result<MalType, MalError> {
+    if let Some(x) = unquoted(form, "unquote") {
+        return eval_form(x.clone(), env);
+    }
+    if unquoted(form, "splice-unquote").is_some() {
+        return Err(MalError::Normal(String::from(
+            "splice-unquote must be in a list or vector",
+        )));
+    }
+    match form {
+        MalType::Atom(MalAtom::Symbol(sym)) => match sym.name.strip_suffix('#') {
+            Some(base) if !base.is_empty() => {
+                let gensym = gensyms.entry(sym.name.clone()).or_insert_with(|| {
+                    let id = stdlib::new_gensym(&format!("{}__", base));
+                    Symbol::new(&format!("{}__auto__", id.name))
+                });
+                Ok(MalType::Atom(MalAtom::Symbol(gensym.clone())))
+            }
+            _ => Ok(MalType::Atom(MalAtom::Symbol(env.qualify(sym)))),
+        },
+        MalType::List(l) => Ok(MalType::List(List::from(syntax_quote_items(
+            l.iter(),
+            env,
+            gensyms,
+        )?))),
+        MalType::Vector(v) => Ok(MalType::Vector(
+            syntax_quote_items(v.iter(), env, gensyms)?.into(),
+        )),
+        MalType::Map(m) => m
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), syntax_quote(value, env, gensyms)?)))
+            .collect::<Result<_, MalError>>()
+            .map(MalType::Map),
+        other => Ok(other.clone()),
+    }
+}
+
+fn parse_params(params: &MalType) -> Result<Vec<MalType>, MalError> {
+    let params = match params.items() {
+        Some(params) => params
+            .map(|param| match param {
+                MalType::Atom(MalAtom::Symbol(_)) | MalType::Vector(_) | MalType::Map(_) => {
+                    Ok(param.clone())
+                }
+                non_form => Err(MalError::Normal(format!(
+                    "fn* params must be symbols, vectors or maps. Given {:?}",
+                    non_form,
+                ))),
+            })
+            .collect::<Result<Vec<MalType>, MalError>>()?,
+        None => {
+            return Err(MalError::Normal(format!(
+                "fn* params must be a list or vector. Given {:?}",
+                params,
+            )))
+        }
+    };
+    let amp = params
+        .iter()
+        .position(|param| matches!(param, MalType::Atom(MalAtom::Symbol(s)) if s.name == "&"));
+    match amp {
+        Some(amp) if amp + 2 != params.len() => Err(MalError::Normal(format!(
+            "fn* needs exactly one param after &. Given {:?}",
+            params,
         ))),
-        _ => Err(MalError::Normal(String::from(
-            "Second arg to def! must exist, given None",
+        _ => Ok(params),
+    }
+}
+
+/// Whether `form` is one arity of a multi-arity `fn*`, a list starting with
+/// its params.
+fn is_method(form: &MalType) -> bool {
+    match form {
+        MalType::List(l) => matches!(l.first(), Some(MalType::List(_) | MalType::Vector(_))),
+        _ => false,
+    }
+}
+
+/// Checks `methods` has at most one variadic arity, and no fixed arity
+/// taking as many args as another or more than the variadic one's params.
+fn check_arities(methods: &[Method]) -> Result<(), MalError> {
+    let variadic = methods
+        .iter()
+        .filter_map(|method| match method.arity {
+            Arity::Variadic(fixed) => Some(fixed),
+            _ => None,
+        })
+        .collect::<Vec<usize>>();
+    if variadic.len() > 1 {
+        return Err(MalError::Normal(String::from(
+            "fn* can't have more than one variadic arity",
+        )));
+    }
+    for (i, method) in methods.iter().enumerate() {
+        if let Arity::Fixed(n) = method.arity {
+            let clashes = methods[..i]
+                .iter()
+                .any(|m| matches!(m.arity, Arity::Fixed(k) if k == n))
+                || variadic.iter().any(|&fixed| fixed < n);
+            if clashes {
+                return Err(MalError::Normal(format!(
+                    "fn* has more than one arity taking {} args",
+                    n
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `(fn* name? [params] body...)` or, with an arity for each params vector,
+/// `(fn* name? ([params] body...) ...)`.
+fn execute_fn_star(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let mut args = args.into_iter().peekable();
+    let name = match args.peek() {
+        Some(MalType::Atom(MalAtom::Symbol(name))) => {
+            let name = name.clone();
+            args.next();
+            Some(name)
+        }
+        _ => None,
+    };
+    let methods = match args.next() {
+        None => {
+            return Err(MalError::Normal(String::from(
+                "Wrong number of args for fn*. Need 1 or more, received 0",
+            )))
+        }
+        Some(first) if is_method(&first) => std::iter::once(first)
+            .chain(args)
+            .map(|method| match method {
+                MalType::List(l) if is_method(&method) => Ok(Method::new(
+                    parse_params(l.first().unwrap())?,
+                    l.rest().to_vec(),
+                )),
+                other => Err(MalError::Normal(format!(
+                    "Each arity of fn* must be a list of params and a body. Given {:?}",
+                    other,
+                ))),
+            })
+            .collect::<Result<Vec<Method>, MalError>>()?,
+        Some(params) => vec![Method::new(parse_params(&params)?, args.collect())],
+    };
+    check_arities(&methods)?;
+    Ok(MalType::Lambda(MalLambda::new(name, methods, env.clone())))
+}
+
+/// The value of a form in tail position of a `loop` body, or the args of
+/// the `recur` it ended in.
+enum Tail {
+    Value(MalType),
+    Recur(Vec<MalType>),
+}
+
+impl From<MalType> for Tail {
+    fn from(value: MalType) -> Self {
+        Tail::Value(value)
+    }
+}
+
+/// Evaluates a form in tail position of a `loop` body, following it through
+/// the special forms that pass their tail position on to find a `recur`.
+fn eval_tail(form: MalType, env: &mut Env) -> Result<Tail, MalError> {
+    let l = match &form {
+        MalType::List(l) => l,
+        _ => return eval_form(form, env).map(Tail::Value),
+    };
+    let args = || l.rest().to_vec();
+    match l.first() {
+        Some(MalType::Atom(MalAtom::Symbol(sym))) => match sym.name.as_str() {
+            "recur" => args()
+                .into_iter()
+                .map(|arg| eval_form(arg, env))
+                .collect::<Result<Vec<MalType>, MalError>>()
+                .map(Tail::Recur),
+            "do" => execute_do(args(), env, eval_tail),
+            "if" => execute_if(args(), env, eval_tail),
+            "let*" => execute_let_star(args(), env, eval_tail),
+            _ => match expand(&form, env)? {
+                Some(expansion) => eval_tail(expansion, env),
+                None => eval_form(form, env).map(Tail::Value),
+            },
+        },
+        _ => eval_form(form, env).map(Tail::Value),
+    }
+}
+
+fn recur_error() -> MalError {
+    MalError::Normal(String::from(
+        "recur can only be used in tail position of a loop",
+    ))
+}
+
+/// Checks every `recur` in `form` is in tail position of the innermost
+/// `loop` around it, with one arg for each of its bindings, which is
+/// `arity` if there is such a loop. `tail` is whether `form` itself is in
+/// tail position. Macro calls, built-in or defined in `env`, are checked as
+/// their expansions, and `form` is returned with each of them expanded, so
+/// evaluating it doesn't run the macros again.
+fn check_recur(
+    form: &MalType,
+    env: &Env,
+    arity: Option<usize>,
+    tail: bool,
+) -> Result<MalType, MalError> {
+    let l = match form {
+        MalType::List(l) => l,
+        MalType::Vector(v) => {
+            return v
+                .iter()
+                .map(|x| check_recur(x, env, arity, false))
+                .collect::<Result<WithMeta<Vector>, MalError>>()
+                .map(|checked| MalType::Vector(checked.with_meta(v.meta.clone())))
+        }
+        MalType::Map(m) => {
+            return m
+                .iter()
+                .map(|(key, x)| Ok((key.clone(), check_recur(x, env, arity, false)?)))
+                .collect::<Result<WithMeta<Map>, MalError>>()
+                .map(|checked| MalType::Map(checked.with_meta(m.meta.clone())))
+        }
+        _ => return Ok(form.clone()),
+    };
+    let args = l.rest().to_vec();
+    // Checks the forms of a body, the last in the tail position of `form`.
+    let check_body = |body: &[MalType], arity: Option<usize>, tail: bool| {
+        body.iter()
+            .enumerate()
+            .map(|(i, x)| check_recur(x, env, arity, tail && i + 1 == body.len()))
+            .collect::<Result<Vec<MalType>, MalError>>()
+    };
+    // `l` with its elements replaced by `items`.
+    let rebuilt = |items: Vec<MalType>| {
+        let items = List::from(items);
+        let items = match l.location() {
+            Some(location) => items.with_location(location.clone()),
+            None => items,
+        };
+        MalType::List(items.with_meta(l.meta().clone()))
+    };
+    let with_args =
+        |checked: Vec<MalType>| rebuilt(l.first().cloned().into_iter().chain(checked).collect());
+    let name = match l.first() {
+        Some(MalType::Atom(MalAtom::Symbol(sym))) => sym.name.as_str(),
+        _ => "",
+    };
+    match name {
+        "recur" => match arity {
+            Some(arity) if tail && arity == args.len() => {
+                check_body(&args, None, false).map(with_args)
+            }
+            Some(arity) if tail => Err(MalError::Normal(format!(
+                "recur needs {} args to match its loop, received {}",
+                arity,
+                args.len(),
+            ))),
+            _ => Err(recur_error()),
+        },
+        "quote" | "quasiquote" => Ok(form.clone()),
+        "do" => check_body(&args, arity, tail).map(with_args),
+        "if" => {
+            let mut checked = check_body(&args[..args.len().min(1)], arity, false)?;
+            for branch in args.iter().skip(1) {
+                checked.push(check_recur(branch, env, arity, tail)?);
+            }
+            Ok(with_args(checked))
+        }
+        "let*" | "loop" if !args.is_empty() => {
+            let bindings = check_recur(&args[0], env, arity, false)?;
+            let body_arity = if name == "loop" {
+                binding_forms("loop", &args[0])
+                    .ok()
+                    .map(|forms| forms.len() / 2)
+            } else {
+                arity
+            };
+            let body = check_body(&args[1..], body_arity, name == "loop" || tail)?;
+            Ok(with_args(iter::once(bindings).chain(body).collect()))
+        }
+        "fn*" | "fn" | "lazy-seq" => check_body(&args, None, false).map(with_args),
+        _ => match expand(form, env)? {
+            Some(expansion) => check_recur(&expansion, env, arity, tail),
+            None => check_body(&l.to_vec(), arity, false).map(rebuilt),
+        },
+    }
+}
+
+/// `(loop [a 1 b 2] body...)` binds as `let*` does, then runs the body.
+/// A `recur` in tail position of the body runs it again with its args bound
+/// in place of the bindings' values, without growing the stack.
+fn execute_loop(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    if args.is_empty() {
+        return Err(MalError::Normal(String::from(
+            "Wrong number of args for loop. Need 1 or more, received 0",
+        )));
+    }
+    let bindings = binding_forms("loop", &check_recur(&args[0], env, None, false)?)?;
+    let names: Vec<MalType> = bindings.iter().step_by(2).cloned().collect();
+    let body = args[1..]
+        .iter()
+        .enumerate()
+        .map(|(i, form)| check_recur(form, env, Some(names.len()), i + 2 == args.len()))
+        .collect::<Result<Vec<MalType>, MalError>>()?;
+    env.push_layer();
+    let mut result =
+        bind_all(&bindings, env).and_then(|()| execute_do(body.clone(), env, eval_tail));
+    env.pop_layer();
+    while let Ok(Tail::Recur(values)) = result {
+        env.push_layer();
+        result = names
+            .iter()
+            .zip(values)
+            .try_for_each(|(name, value)| destructure::bind(env, name, value))
+            .and_then(|()| execute_do(body.clone(), env, eval_tail));
+        env.pop_layer();
+    }
+    result.map(|tail| match tail {
+        Tail::Value(value) => value,
+        Tail::Recur(_) => unreachable!("recur is handled by the loop"),
+    })
+}
+
+/// Rewrites `(-> x (a a1) b)` as `(b (a x a1))`, or with `thread_last` set,
+/// `(->> x (a a1) b)` as `(b (a a1 x))`.
+fn thread_forms(args: Vec<MalType>, thread_last: bool) -> Result<MalType, MalError> {
+    let mut args = args.into_iter();
+    let init = args.next().ok_or_else(|| {
+        MalError::Normal(format!(
+            "Wrong number of args for {}. Need 1 or more, received 0",
+            if thread_last { "->>" } else { "->" },
+        ))
+    })?;
+    Ok(args.fold(init, |acc, form| match form {
+        MalType::List(l) if !l.is_empty() => {
+            if thread_last {
+                let mut l = l.to_vec();
+                l.push(acc);
+                MalType::List(l.into())
+            } else {
+                MalType::List(l.rest().cons(acc).cons(l.first().unwrap().clone()))
+            }
+        }
+        non_list => MalType::List(vec![non_list, acc].into()),
+    }))
+}
+
+/// Rewrites `(cond c1 e1 c2 e2)` as `(if c1 e1 (cond c2 e2))`, so `(cond)`
+/// is `nil`.
+fn cond_form(args: Vec<MalType>) -> Result<MalType, MalError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(MalError::Normal(format!(
+            "cond needs an even number of forms. Given {}",
+            args.len()
+        )));
+    }
+    let mut args = args.into_iter();
+    Ok(match (args.next(), args.next()) {
+        (Some(test), Some(then_form)) => {
+            let rest = std::iter::once(MalType::Atom(MalAtom::Symbol(Symbol::new("cond"))))
+                .chain(args)
+                .collect();
+            MalType::List(
+                vec![
+                    MalType::Atom(MalAtom::Symbol(Symbol::new("if"))),
+                    test,
+                    then_form,
+                    MalType::List(rest),
+                ]
+                .into(),
+            )
+        }
+        _ => MalType::Atom(MalAtom::Nil),
+    })
+}
+
+/// Rewrites `(when test body...)` as `(if test (do body...))`.
+fn when_form(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut args = args.into_iter();
+    let test = args.next().ok_or_else(|| {
+        MalError::Normal(String::from(
+            "Wrong number of args for when. Need 1 or more, received 0",
+        ))
+    })?;
+    Ok(MalType::List(
+        vec![
+            MalType::Atom(MalAtom::Symbol(Symbol::new("if"))),
+            test,
+            MalType::List(
+                std::iter::once(MalType::Atom(MalAtom::Symbol(Symbol::new("do"))))
+                    .chain(args)
+                    .collect(),
+            ),
+        ]
+        .into(),
+    ))
+}
+
+/// Rewrites `(defn name "docstring" [params] body...)` as
+/// `(def! name "docstring" (fn* name [params] body...))`. The docstring is
+/// optional, and as with `fn*`, each arity may be given as its own list.
+fn defn_form(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let symbol = |name: &str| MalType::Atom(MalAtom::Symbol(Symbol::new(name)));
+    let mut args = args.into_iter().peekable();
+    let name = match args.next() {
+        Some(name @ MalType::Atom(MalAtom::Symbol(_))) => name,
+        other => {
+            return Err(MalError::Normal(format!(
+                "defn needs a name. Given {:?}",
+                other
+            )))
+        }
+    };
+    let doc = args.next_if(|arg| matches!(arg, MalType::Atom(MalAtom::Str(_))));
+    let lambda = iter::once(symbol("fn*"))
+        .chain(iter::once(name.clone()))
+        .chain(args)
+        .collect();
+    Ok(MalType::List(
+        iter::once(symbol("def!"))
+            .chain(iter::once(name))
+            .chain(doc)
+            .chain(iter::once(MalType::List(lambda)))
+            .collect(),
+    ))
+}
+
+/// Rewrites `(bool-and a b)` as `(if a (bool-and b) false)`, or with `or`
+/// set, `(bool-or a b)` as `(if a true (bool-or b))`. Either returns a
+/// boolean, evaluating only as many of its forms as it needs to.
+fn bool_form(args: Vec<MalType>, or: bool) -> MalType {
+    let name = if or { "bool-or" } else { "bool-and" };
+    let mut args = args.into_iter();
+    match args.next() {
+        Some(test) => {
+            let rest = std::iter::once(MalType::Atom(MalAtom::Symbol(Symbol::new(name))))
+                .chain(args)
+                .collect();
+            let (then_form, else_form) = if or {
+                (MalType::Atom(MalAtom::Boolean(true)), MalType::List(rest))
+            } else {
+                (MalType::List(rest), MalType::Atom(MalAtom::Boolean(false)))
+            };
+            MalType::List(
+                vec![
+                    MalType::Atom(MalAtom::Symbol(Symbol::new("if"))),
+                    test,
+                    then_form,
+                    else_form,
+                ]
+                .into(),
+            )
+        }
+        None => MalType::Atom(MalAtom::Boolean(!or)),
+    }
+}
+
+/// `(defmacro! name (fn* [params] body...))` defines `name` as a macro: a
+/// call to it is replaced by what the function returns given the call's
+/// args unevaluated, which is then evaluated in its place.
+fn execute_defmacro(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let name = match args.as_slice() {
+        [MalType::Atom(MalAtom::Symbol(name)), _] => name.clone(),
+        _ => {
+            return Err(MalError::Normal(format!(
+                "defmacro! needs a name and a function. Given {:?}",
+                args,
+            )))
+        }
+    };
+    match eval_form(args[1].clone(), env)? {
+        MalType::Lambda(lambda) => Ok(env.set(
+            name.clone(),
+            MalType::Lambda(MalLambda {
+                name: Some(name),
+                is_macro: true,
+                ..lambda
+            }),
+        )),
+        other => Err(MalError::Normal(format!(
+            "defmacro! needs a fn* to expand {}. Given {}",
+            name, other,
         ))),
     }
 }
 
-fn execute_let_star(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
-    if args.len() < 2 {
+/// The expansion of the call `l` if it's to one of the macros `eval`
+/// rewrites itself, like `->` and `cond`.
+fn expand_builtin(l: &List) -> Result<Option<MalType>, MalError> {
+    let args = || l.rest().to_vec();
+    let name = match l.first() {
+        Some(MalType::Atom(MalAtom::Symbol(sym))) => sym.name.as_str(),
+        _ => return Ok(None),
+    };
+    Ok(Some(match name {
+        "->" => thread_forms(args(), false)?,
+        "->>" => thread_forms(args(), true)?,
+        "cond" => cond_form(args())?,
+        "when" => when_form(args())?,
+        "defn" => defn_form(args())?,
+        "bool-and" => bool_form(args(), false),
+        "bool-or" => bool_form(args(), true),
+        _ => return Ok(None),
+    }))
+}
+
+/// An expansion of the call `l` that wasn't read anywhere is read where
+/// the call was. A lazy seq, as a macro built with `concat` returns, is
+/// realised as the list it stands for.
+fn located(expansion: MalType, l: &List) -> Result<MalType, MalError> {
+    let expansion = match expansion {
+        MalType::LazySeq(seq) => MalType::List(seq.to_vec()?.into()),
+        expansion => expansion,
+    };
+    Ok(match (expansion, l.location()) {
+        (MalType::List(expanded), Some(location)) if expanded.location().is_none() => {
+            MalType::List(expanded.with_location(location.clone()))
+        }
+        (expansion, _) => expansion,
+    })
+}
+
+/// The expansion of `form` if it's a call to a macro, either one `eval`
+/// rewrites itself or one defined by `defmacro!`.
+pub fn macroexpand_1(form: &MalType, env: &Env) -> Result<Option<MalType>, MalError> {
+    let l = match form {
+        MalType::List(l) => l,
+        _ => return Ok(None),
+    };
+    let expansion = match (expand_builtin(l)?, l.first()) {
+        (Some(expansion), _) => expansion,
+        (None, Some(MalType::Atom(MalAtom::Symbol(sym)))) => match env.get(sym) {
+            Ok(MalType::Lambda(lambda)) if lambda.is_macro => {
+                apply_lambda(&lambda, l.rest().to_vec())?
+            }
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    located(expansion, l).map(Some)
+}
+
+/// Writes the expansion of the call `l` to `*out*` while `*trace-macros*`
+/// is set.
+fn trace_expansion(l: &List, expansion: &MalType) -> Result<(), MalError> {
+    if !dynamic::tracing_macros() {
+        return Ok(());
+    }
+    dynamic::write_out(&format!(
+        "{}{} => {}\n",
+        printer::pr_str(&MalType::List(l.clone())),
+        l.location()
+            .map(|location| format!(" at {}", location))
+            .unwrap_or_default(),
+        printer::pr_str(expansion),
+    ))
+}
+
+/// `macroexpand_1` for `eval`, tracing the expansion.
+fn expand(form: &MalType, env: &Env) -> Result<Option<MalType>, MalError> {
+    let expansion = macroexpand_1(form, env)?;
+    if let (MalType::List(l), Some(expanded)) = (form, &expansion) {
+        trace_expansion(l, expanded)?;
+    }
+    Ok(expansion)
+}
+
+/// A call as `eval` sees it once its first element is evaluated.
+enum Call {
+    /// The traced expansion of a macro call.
+    Expansion(MalType),
+    /// The function to call with the evaluated args.
+    Function(MalType),
+}
+
+/// Expands the call `l` if it's to a macro, without looking up the function
+/// it calls twice as `macroexpand_1` and then `eval` would.
+fn eval_call(l: &List, env: &mut Env) -> Result<Call, MalError> {
+    let expansion = match expand_builtin(l)? {
+        Some(expansion) => expansion,
+        None => match eval_arg(l.first().unwrap(), env)? {
+            MalType::Lambda(lambda) if lambda.is_macro => apply_lambda(&lambda, l.rest().to_vec())?,
+            func => return Ok(Call::Function(func)),
+        },
+    };
+    let expansion = located(expansion, l)?;
+    trace_expansion(l, &expansion)?;
+    Ok(Call::Expansion(expansion))
+}
+
+/// Expands `form` until it's no longer a macro call, then every macro call
+/// in its elements, except those of a quoted form.
+pub fn macroexpand_all(form: &MalType, env: &Env) -> Result<MalType, MalError> {
+    let mut form = form.clone();
+    while let Some(expansion) = macroexpand_1(&form, env)? {
+        form = expansion;
+    }
+    match &form {
+        MalType::List(l)
+            if matches!(l.first(), Some(MalType::Atom(MalAtom::Symbol(sym)))
+                if sym.name == "quote" || sym.name == "quasiquote") =>
+        {
+            Ok(form)
+        }
+        MalType::List(l) => {
+            let expanded = l
+                .iter()
+                .map(|x| macroexpand_all(x, env))
+                .collect::<Result<List, MalError>>()?;
+            Ok(MalType::List(match l.location() {
+                Some(location) => expanded.with_location(location.clone()),
+                None => expanded,
+            }))
+        }
+        MalType::Vector(v) => v
+            .iter()
+            .map(|x| macroexpand_all(x, env))
+            .collect::<Result<_, MalError>>()
+            .map(MalType::Vector),
+        MalType::Map(m) => m
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), macroexpand_all(value, env)?)))
+            .collect::<Result<_, MalError>>()
+            .map(MalType::Map),
+        _ => Ok(form),
+    }
+}
+
+/// `(macroexpand-1 form)` and `(macroexpand-all form)`, which evaluate
+/// `form` and expand the form it evaluates to, as `'(when x y)`.
+fn execute_macroexpand(name: &str, args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    if args.len() != 1 {
         return Err(MalError::Normal(format!(
-            "Wrong number of args for let*. Need 2 or more, received {}",
+            "Wrong number of args for {}. Need 1, received {}",
+            name,
             args.len(),
         )));
     }
-    env.push_layer();
-    match args.get(0) {
-        None => unreachable!("First let* arg doesn't exist???"),
-        Some(MalType::List(ref l)) | Some(MalType::Vector(ref l)) => {
-            for pair in l {
-                let p = if let MalType::List(p) | MalType::Vector(p) = pair {
-                    p
-                } else {
-                    return Err(MalError::Normal(String::from("let* needs tuples")));
-                };
-                if p.len() == 2 {
-                    match &p[0] {
-                        MalType::Atom(MalAtom::Symbol(s)) => {
-                            let evaled_rhs = eval_form(p[1].clone(), env)?;
-                            env.set(s.clone(), evaled_rhs);
-                        }
-                        non_sym => {
-                            return Err(MalError::Normal(format!(
-                                "let* binding needs a symbol for lhs: {:?}",
-                                non_sym
-                            )));
-                        }
-                    }
-                } else {
-                    return Err(MalError::Normal(String::from("let* binding needs 2 parts")));
+    let form = eval_form(args[0].clone(), env)?;
+    if name == "macroexpand-all" {
+        macroexpand_all(&form, env)
+    } else {
+        Ok(macroexpand_1(&form, env)?.unwrap_or(form))
+    }
+}
+
+/// `(find-doc pattern)` or `(apropos pattern)`, which take the environment
+/// to search as well as their evaluated arg.
+fn execute_doc_search(name: &str, args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    if args.len() != 1 {
+        return Err(MalError::Normal(format!(
+            "Wrong number of args for {}. Need 1, received {}",
+            name,
+            args.len(),
+        )));
+    }
+    let pattern = eval_form(args[0].clone(), env)?;
+    if name == "find-doc" {
+        doc::find_doc(&pattern, env)
+    } else {
+        doc::apropos(&pattern, env)
+    }
+}
+
+/// `(defprotocol Name [method [this args...]] ...)` defines a new protocol
+/// and a function for each method. Unlike `defmulti`, it replaces a protocol
+/// already named `Name`, with none of its implementations.
+fn execute_defprotocol(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let protocol = match args.first() {
+        Some(MalType::Atom(MalAtom::Symbol(name))) => Protocol::new(name.clone()),
+        non_sym => {
+            return Err(MalError::Normal(format!(
+                "First arg to defprotocol must be a symbol. Given {:?}",
+                non_sym,
+            )))
+        }
+    };
+    for method in &args[1..] {
+        match method.items().map(|mut m| m.next()) {
+            Some(first) => match first {
+                Some(MalType::Atom(MalAtom::Symbol(name))) => {
+                    env.set(
+                        name.clone(),
+                        MalType::Function(stdlib::protocol_method(name.clone(), &protocol)),
+                    );
+                }
+                _ => {
+                    return Err(MalError::Normal(format!(
+                        "defprotocol method needs a symbol for its name: {:?}",
+                        method,
+                    )))
                 }
+            },
+            None => {
+                return Err(MalError::Normal(format!(
+                    "defprotocol methods must be lists or vectors: {:?}",
+                    method,
+                )))
             }
         }
-        _ => {
+    }
+    Ok(env.set(protocol.name.clone(), MalType::Protocol(protocol)))
+}
+
+fn as_protocol(value: MalType) -> Result<Protocol, MalError> {
+    match value {
+        MalType::Protocol(protocol) => Ok(protocol),
+        other => Err(MalError::Normal(format!("`{:?}` is not a protocol", other))),
+    }
+}
+
+/// The type keyword `value` names, like `:mal/list` or the value of a
+/// record type's name.
+fn as_type(value: MalType) -> Result<Keyword, MalError> {
+    match value {
+        MalType::Atom(MalAtom::Keyword(k)) => Ok(k),
+        other => Err(MalError::Normal(format!(
+            "`{:?}` is not a type keyword",
+            other
+        ))),
+    }
+}
+
+/// Splits the specs of `extend-type`, `extend-protocol` or `defrecord` into
+/// each protocol or type and the method forms after it.
+fn impl_groups(
+    special_form: &str,
+    specs: &[MalType],
+) -> Result<Vec<(MalType, Vec<MalType>)>, MalError> {
+    let mut groups: Vec<(MalType, Vec<MalType>)> = Vec::new();
+    for spec in specs {
+        match (spec, groups.last_mut()) {
+            (MalType::List(_), Some((_, methods))) => methods.push(spec.clone()),
+            (MalType::List(_), None) => {
+                return Err(MalError::Normal(format!(
+                    "{} methods must follow what they implement. Given {:?}",
+                    special_form, spec,
+                )))
+            }
+            (name, _) => groups.push((name.clone(), Vec::new())),
+        }
+    }
+    Ok(groups)
+}
+
+/// Rewrites the arities of a `defrecord` method so its body sees the
+/// `fields` of the record it's called on as locals.
+fn bind_fields(arities: Vec<MalType>, fields: &Vector) -> Result<Vec<MalType>, MalError> {
+    let bind = |params: &MalType, body: Vec<MalType>| -> Result<MalType, MalError> {
+        let mut params: Vec<MalType> = params
+            .items()
+            .map(|params| params.cloned().collect())
+            .unwrap_or_default();
+        if params.is_empty() {
             return Err(MalError::Normal(String::from(
-                "Second arg to def! must exist, given None",
+                "defrecord methods need a param for the record",
             )));
         }
+        let this = MalType::Atom(MalAtom::Symbol(stdlib::new_gensym("G__")));
+        let record_form = mem::replace(&mut params[0], this.clone());
+        let keys = MalAtom::Keyword(Keyword::new(String::from("keys")));
+        let bindings = vec![
+            MalType::Map(iter::once((keys, MalType::Vector(fields.clone().into()))).collect()),
+            this.clone(),
+            record_form,
+            this,
+        ];
+        let let_star = iter::once(MalType::Atom(MalAtom::Symbol(Symbol::new("let*"))))
+            .chain(iter::once(MalType::Vector(bindings.into())))
+            .chain(body)
+            .collect();
+        Ok(MalType::List(
+            vec![MalType::Vector(params.into()), MalType::List(let_star)].into(),
+        ))
+    };
+    match arities.first() {
+        Some(first) if is_method(first) => arities
+            .iter()
+            .map(|arity| match arity {
+                MalType::List(l) => bind(l.first().unwrap(), l.rest().to_vec()),
+                other => Ok(other.clone()),
+            })
+            .collect(),
+        Some(params) => Ok(bind(params, arities[1..].to_vec())?
+            .items()
+            .unwrap()
+            .cloned()
+            .collect()),
+        None => Ok(arities),
     }
-    let nested_forms = Vec::from(&args[1..]);
-    let results: Vec<Result<MalType, MalError>> = nested_forms
-        .into_iter()
-        .map(|form| eval_form(form, env))
-        .collect();
-    env.pop_layer();
+}
 
-    if let Some(result) = results.last() {
-        result.clone()
-    } else {
-        Ok(MalType::Atom(MalAtom::Nil))
+/// The method map of a protocol implementation, from method forms
+/// `(name [this args...] body...)` which may have several arities like
+/// `fn*`. A record's methods see its `fields` as locals.
+fn method_map(
+    methods: &[MalType],
+    fields: Option<&Vector>,
+    env: &mut Env,
+) -> Result<Map, MalError> {
+    methods
+        .iter()
+        .map(|method| {
+            let (name, arities) = match method {
+                MalType::List(l) => match l.first() {
+                    Some(MalType::Atom(MalAtom::Symbol(name))) => (name.clone(), l.rest().to_vec()),
+                    _ => {
+                        return Err(MalError::Normal(format!(
+                            "Protocol method needs a symbol for its name: {:?}",
+                            method,
+                        )))
+                    }
+                },
+                _ => unreachable!("impl_groups only keeps lists as methods"),
+            };
+            let arities = match fields {
+                Some(fields) => bind_fields(arities, fields)?,
+                None => arities,
+            };
+            let key = MalAtom::Keyword(Keyword::new(name.get_name()));
+            let fn_star_args = iter::once(MalType::Atom(MalAtom::Symbol(name)))
+                .chain(arities)
+                .collect();
+            Ok((key, execute_fn_star(fn_star_args, env)?))
+        })
+        .collect()
+}
+
+/// `(extend-type type Protocol (method [this] body...) ...)` implements
+/// each protocol's methods for `type`, which is evaluated to a type keyword.
+fn execute_extend_type(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let type_form = args.first().cloned().ok_or_else(|| {
+        MalError::Normal(String::from(
+            "Wrong number of args for extend-type. Need 1 or more, received 0",
+        ))
+    })?;
+    let type_name = as_type(eval_form(type_form, env)?)?;
+    for (protocol, methods) in impl_groups("extend-type", &args[1..])? {
+        let protocol = as_protocol(eval_form(protocol, env)?)?;
+        let methods = method_map(&methods, None, env)?;
+        protocol
+            .impls
+            .borrow_mut()
+            .insert(type_name.clone(), methods);
+    }
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+/// `(extend-protocol Protocol type (method [this] body...) ... type ...)`
+/// implements the protocol's methods for each type.
+fn execute_extend_protocol(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let protocol_form = args.first().cloned().ok_or_else(|| {
+        MalError::Normal(String::from(
+            "Wrong number of args for extend-protocol. Need 1 or more, received 0",
+        ))
+    })?;
+    let protocol = as_protocol(eval_form(protocol_form, env)?)?;
+    for (type_form, methods) in impl_groups("extend-protocol", &args[1..])? {
+        let type_name = as_type(eval_form(type_form, env)?)?;
+        let methods = method_map(&methods, None, env)?;
+        protocol.impls.borrow_mut().insert(type_name, methods);
+    }
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+/// `(defrecord Name [fields...] Protocol (method [this] body...) ...)`
+/// defines `Name` as the record type's keyword, the constructors `->Name`
+/// and `map->Name`, and implements any protocols given for the type.
+fn execute_defrecord(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let (name, fields) = match args.as_slice() {
+        [MalType::Atom(MalAtom::Symbol(name)), MalType::Vector(fields), ..] => {
+            (name.clone(), fields.clone())
+        }
+        _ => {
+            return Err(MalError::Normal(format!(
+                "defrecord needs a name and a vector of fields. Given {:?}",
+                args,
+            )))
+        }
+    };
+    let field_keys = fields
+        .iter()
+        .map(|field| match field {
+            MalType::Atom(MalAtom::Symbol(field)) => Ok(Keyword::new(field.get_name())),
+            other => Err(MalError::Normal(format!(
+                "defrecord fields must be symbols. Given {:?}",
+                other
+            ))),
+        })
+        .collect::<Result<Vec<Keyword>, MalError>>()?;
+    let record_type = Rc::new(RecordType {
+        name: name.clone(),
+        fields: field_keys,
+    });
+    let type_name = MalType::Atom(MalAtom::Keyword(record_type.keyword()));
+    env.set(name, type_name.clone());
+    for constructor in [
+        record::positional_constructor(&record_type),
+        record::map_constructor(&record_type),
+    ] {
+        env.set(constructor.name.clone(), MalType::Function(constructor));
+    }
+    for (protocol, methods) in impl_groups("defrecord", &args[2..])? {
+        let protocol = as_protocol(eval_form(protocol, env)?)?;
+        let methods = method_map(&methods, Some(&fields), env)?;
+        protocol
+            .impls
+            .borrow_mut()
+            .insert(record_type.keyword(), methods);
+    }
+    Ok(type_name)
+}
+
+/// `(defmulti name dispatch-fn)` or `(defmulti name dispatch-fn :default
+/// value)` defines the multimethod `name`. It's left as it is if `name` is
+/// already one, keeping its methods.
+fn execute_defmulti(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    let (name, dispatch, options) = match args.as_slice() {
+        [MalType::Atom(MalAtom::Symbol(name)), dispatch, options @ ..] => {
+            (name.clone(), dispatch.clone(), options)
+        }
+        _ => {
+            return Err(MalError::Normal(format!(
+                "defmulti needs a name and a dispatch function. Given {:?}",
+                args,
+            )))
+        }
+    };
+    if let Some(multi @ MalType::MultiFn(_)) = env.find(&name) {
+        return Ok(multi);
+    }
+    let default = match options {
+        [] => MalType::Atom(MalAtom::Keyword(Keyword::new(String::from("default")))),
+        [MalType::Atom(MalAtom::Keyword(k)), value] if k.name == "default" => {
+            eval_form(value.clone(), env)?
+        }
+        _ => {
+            return Err(MalError::Normal(format!(
+                "defmulti options must be :default and a value. Given {:?}",
+                options,
+            )))
+        }
+    };
+    let dispatch = eval_form(dispatch, env)?;
+    let multi = MultiFn::new(name.clone(), dispatch, default, env.hierarchy().clone());
+    Ok(env.set(name, MalType::MultiFn(multi)))
+}
+
+/// `(defmethod name value [params] body...)` adds the method for the
+/// dispatch value `value` to the multimethod `name`. Like `fn*`, it may
+/// have several arities.
+fn execute_defmethod(args: Vec<MalType>, env: &mut Env) -> Result<MalType, MalError> {
+    if args.len() < 3 {
+        return Err(MalError::Normal(format!(
+            "Wrong number of args for defmethod. Need 3 or more, received {}",
+            args.len(),
+        )));
+    }
+    let multi = match eval_form(args[0].clone(), env)? {
+        MalType::MultiFn(multi) => multi,
+        other => {
+            return Err(MalError::Normal(format!(
+                "`{:?}` is not a multimethod",
+                other
+            )))
+        }
+    };
+    let value = eval_form(args[1].clone(), env)?;
+    multi.add_method(value, execute_fn_star(args[2..].to_vec(), env)?);
+    Ok(MalType::MultiFn(multi))
+}
+
+/// `(lazy-seq body...)` evaluates `body` the first time the seq is used,
+/// expecting a sequence back.
+fn execute_lazy_seq(body: Vec<MalType>, env: &Env) -> MalType {
+    let mut env = env.clone();
+    MalType::LazySeq(LazySeq::new(move || execute_do(body, &mut env, eval_form)))
+}
+
+fn execute_load_file(
+    args: Vec<MalType>,
+    env: &mut Env,
+    only_once: bool,
+) -> Result<MalType, MalError> {
+    let path = match args.as_slice() {
+        [MalType::Atom(MalAtom::Str(path))] => path,
+        _ => {
+            return Err(MalError::Normal(format!(
+                "load-file needs a single string path. Given {:?}",
+                args,
+            )))
+        }
+    };
+    if only_once && !env.mark_loaded(path) {
+        return Ok(MalType::Atom(MalAtom::Nil));
+    }
+    load_file(path, env)
+}
+
+/// Reads and evaluates every form in the file at `path`.
+pub fn load_file(path: &str, env: &mut Env) -> Result<MalType, MalError> {
+    // Bundled libraries are already native to every REPL environment.
+    if let Some(name) = stdlib::bundled(path).filter(|_| env.has_core()) {
+        return stdlib::load_bundled(name, env);
+    }
+    let source = fs::read_to_string(path)
+        .map_err(|err| MalError::Normal(format!("Couldn't read file {}: {}", path, err)))?;
+    for form in read_file_str(&source, path)? {
+        eval_form(form, env)?;
+    }
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+fn apply_lambda(lambda: &MalLambda, args: Vec<MalType>) -> Result<MalType, MalError> {
+    let method = match lambda
+        .methods
+        .iter()
+        .find(|method| matches!(method.arity, Arity::Fixed(n) if n == args.len()))
+        .or_else(|| {
+            lambda
+                .methods
+                .iter()
+                .find(|method| method.arity.accepts(args.len()))
+        }) {
+        Some(method) => method,
+        None => {
+            return Err(MalError::Normal(format!(
+                "Wrong number of args for {}. Need {}, received {}",
+                lambda
+                    .name
+                    .as_ref()
+                    .map_or("fn*", |name| name.name.as_str()),
+                lambda
+                    .methods
+                    .iter()
+                    .map(|method| method.arity.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" or "),
+                args.len(),
+            )))
+        }
+    };
+    let mut fn_env = lambda.env.clone();
+    fn_env.push_layer();
+    fn_env.bind(&method.params, args)?;
+    execute_do(method.body.to_vec(), &mut fn_env, eval_form)
+}
+
+/// Calls any function value, native or user-defined, with already evaluated
+/// args.
+pub fn apply(func: &MalType, args: Vec<MalType>) -> Result<MalType, MalError> {
+    match func {
+        MalType::Function(func) => func.invoke(args),
+        MalType::Lambda(lambda) => apply_lambda(lambda, args),
+        MalType::MultiFn(multi) => multi.invoke(args),
+        _ => Err(MalError::Normal(format!(
+            "Symbol `{:?}` is not a function",
+            func
+        ))),
     }
 }
 
 pub fn eval_form(form: MalType, env: &mut Env) -> Result<MalType, MalError> {
     match form {
-        MalType::List(l) => match l.get(0) {
+        MalType::List(l) => match l.first() {
             None => Ok(MalType::List(l)),
             Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "def!" => {
-                execute_def(Vec::from(&l[1..]), env)
+                execute_def(l.rest().to_vec(), l.location(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "def-dynamic" => {
+                execute_def_dynamic(l.rest().to_vec(), l.location(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "var-meta" => {
+                execute_var_meta(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "binding" => {
+                execute_binding(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "set-print-length!" => {
+                execute_set_print_length(l.rest().to_vec(), env)
             }
             Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "let*" => {
-                execute_let_star(Vec::from(&l[1..]), env)
+                execute_let_star(l.rest().to_vec(), env, eval_form)
             }
-            _ => match resolve_ast(MalType::List(l), env)? {
-                MalType::List(evaled_list) => {
-                    if evaled_list.is_empty() {
-                        return Ok(MalType::List(evaled_list));
-                    }
-                    let symbol = evaled_list[0].clone();
-                    let args = Vec::from(&evaled_list[1..]);
-                    match symbol {
-                        MalType::Function(func) => func.invoke(args),
-                        _ => Err(MalError::Normal(format!(
-                            "Symbol `{:?}` is not a function",
-                            symbol
-                        ))),
-                    }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "do" => {
+                execute_do(l.rest().to_vec(), env, eval_form)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "if" => {
+                execute_if(l.rest().to_vec(), env, eval_form)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "quote" => {
+                execute_quote(l.rest().to_vec())
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "quasiquote" => {
+                execute_quasiquote(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "fn*" || sym.name == "fn" => {
+                execute_fn_star(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "defmacro!" => {
+                execute_defmacro(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym)))
+                if sym.name == "macroexpand-1" || sym.name == "macroexpand-all" =>
+            {
+                execute_macroexpand(&sym.name, l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "doc" => {
+                doc::doc(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym)))
+                if sym.name == "find-doc" || sym.name == "apropos" =>
+            {
+                execute_doc_search(&sym.name, l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "loop" => {
+                execute_loop(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "recur" => Err(recur_error()),
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "lazy-seq" => {
+                Ok(execute_lazy_seq(l.rest().to_vec(), env))
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "defprotocol" => {
+                execute_defprotocol(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "extend-type" => {
+                execute_extend_type(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "extend-protocol" => {
+                execute_extend_protocol(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "defrecord" => {
+                execute_defrecord(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "defmulti" => {
+                execute_defmulti(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "defmethod" => {
+                execute_defmethod(l.rest().to_vec(), env)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "load-file" => {
+                execute_load_file(l.rest().to_vec(), env, false)
+            }
+            Some(MalType::Atom(MalAtom::Symbol(sym))) if sym.name == "load-file-once" => {
+                execute_load_file(l.rest().to_vec(), env, true)
+            }
+            Some(_) => match eval_call(&l, env)? {
+                Call::Expansion(expansion) => eval_form(expansion, env),
+                Call::Function(func) => {
+                    let args = l
+                        .rest()
+                        .iter()
+                        .map(|arg| eval_arg(arg, env))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    apply(&func, args)
                 }
-                non_list => Ok(non_list),
             },
         },
+        // A lazy seq is code as the list it stands for is, as `eval` of one
+        // built with `concat` expects.
+        MalType::LazySeq(seq) => eval_form(MalType::List(seq.to_vec()?.into()), env),
         non_list => resolve_ast(non_list, env),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{env::Env, eval::eval_form, printer::pr_str, reader::read_str, test_util::rep};
+
+    #[test]
+    fn let_star() {
+        assert_eq!(
+            "[3 6 [1 2]]",
+            rep("[(let* (a 1 b (+ a 1)) (+ a b)) (let* [a 1 a (+ a 5)] a) (let* [] [1 2])]")
+                .unwrap()
+        );
+        assert_eq!(
+            "let* needs an even number of binding forms. Given 3",
+            rep("(let* [a 1 b] a)").unwrap_err().to_string()
+        );
+        assert!(rep("(let* [[a 1]] a)").is_err());
+        assert!(rep("(let* [a (undefined)] a)").is_err());
+        assert!(rep("(let* [x 1] (undefined)) x").is_err());
+    }
+
+    #[test]
+    fn nested_let_bindings() {
+        let mut env = Env::repl().with_nested_let(true);
+        let mut eval = |input: &str| {
+            let form = read_str(input).unwrap().remove(0);
+            pr_str(&eval_form(form, &mut env).unwrap())
+        };
+        assert_eq!("12", eval("(let* ((p (+ 2 3)) (q (+ 2 p))) (+ p q))"));
+        assert_eq!("3", eval("(let* [a 1 b 2] (+ a b))"));
+        assert_eq!("[1 2]", eval("(let* [[a 1] [b 2]] [a b])"));
+    }
+
+    #[test]
+    fn loop_recur() {
+        assert_eq!(
+            "[5050 (3 2 1) 120 :done]",
+            rep("[(loop [i 0 acc 0] (if (> i 100) acc (recur (+ i 1) (+ acc i)))) \
+                  (loop [[x & xs] [1 2 3] out ()] (if x (recur xs (cons x out)) out)) \
+                  (loop [n 5 acc 1] (cond (= n 0) acc :else (let* [m (- n 1)] (recur m (* acc n))))) \
+                  (loop [] :done)]")
+            .unwrap()
+        );
+        assert_eq!(
+            "1000000",
+            rep("(loop [i 0] (if (< i 1000000) (recur (+ i 1)) i))").unwrap()
+        );
+        assert_eq!(
+            "(2 1 0)",
+            rep("(map (fn* [f] (f)) \
+                      (loop [i 0 fs ()] (if (< i 3) (recur (+ i 1) (cons (fn* [] i) fs)) fs)))")
+            .unwrap()
+        );
+        assert_eq!(
+            "[nil 3]",
+            rep("(defmacro! my-when (fn* [c & body] `(if ~c (do ~@body)))) \
+                 (def! n (atom 0)) \
+                 [(loop [i 0] (my-when (< i 3) (swap! n inc) (recur (inc i)))) (deref n)]")
+            .unwrap()
+        );
+        assert!(
+            rep("(defmacro! my-when (fn* [c & body] `(if ~c (do ~@body)))) \
+                     (loop [i 0] (my-when (< i 3) (recur (inc i)) i))")
+            .is_err()
+        );
+        assert_eq!(
+            "[3 1 \"(counted (inc i)) at 1:164 => (inc i)\\n\"]",
+            rep("(def! n (atom 0)) \
+                 (defmacro! counted (fn* [x] (do (swap! n inc) x))) \
+                 (def! out (atom \"\")) \
+                 (binding [*out* out *trace-macros* true] \
+                   [(loop [i 0] (if (< i 3) (recur (counted (inc i))) i)) @n @out])")
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn recur_errors() {
+        assert_eq!(
+            "recur can only be used in tail position of a loop",
+            rep("(loop [i 0] (+ 1 (recur i)))").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "recur needs 1 args to match its loop, received 2",
+            rep("(loop [i 0] (if i (recur 1 2) 0))")
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(rep("(recur 1)").is_err());
+        assert!(rep("(loop [i 0] (do (recur 1) 2))").is_err());
+        assert!(rep("(loop [i 0] (fn* [] (recur 1)))").is_err());
+        assert!(rep("(loop [i (recur 1)] i)").is_err());
+        assert!(rep("(loop [i 0 j] i)").is_err());
+        assert_eq!(
+            "[1 2]",
+            rep("(loop [i 0] (if (< i 1) (recur (+ i 1)) [i (loop [j 0] (if (< j 2) (recur (+ j 1)) j))]))")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn var_meta() {
+        assert_eq!(
+            "[\"adds\" ([x] [x y]) 2 true \"doc g\" nil nil]",
+            rep("(def! x 1)\n\
+                 (def! ^{:doc \"adds\" :private true} f (fn* ([x] x) ([x y] (+ x y)))) \
+                 (def! g \"doc g\" 1) \
+                 (let* [m (var-meta f)] \
+                   [(get m :doc) (get m :arglists) (get m :line) (get m :private) \
+                    (get (var-meta g) :doc) (get (var-meta g) :arglists) (var-meta +)])")
+            .unwrap()
+        );
+        let path = std::env::temp_dir().join(format!("mal-var-meta-{}.mal", std::process::id()));
+        std::fs::write(&path, "(def! a 1)\n\n(def! b 2)\n").unwrap();
+        let path = path.to_str().unwrap().replace('\\', "/");
+        assert_eq!(
+            format!("[\"{}\" 3]", path),
+            rep(&format!(
+                "(load-file \"{}\") [(get (var-meta b) :file) (get (var-meta b) :line)]",
+                path
+            ))
+            .unwrap()
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(rep("(def! ^[1] x 1)").is_err());
+        assert!(rep("(def! x \"doc\")").is_ok());
+        assert!(rep("(def! x 1 2)").is_err());
+    }
+
+    #[test]
+    fn syntax_quote() {
+        assert_eq!(
+            "[(a 2 3 4) [1 (a b)] {:k 2} (mal.core/cons 1 ()) (user/x [y])]",
+            rep("(def! x 2) (def! xs [3 4]) \
+                 [`(a ~x ~@xs) `[1 (a b)] `{:k ~x} `(cons 1 ()) `(x ~@(list `[y]))]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[(0) 5 true false]",
+            rep("(def! x 5) \
+                 [(let* [cons 1] (mal.core/cons 0 ())) user/x \
+                  (let* [f `(x# x#)] (= (first f) (nth f 1))) \
+                  (= (first `(x#)) (first `(x#)))]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[true true]",
+            rep(
+                "[(let* [s (str (first `(x#)))] (= s (re-find (re-pattern \"x__\\\\d+__auto__\") s))) \
+                  (starts-with? (str (gensym \"tmp\")) \"tmp\")]"
+            )
+            .unwrap()
+        );
+        assert!(rep("`~@(list 1)").is_err());
+    }
+
+    #[test]
+    fn macros() {
+        assert_eq!(
+            "[2 nil (if x nil (do y)) (g (f x)) (+ 1 2) 3]",
+            rep(
+                "(defmacro! unless (fn* [test & body] `(if ~test nil (do ~@body)))) \
+                 [(unless false 1 2) (unless true 1) \
+                  (macroexpand-1 '(unless x y)) (macroexpand-1 '(-> x f g)) \
+                  (macroexpand-1 '(+ 1 2)) (unless (< 5 3) 3)]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "(if a nil (do (if b (quote (when c)) nil) [(if d (do e))]))",
+            rep(
+                "(defmacro! unless (fn* [test & body] `(if ~test nil (do ~@body)))) \
+                 (macroexpand-all '(unless a (cond b '(when c)) [(when d e)]))"
+            )
+            .unwrap()
+        );
+        assert!(rep("(defmacro! m 1)").is_err());
+        assert!(rep("(macroexpand-1 '(-> ))").is_err());
+    }
+
+    #[test]
+    fn trace_macros() {
+        assert_eq!(
+            "\"(unless false (when true 1)) at 4:3 => (if false nil (do (when true 1)))\\n\
+             (when true 1) at 4:17 => (if true (do 1))\\n\"",
+            rep(
+                "(defmacro! unless (fn* [test & body] `(if ~test nil (do ~@body))))\n\
+                 (def! out (atom \"\"))\n\
+                 (binding [*out* out *trace-macros* true]\n  (unless false (when true 1)))\n\
+                 @out"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn variadic_fns() {
+        assert_eq!(
+            "[(1 (2 3)) (1 nil) 0 (1 2)]",
+            rep("(def! f (fn* [a & more] (list a more))) \
+                 [(f 1 2 3) (f 1) ((fn* (& xs) (count xs))) ((fn* [& xs] xs) 1 2)]")
+            .unwrap()
+        );
+        assert!(rep("((fn* [a & more] a))").is_err());
+        assert!(rep("(fn* [a &] a)").is_err());
+        assert!(rep("(fn* [& a b] a)").is_err());
+    }
+
+    #[test]
+    fn multi_arity_fns() {
+        assert_eq!(
+            "[:none (:one 1) (:two 1 2) (:many 1 2 (3 4))]",
+            rep(
+                "(def! f (fn ([] :none) ([x] (list :one x)) ([x y] (list :two x y)) \
+                 ([x y & more] (list :many x y more)))) \
+                 [(f) (f 1) (f 1 2) (f 1 2 3 4)]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[1 (1 (2))]",
+            rep("(def! g (fn* named ([x & r] (list x r)) ([x] x))) [(g 1) (g 1 2)]").unwrap()
+        );
+        assert!(rep("(fn* ([x] 1) ([y] 2))").is_err());
+        assert!(rep("(fn* ([& x] 1) ([a & y] 2))").is_err());
+        assert!(rep("(fn* ([a & x] 1) ([a b c] 2))").is_err());
+    }
+
+    #[test]
+    fn arity_errors() {
+        assert_eq!(
+            "Wrong number of args for f. Need 1 or 3 or more, received 2",
+            rep("(def! f (fn* ([x] x) ([x y z & more] x))) (f 1 2)")
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "Wrong number of args for named. Need 0, received 1",
+            rep("(def! f (fn* named [] 1)) (f 1)")
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "Wrong number of args for fn*. Need 1, received 0",
+            rep("((fn* [x] x))").unwrap_err().to_string()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    convert::TypedFn,
+    env::Env,
+    eval::{apply, eval_form, load_file},
+    reader::read_str,
+    symbol::Symbol,
+    types::{MalAtom, MalError, MalFunction, MalType},
+};
+
+/// An embeddable mal interpreter, owning a REPL environment with every
+/// built-in and bundled library function defined.
+///
+/// The state behind `*out*`, `*print-length*`, `*print-readably*` and
+/// `*trace-macros*` is per thread rather than per interpreter, so setting
+/// one of them, or `set-print-length!`, in one interpreter changes it for
+/// every other interpreter on the same thread. `binding` restores it when
+/// its body ends. Interpreters on separate threads don't affect each other.
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    env: Env,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter { env: Env::repl() }
+    }
+
+    /// Wraps an existing environment, e.g. a bare `Env::new()` for a sandbox
+    /// without built-ins.
+    pub fn with_env(env: Env) -> Self {
+        Interpreter { env }
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    /// Reads and evaluates every form in `input`, returning the value of the
+    /// last one, or nil if there were none.
+    pub fn eval_str(&mut self, input: &str) -> Result<MalType, MalError> {
+        let mut result = MalType::Atom(MalAtom::Nil);
+        for form in read_str(input)? {
+            result = eval_form(form, &mut self.env)?;
+        }
+        Ok(result)
+    }
+
+    pub fn load_file(&mut self, path: &str) -> Result<MalType, MalError> {
+        load_file(path, &mut self.env)
+    }
+
+    /// Calls the function bound to `name` with already evaluated args.
+    pub fn call(&mut self, name: &str, args: Vec<MalType>) -> Result<MalType, MalError> {
+        let func = self.env.get(&Symbol::new(name))?;
+        apply(&func, args)
+    }
+
+    pub fn define(&mut self, name: &str, value: MalType) -> MalType {
+        self.env.set(Symbol::new(name), value)
+    }
+
+    /// Defines a native function under `name`. `f` may be a closure over
+    /// host state; use `define` with a `MalFunction` to also give it an
+    /// arity or docstring.
+    pub fn register<F>(&mut self, name: &str, f: F) -> MalType
+    where
+        F: Fn(Vec<MalType>) -> Result<MalType, MalError> + 'static,
+    {
+        self.define(
+            name,
+            MalType::Function(MalFunction::new(Symbol::new(name), f)),
+        )
+    }
+
+    /// Defines a native function taking and returning Rust values, e.g.
+    /// `fn add(a: i64, b: i64) -> i64`. Calls with the wrong number or
+    /// types of args fail before `f` is reached.
+    pub fn register_typed<Args, F: TypedFn<Args>>(&mut self, name: &str, f: F) -> MalType {
+        self.define(
+            name,
+            MalType::Function(MalFunction::typed(Symbol::new(name), f)),
+        )
+    }
+}
+
+/// The same as `Interpreter::new`, with every built-in defined.
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::{
+        symbol::Symbol,
+        types::{Arity, MalAtom, MalError, MalFunction, MalType},
+        Interpreter,
+    };
+
+    fn int(num: isize) -> MalType {
+        MalType::Atom(MalAtom::Integer(num))
+    }
+
+    #[test]
+    fn eval_str() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(int(3), interpreter.eval_str("(def! x 1) (+ x 2)").unwrap());
+        assert_eq!(
+            MalType::Atom(MalAtom::Nil),
+            interpreter.eval_str("").unwrap()
+        );
+        assert!(matches!(
+            interpreter.eval_str("(abc)"),
+            Err(MalError::Resolve(_))
+        ));
+        assert_eq!(int(3), Interpreter::default().eval_str("(+ 1 2)").unwrap());
+    }
+
+    #[test]
+    fn define_and_call() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define("limit", int(10));
+        interpreter
+            .eval_str("(def! clamp (fn* [n] (if (> n limit) limit n)))")
+            .unwrap();
+        assert_eq!(int(10), interpreter.call("clamp", vec![int(12)]).unwrap());
+        assert_eq!(int(4), interpreter.call("clamp", vec![int(4)]).unwrap());
+    }
+
+    #[test]
+    fn register() {
+        fn double(args: Vec<MalType>) -> Result<MalType, MalError> {
+            match args.as_slice() {
+                [MalType::Atom(MalAtom::Integer(num))] => Ok(int(num * 2)),
+                _ => Err(MalError::Normal(String::from("double needs a number"))),
+            }
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter.register("double", double);
+        assert_eq!(int(42), interpreter.eval_str("(double 21)").unwrap());
+    }
+
+    #[test]
+    fn register_closure() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let mut interpreter = Interpreter::new();
+        interpreter.register("tick!", move |_| {
+            counter.set(counter.get() + 1);
+            Ok(int(counter.get()))
+        });
+        interpreter.eval_str("(tick!) (tick!)").unwrap();
+        assert_eq!(int(3), interpreter.eval_str("(tick!)").unwrap());
+        assert_eq!(3, calls.get());
+    }
+
+    #[test]
+    fn native_arity() {
+        let mut interpreter = Interpreter::new();
+        let first_of_two =
+            MalFunction::new(Symbol::new("first-of-two"), |args| Ok(args[0].clone()))
+                .with_arity(Arity::Fixed(2));
+        interpreter.define("first-of-two", MalType::Function(first_of_two));
+        assert_eq!(int(1), interpreter.eval_str("(first-of-two 1 2)").unwrap());
+        match interpreter.eval_str("(first-of-two 1)") {
+            Err(MalError::Normal(err)) => assert_eq!(
+                "Wrong number of args for first-of-two. Need 2, received 1",
+                err
+            ),
+            other => panic!("expected an arity error, got {:?}", other),
+        }
+    }
+}
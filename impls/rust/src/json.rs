@@ -0,0 +1,210 @@
+//! JSON built-ins. `(json-parse s)` reads JSON text into mal values, with
+//! objects as maps, arrays as vectors and null as nil; `(json-stringify x)`
+//! writes one back out. Both take an optional map of options.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{
+    keyword::Keyword,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType},
+};
+
+/// Looks up the boolean option `name` in an optional map of options.
+fn option(name: &str, options: Option<&MalType>, default: bool) -> Result<bool, MalError> {
+    match options {
+        None | Some(MalType::Atom(MalAtom::Nil)) => Ok(default),
+        Some(MalType::Map(options)) => Ok(options
+            .get(&MalAtom::Keyword(Keyword::new(name.to_string())))
+            .map_or(default, MalType::is_truthy)),
+        Some(other) => Err(MalError::Normal(format!(
+            "`{:?}` is not a map of options",
+            other
+        ))),
+    }
+}
+
+/// The byte offset in `input` of a 1-based line and column.
+fn offset(input: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = input
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    line_start + column.saturating_sub(1)
+}
+
+/// Object keys are read as keywords, so turning them back into strings is
+/// exact.
+fn string_keys(value: MalType) -> MalType {
+    match value {
+        MalType::Vector(items) => MalType::Vector(items.into_iter().map(string_keys).collect()),
+        MalType::Map(entries) => MalType::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| {
+                    let k = match k {
+                        MalAtom::Keyword(k) => MalAtom::Str(k.name),
+                        other => other,
+                    };
+                    (k, string_keys(v))
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// `(json-parse s)` reads object keys as keywords; `(json-parse s
+/// {:keywordize false})` keeps them as strings. Numbers must be integers.
+fn json_parse(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let input = match &args[0] {
+        MalType::Atom(MalAtom::Str(s)) => s,
+        other => return Err(MalError::Normal(format!("`{:?}` is not a string", other))),
+    };
+    let keywordize = option("keywordize", args.get(1), true)?;
+    let value = serde_json::from_str::<MalType>(input).map_err(|err| {
+        MalError::Normal(format!(
+            "Invalid JSON at offset {}: {}",
+            offset(input, err.line(), err.column()),
+            err,
+        ))
+    })?;
+    Ok(if keywordize {
+        value
+    } else {
+        string_keys(value)
+    })
+}
+
+pub fn json_parse_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("json-parse"), json_parse)
+        .with_arglists("([s] [s opts])")
+        .with_doc("Returns the value of the JSON s, with object keys as keywords unless opts has :keywordize false.")
+        .with_arity(Arity::Range(1, 2))
+}
+
+/// `(json-stringify x)` writes compact JSON; the options `:pretty` and
+/// `:sort-keys` indent it and order object keys.
+fn json_stringify(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let pretty = option("pretty", args.get(1), false)?;
+    let sort_keys = option("sort-keys", args.get(1), false)?;
+    let json = if sort_keys {
+        let value = serde_json::to_value(&args[0]).map_err(json_error)?;
+        write(&sorted(value), pretty)
+    } else {
+        write(&args[0], pretty)
+    }
+    .map_err(json_error)?;
+    Ok(MalType::Atom(MalAtom::Str(json)))
+}
+
+/// `value` with the keys of every object in it in order. Objects keep their
+/// keys in insertion order if serde_json's `preserve_order` feature is on,
+/// which any crate in the build can turn on, so they're inserted in order.
+fn sorted(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(k, v)| (k, sorted(v)))
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(sorted).collect()),
+        other => other,
+    }
+}
+
+fn write<T: serde::Serialize>(value: &T, pretty: bool) -> Result<String, serde_json::Error> {
+    if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+}
+
+fn json_error(err: serde_json::Error) -> MalError {
+    MalError::Normal(format!("Can't write JSON: {}", err))
+}
+
+pub fn json_stringify_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("json-stringify"), json_stringify)
+        .with_arglists("([x] [x opts])")
+        .with_doc("Returns x as compact JSON, indented if opts has :pretty true and with object keys in order if it has :sort-keys true.")
+        .with_arity(Arity::Range(1, 2))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![json_parse_fn(), json_stringify_fn()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util::rep, types::MalError};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "[1 \"a\" nil true]",
+            rep(r#"(json-parse "[1, \"a\", null, true]")"#).unwrap()
+        );
+        assert_eq!(
+            "[{:a [{:b 2}]} {\"a\" [{\"b\" 2}]}]",
+            rep(r#"[(json-parse "{\"a\": [{\"b\": 2}]}")
+                    (json-parse "{\"a\": [{\"b\": 2}]}" {:keywordize false})]"#)
+            .unwrap()
+        );
+        match rep(r#"(json-parse "{\"a\":\n [1, }")"#) {
+            Err(MalError::Normal(err)) => assert_eq!(
+                "Invalid JSON at offset 11: expected value at line 2 column 6",
+                err
+            ),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        match rep(r#"(json-parse "1.5")"#) {
+            Err(MalError::Normal(err)) => assert_eq!(
+                "Invalid JSON at offset 2: 1.5 is not an integer at line 1 column 3",
+                err
+            ),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stringify() {
+        assert_eq!(
+            r#""{\"a\":[1,\"b\",null,\"c\"],\"b\":{\"c\":true}}""#,
+            rep("(json-stringify {:b {:c true} :a [1 \"b\" nil :c]} {:sort-keys true})").unwrap()
+        );
+        assert_eq!(
+            r#""[\n  1,\n  2\n]""#,
+            rep("(json-stringify '(1 2) {:pretty true})").unwrap()
+        );
+        assert_eq!(
+            "{:a [1 2]}",
+            rep("(json-parse (json-stringify {:a [1 2]}))").unwrap()
+        );
+        assert!(rep("(json-stringify +)").is_err());
+        assert_eq!(
+            "Can't write JSON: Map keys \"a\" and :a are both written as \"a\"",
+            rep("(json-stringify {:a 1 \"a\" 2})")
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(rep("(json-stringify {'a 1 \"a\" 2} {:sort-keys true})").is_err());
+        assert_eq!(
+            "\"[0,1,2]\"",
+            rep("(json-stringify (take 3 (range)))").unwrap()
+        );
+        assert!(rep("(json-stringify (range))").is_err());
+        assert_eq!(
+            "150",
+            rep("(count (json-parse (binding [*print-length* nil] (json-stringify (range 150)))))")
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,451 @@
+//! Lazy sequences, realised an element at a time and cached, so that
+//! `(take 5 (iterate inc 0))` only ever computes five elements.
+//!
+//! A `LazySeq` starts as a thunk returning any sequence: nil, a collection or
+//! another lazy seq. Realising it leaves either an empty seq or a cell of a
+//! first element and a lazy rest. `(lazy-seq body...)` in `eval` makes one
+//! from mal, and `LazySeq::from_iter` from any Rust iterator, which is how
+//! the sequence library stays lazy.
+
+use std::{cell::RefCell, mem, rc::Rc, vec};
+
+use crate::{
+    built_ins::as_seq,
+    convert::FromMal,
+    eval::apply,
+    list, printer,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType},
+};
+
+type Thunk = Box<dyn FnOnce() -> Result<MalType, MalError>>;
+
+enum State {
+    Unrealized(Thunk),
+    /// The thunk is running. Realising the seq again from inside it is an
+    /// error rather than a deadlock.
+    Realizing,
+    Realized(Option<(MalType, LazySeq)>),
+    Failed(MalError),
+}
+
+#[derive(Clone)]
+pub struct LazySeq(Rc<RefCell<State>>);
+
+impl LazySeq {
+    pub fn new<F>(thunk: F) -> Self
+    where
+        F: FnOnce() -> Result<MalType, MalError> + 'static,
+    {
+        LazySeq::with_state(State::Unrealized(Box::new(thunk)))
+    }
+
+    pub fn empty() -> Self {
+        LazySeq::with_state(State::Realized(None))
+    }
+
+    /// An already realised seq of `first` followed by `rest`.
+    pub fn cons(first: MalType, rest: LazySeq) -> Self {
+        LazySeq::with_state(State::Realized(Some((first, rest))))
+    }
+
+    fn with_state(state: State) -> Self {
+        LazySeq(Rc::new(RefCell::new(state)))
+    }
+
+    /// A seq of the items of `iter`, each taken when first needed. An error
+    /// from `iter` is returned whenever that element is realised.
+    pub fn from_iter<I>(mut iter: I) -> Self
+    where
+        I: Iterator<Item = Result<MalType, MalError>> + 'static,
+    {
+        LazySeq::new(move || {
+            Ok(match iter.next().transpose()? {
+                Some(first) => MalType::LazySeq(LazySeq::cons(first, LazySeq::from_iter(iter))),
+                None => MalType::Atom(MalAtom::Nil),
+            })
+        })
+    }
+
+    fn from_vec(items: Vec<MalType>) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(LazySeq::empty(), |rest, first| LazySeq::cons(first, rest))
+    }
+
+    pub fn is_realized(&self) -> bool {
+        matches!(&*self.0.borrow(), State::Realized(_))
+    }
+
+    /// Runs this seq's thunk if it hasn't been, returning what it returned,
+    /// or the cell it was realised as.
+    fn run_thunk(&self) -> Result<Result<MalType, Option<(MalType, LazySeq)>>, MalError> {
+        let state = mem::replace(&mut *self.0.borrow_mut(), State::Realizing);
+        match state {
+            State::Unrealized(thunk) => Ok(Ok(thunk()?)),
+            State::Realized(cell) => {
+                *self.0.borrow_mut() = State::Realized(cell.clone());
+                Ok(Err(cell))
+            }
+            State::Failed(err) => {
+                *self.0.borrow_mut() = State::Failed(err.clone());
+                Err(err)
+            }
+            State::Realizing => Err(MalError::Normal(
+                "Lazy seq realised while realising itself".to_string(),
+            )),
+        }
+    }
+
+    /// Realises the first element, returning it and the rest, or `None` if
+    /// the seq is empty. A thunk returning another lazy seq realises that one
+    /// in turn, in a loop so long chains of them don't overflow the stack.
+    pub fn step(&self) -> Result<Option<(MalType, LazySeq)>, MalError> {
+        let mut pending = vec![self.clone()];
+        let realized = loop {
+            let seq = pending.last().unwrap().clone();
+            match seq.run_thunk() {
+                Ok(Ok(MalType::LazySeq(next))) => pending.push(next),
+                Ok(Ok(value)) => break as_seq(&value).map(|items| LazySeq::from_vec(items).cell()),
+                Ok(Err(cell)) => break Ok(cell),
+                Err(err) => break Err(err),
+            }
+        };
+        for seq in pending {
+            *seq.0.borrow_mut() = match &realized {
+                Ok(cell) => State::Realized(cell.clone()),
+                Err(err) => State::Failed(err.clone()),
+            };
+        }
+        realized
+    }
+
+    /// The cell of a seq known to be realised.
+    fn cell(&self) -> Option<(MalType, LazySeq)> {
+        match &*self.0.borrow() {
+            State::Realized(cell) => cell.clone(),
+            _ => unreachable!("cell of an unrealised lazy seq"),
+        }
+    }
+
+    pub fn iter(&self) -> SeqIter {
+        SeqIter::Lazy(Some(self.clone()))
+    }
+
+    /// Realises every element, which never returns for an infinite seq.
+    pub fn to_vec(&self) -> Result<Vec<MalType>, MalError> {
+        self.iter().collect()
+    }
+
+    /// Realises every element as `to_vec` does, but fails rather than
+    /// realise more than the print length, so writing an infinite seq out as
+    /// data ends as printing it does. Binding `*print-length*` to nil lifts
+    /// the limit.
+    pub fn to_vec_limited(&self) -> Result<Vec<MalType>, MalError> {
+        let limit = printer::print_length().unwrap_or(usize::MAX);
+        let mut items = Vec::new();
+        for item in self.iter() {
+            if items.len() == limit {
+                return Err(MalError::Normal(format!(
+                    "Can't write a lazy seq of more than *print-length* ({}) elements",
+                    limit
+                )));
+            }
+            items.push(item?);
+        }
+        Ok(items)
+    }
+
+    /// Takes the rest of this seq out of it if nothing else refers to it, so
+    /// dropping a long realised seq can unlink it a cell at a time.
+    fn take_rest_if_unique(&mut self) -> Option<LazySeq> {
+        if Rc::strong_count(&self.0) != 1 {
+            return None;
+        }
+        match &mut *self.0.borrow_mut() {
+            State::Realized(Some((_, rest))) => Some(mem::replace(rest, LazySeq::empty())),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        let mut next = self.take_rest_if_unique();
+        while let Some(mut seq) = next {
+            next = seq.take_rest_if_unique();
+        }
+    }
+}
+
+/// Iterates over the elements of any sequence, realising lazy ones only as
+/// far as needed.
+pub enum SeqIter {
+    Lazy(Option<LazySeq>),
+    List(list::IntoIter),
+    Vector(im_rc::vector::ConsumingIter<MalType>),
+    Items(vec::IntoIter<MalType>),
+}
+
+impl Iterator for SeqIter {
+    type Item = Result<MalType, MalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SeqIter::Lazy(seq) => match seq.take()?.step() {
+                Ok(Some((first, rest))) => {
+                    *seq = Some(rest);
+                    Some(Ok(first))
+                }
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            },
+            SeqIter::List(items) => items.next().map(Ok),
+            SeqIter::Vector(items) => items.next().map(Ok),
+            SeqIter::Items(items) => items.next().map(Ok),
+        }
+    }
+}
+
+/// Iterates over `value`, which may be anything `as_seq` accepts or a lazy
+/// seq.
+pub fn iter(value: &MalType) -> Result<SeqIter, MalError> {
+    match value {
+        MalType::LazySeq(seq) => Ok(seq.iter()),
+        MalType::List(l) => Ok(SeqIter::List(l.clone().into_iter())),
+        MalType::Vector(v) => Ok(SeqIter::Vector(v.clone().into_iter())),
+        other => Ok(SeqIter::Items(as_seq(other)?.into_iter())),
+    }
+}
+
+/// Whether two sequences, at least one lazy, have equal elements. Seqs that
+/// fail to realise are unequal to everything.
+pub fn seq_eq(a: &MalType, b: &MalType) -> bool {
+    let (Ok(mut a), Ok(mut b)) = (iter(a), iter(b)) else {
+        return false;
+    };
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (Some(Ok(x)), Some(Ok(y))) if x == y => {}
+            _ => return false,
+        }
+    }
+}
+
+/// `(doall xs)` realises every element of `xs`, returning them as a list.
+fn doall(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::List(
+        iter(&args[0])?.collect::<Result<Vec<_>, _>>()?.into(),
+    ))
+}
+
+pub fn doall_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("doall"), doall)
+        .with_arglists("([coll])")
+        .with_doc("Realises every element of coll, returning them as a list.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(realized? xs)` is whether a lazy seq's first element has been
+/// computed. Every other value is already realised.
+fn is_realized(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Boolean(match &args[0] {
+        MalType::LazySeq(seq) => seq.is_realized(),
+        _ => true,
+    })))
+}
+
+pub fn is_realized_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("realized?"), is_realized)
+        .with_arglists("([coll])")
+        .with_doc(
+            "Returns true unless coll is a lazy seq whose first element hasn't been computed.",
+        )
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(iterate f x)` is the infinite seq `x`, `(f x)`, `(f (f x))`...
+fn iterate(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let f = args[0].clone();
+    let mut next = Some(Ok(args[1].clone()));
+    Ok(MalType::LazySeq(LazySeq::from_iter(std::iter::from_fn(
+        move || {
+            let current = next.take()?;
+            if let Ok(x) = &current {
+                next = Some(apply(&f, vec![x.clone()]));
+            }
+            Some(current)
+        },
+    ))))
+}
+
+pub fn iterate_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("iterate"), iterate)
+        .with_arglists("([f x])")
+        .with_doc("Returns the infinite lazy seq of x, (f x), (f (f x)) and so on.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(repeat x)` is `x` forever; `(repeat n x)` is `n` of it.
+fn repeat(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::LazySeq(match args.as_slice() {
+        [x] => LazySeq::from_iter(std::iter::repeat(x.clone()).map(Ok)),
+        [n, x] => {
+            let n = isize::from_mal(n.clone())?.max(0) as usize;
+            LazySeq::from_iter(std::iter::repeat_n(x.clone(), n).map(Ok))
+        }
+        _ => unreachable!(),
+    }))
+}
+
+pub fn repeat_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("repeat"), repeat)
+        .with_arglists("([x] [n x])")
+        .with_doc("Returns a lazy seq of n, or infinitely many, xs.")
+        .with_arity(Arity::Range(1, 2))
+}
+
+/// `(repeatedly f)` calls `f` with no args for each element.
+fn repeatedly(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let f = args[0].clone();
+    Ok(MalType::LazySeq(LazySeq::from_iter(std::iter::from_fn(
+        move || Some(apply(&f, Vec::new())),
+    ))))
+}
+
+pub fn repeatedly_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("repeatedly"), repeatedly)
+        .with_arglists("([f])")
+        .with_doc("Returns an infinite lazy seq of the results of calling f with no args.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(cycle xs)` repeats the elements of `xs` forever. `xs` is realised in
+/// full first, so it must be finite.
+fn cycle(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let items: Vec<MalType> = iter(&args[0])?.collect::<Result<_, _>>()?;
+    Ok(MalType::LazySeq(LazySeq::from_iter(
+        items.into_iter().cycle().map(Ok),
+    )))
+}
+
+pub fn cycle_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("cycle"), cycle)
+        .with_arglists("([coll])")
+        .with_doc("Returns an infinite lazy seq of the elements of the finite coll, repeated.")
+        .with_arity(Arity::Fixed(1))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        doall_fn(),
+        is_realized_fn(),
+        iterate_fn(),
+        repeat_fn(),
+        repeatedly_fn(),
+        cycle_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreter::Interpreter, test_util::rep_in};
+
+    #[test]
+    fn infinite_seqs() {
+        let mut mal = Interpreter::new();
+        assert_eq!(
+            "[(0 1 2 3 4) (:a :a) (1 2 1 2 1) (0 2 4) (\"x\" \"x\") 3]",
+            rep_in(
+                "[(take 5 (iterate inc 0)) (take 2 (repeat :a)) (take 5 (cycle [1 2])) \
+                  (take 3 (filter (fn* [x] (= x (* 2 (/ x 2)))) (range))) \
+                  (repeat 2 \"x\") (first (drop 3 (range)))]",
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[(1 0 1) (1 2 3) 3 3 (+ 1 2)]",
+            rep_in(
+                "(defmacro! plus (fn* [& xs] (concat '(+) xs))) \
+                 (defmacro! inc-do (fn* [x] (list 'do (concat '(+ 1) [x])))) \
+                 [(take 3 (concat [1] (range))) (concat '(1) [2] (lazy-seq '(3))) \
+                  (plus 1 2) (inc-do 2) (macroexpand-1 '(plus 1 2))]",
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert!(rep_in("(concat [1] 2)", &mut mal).is_err());
+        rep_in("(set-print-length! 3)", &mut mal).unwrap();
+        assert_eq!("(0 1 2 ...)", rep_in("(range)", &mut mal).unwrap());
+        assert_eq!("(0 1 2)", rep_in("(range 3)", &mut mal).unwrap());
+        assert_eq!(
+            "[3 \"(0 1 ...)\" 3]",
+            rep_in(
+                "[*print-length* (binding [*print-length* 2] (pr-str (range))) *print-length*]",
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!("(0 1 2 ...)", rep_in("(range)", &mut mal).unwrap());
+        assert!(rep_in("(set-print-length! -1)", &mut mal).is_err());
+        rep_in("(set-print-length! 100)", &mut mal).unwrap();
+        assert_eq!(
+            "[101 \"...\"]",
+            rep_in(
+                "(let* [s (pr-str (range))] [(count (split s \" \")) (subs s (- (count s) 4) (- (count s) 1))])",
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[150 149]",
+            rep_in(
+                "(let* [xs (read-edn (binding [*print-length* nil] (pr-str (range 150))))] \
+                   [(count xs) (last xs)])",
+                &mut mal
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn lazy_seq() {
+        let mut mal = Interpreter::new();
+        rep_in(
+            "(def! calls (atom 0)) \
+             (def! nums (fn* [n] (lazy-seq (swap! calls inc) (cons n (nums (inc n))))))",
+            &mut mal,
+        )
+        .unwrap();
+        assert_eq!(
+            "[(0 1 2) 3]",
+            rep_in("(def! xs (nums 0)) [(doall (take 3 xs)) @calls]", &mut mal).unwrap()
+        );
+        assert_eq!(
+            "[(0 1 2 3) 4 true]",
+            rep_in("[(doall (take 4 xs)) @calls (realized? xs)]", &mut mal).unwrap()
+        );
+        assert_eq!(
+            "[true false (1 2) true nil]",
+            rep_in(
+                "[(= (map inc [0 1]) [1 2]) (empty? (lazy-seq [1])) (doall (lazy-seq (list 1 2))) \
+                  (empty? (lazy-seq nil)) (first (lazy-seq nil))]",
+                &mut mal
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "100000",
+            rep_in("(count (map inc (range 100000)))", &mut mal).unwrap()
+        );
+        // Dropping a long realised seq mustn't overflow the stack.
+        assert_eq!(
+            "nil",
+            rep_in("(def! r (range 200000)) (count r) (def! r nil)", &mut mal).unwrap()
+        );
+        assert!(rep_in("(doall (map (fn* [x] (nth [] x)) [1]))", &mut mal).is_err());
+    }
+}
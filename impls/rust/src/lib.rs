@@ -1,12 +1,38 @@
 extern crate pest;
+// Lets `#[derive(FromMal, IntoMal)]` name `::mal` from inside this crate.
+extern crate self as mal;
 #[macro_use]
 extern crate pest_derive;
 
+pub mod atom;
 pub mod built_ins;
+pub mod convert;
+pub mod csv;
+pub mod destructure;
+pub mod doc;
+pub mod dynamic;
+pub mod edn;
 pub mod env;
 pub mod eval;
+pub mod interpreter;
+pub mod json;
 pub mod keyword;
+pub mod lazy;
+pub mod list;
+pub mod meta;
+pub mod multi;
+pub mod pattern;
 pub mod printer;
 pub mod reader;
+pub mod record;
+pub mod seq;
+pub mod serde;
+pub mod stdlib;
+pub mod string;
 pub mod symbol;
+#[cfg(test)]
+mod test_util;
+pub mod transient;
 pub mod types;
+
+pub use interpreter::Interpreter;
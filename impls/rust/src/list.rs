@@ -0,0 +1,229 @@
+//! Persistent lists of cons cells. `cons` and `rest` are O(1) and the new
+//! list shares its tail with the old one, so building a list one element at
+//! a time, or walking it, never copies it.
+
+use std::{fmt, iter::FromIterator, mem, rc::Rc};
+
+use crate::{
+    reader::Location,
+    types::{MalType, Meta},
+};
+
+struct Cons {
+    first: MalType,
+    rest: List,
+}
+
+#[derive(Clone, Default)]
+pub struct List {
+    head: Option<Rc<Cons>>,
+    len: usize,
+    /// Where the reader read the list from, which isn't part of its value.
+    location: Option<Rc<Location>>,
+    meta: Meta,
+}
+
+impl List {
+    pub fn new() -> Self {
+        List::default()
+    }
+
+    /// This list with `first` in front of it.
+    pub fn cons(&self, first: MalType) -> Self {
+        List {
+            head: Some(Rc::new(Cons {
+                first,
+                rest: self.clone(),
+            })),
+            len: self.len + 1,
+            location: None,
+            meta: None,
+        }
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_deref()
+    }
+
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(Rc::new(location));
+        self
+    }
+
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    pub fn with_meta(mut self, meta: Meta) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn first(&self) -> Option<&MalType> {
+        self.head.as_ref().map(|cons| &cons.first)
+    }
+
+    /// Every element but the first, or the empty list if there are none.
+    pub fn rest(&self) -> Self {
+        self.head
+            .as_ref()
+            .map_or_else(List::new, |cons| cons.rest.clone())
+    }
+
+    /// The number of elements, which is kept with the list rather than
+    /// counted.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The element at `index`, found in O(`index`) steps.
+    pub fn get(&self, index: usize) -> Option<&MalType> {
+        self.iter().nth(index)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            next: self.head.as_deref(),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<MalType> {
+        self.iter().cloned().collect()
+    }
+}
+
+/// Unlinks cells no other list shares one at a time, so dropping a long list
+/// doesn't recurse once per element.
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut head = self.head.take();
+        while let Some(cons) = head {
+            head = match Rc::try_unwrap(cons) {
+                Ok(mut cons) => cons.rest.head.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl From<Vec<MalType>> for List {
+    fn from(items: Vec<MalType>) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(List::new(), |rest, first| rest.cons(first))
+    }
+}
+
+impl FromIterator<MalType> for List {
+    fn from_iter<I: IntoIterator<Item = MalType>>(iter: I) -> Self {
+        List::from(iter.into_iter().collect::<Vec<MalType>>())
+    }
+}
+
+pub struct Iter<'a> {
+    next: Option<&'a Cons>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a MalType;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cons = self.next?;
+        self.next = cons.rest.head.as_deref();
+        Some(&cons.first)
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a MalType;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Moves elements out of cells no other list shares, and clones the rest.
+pub struct IntoIter {
+    list: List,
+}
+
+impl Iterator for IntoIter {
+    type Item = MalType;
+
+    fn next(&mut self) -> Option<MalType> {
+        let cons = self.list.head.take()?;
+        match Rc::try_unwrap(cons) {
+            Ok(mut cons) => {
+                self.list = mem::take(&mut cons.rest);
+                Some(cons.first)
+            }
+            Err(cons) => {
+                self.list = cons.rest.clone();
+                Some(cons.first.clone())
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.list.len, Some(self.list.len))
+    }
+}
+
+impl ExactSizeIterator for IntoIter {}
+
+impl IntoIterator for List {
+    type Item = MalType;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter { list: self }
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::List;
+    use crate::types::{MalAtom, MalType};
+
+    fn int(n: isize) -> MalType {
+        MalType::Atom(MalAtom::Integer(n))
+    }
+
+    #[test]
+    fn sharing() {
+        let tail = List::from(vec![int(2), int(3)]);
+        let list = tail.cons(int(1));
+        assert_eq!(3, list.len());
+        assert_eq!(Some(&int(1)), list.first());
+        assert_eq!(tail, list.rest());
+        assert_eq!(vec![int(2), int(3)], tail.into_iter().collect::<Vec<_>>());
+        assert_eq!(Some(&int(3)), list.get(2));
+        assert_eq!(vec![int(1), int(2), int(3)], list.to_vec());
+        assert!(List::new().rest().is_empty());
+    }
+
+    #[test]
+    fn long_lists() {
+        let list: List = (0..1_000_000).map(int).collect();
+        assert_eq!(1_000_000, list.iter().count());
+        drop(list);
+    }
+}
@@ -0,0 +1,138 @@
+//! Metadata, a map attached to a list, vector, map, symbol or function
+//! without being part of its value: `=` and hashing ignore it, and
+//! `with-meta` returns a copy of the value with other metadata.
+//!
+//! `^{:a 1} x` is read as `(with-meta x {:a 1})`, and `^:a x` as
+//! `(with-meta x {:a true})`.
+
+use std::rc::Rc;
+
+use crate::{
+    eval::apply,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalLambda, MalType, Meta},
+};
+
+/// The metadata of `value`, or `None` if it can't have any.
+pub fn meta_of(value: &MalType) -> Option<&Meta> {
+    match value {
+        MalType::List(l) => Some(l.meta()),
+        MalType::Vector(v) => Some(&v.meta),
+        MalType::Map(m) => Some(&m.meta),
+        MalType::Atom(MalAtom::Symbol(sym)) => Some(&sym.meta),
+        MalType::Function(f) => Some(&f.meta),
+        MalType::Lambda(lambda) => Some(&lambda.meta),
+        _ => None,
+    }
+}
+
+/// `value` with the metadata `meta` in place of its own.
+pub fn replace_meta(value: MalType, meta: Meta) -> Result<MalType, MalError> {
+    Ok(match value {
+        MalType::List(l) => MalType::List(l.with_meta(meta)),
+        MalType::Vector(v) => MalType::Vector(v.with_meta(meta)),
+        MalType::Map(m) => MalType::Map(m.with_meta(meta)),
+        MalType::Atom(MalAtom::Symbol(sym)) => MalType::Atom(MalAtom::Symbol(sym.with_meta(meta))),
+        MalType::Function(f) => MalType::Function(f.with_meta(meta)),
+        MalType::Lambda(lambda) => MalType::Lambda(MalLambda { meta, ..lambda }),
+        other => return Err(MalError::Normal(format!("`{}` can't have metadata", other))),
+    })
+}
+
+/// Metadata as a mal value, nil for none.
+fn to_mal(meta: &Meta) -> MalType {
+    match meta {
+        Some(m) => MalType::Map((**m).clone().into()),
+        None => MalType::Atom(MalAtom::Nil),
+    }
+}
+
+/// A mal value as metadata, which must be a map or nil.
+fn from_mal(meta: &MalType) -> Result<Meta, MalError> {
+    match meta {
+        MalType::Map(m) => Ok(Some(Rc::new(m.value.clone()))),
+        MalType::Atom(MalAtom::Nil) => Ok(None),
+        other => Err(MalError::Normal(format!(
+            "Metadata must be a map or nil. Given {}",
+            other
+        ))),
+    }
+}
+
+fn meta(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(meta_of(&args[0]).map_or(MalType::Atom(MalAtom::Nil), to_mal))
+}
+
+pub fn meta_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("meta"), meta)
+        .with_arglists("([x])")
+        .with_doc("Returns the metadata of x, or nil if it has none.")
+        .with_arity(Arity::Fixed(1))
+}
+
+fn with_meta(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let meta = from_mal(&args[1])?;
+    replace_meta(args.into_iter().next().unwrap(), meta)
+}
+
+pub fn with_meta_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("with-meta"), with_meta)
+        .with_arglists("([x meta])")
+        .with_doc("Returns x with the metadata meta.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(vary-meta x f args...)` gives `x` the metadata `(f (meta x) args...)`.
+fn vary_meta(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut args = args.into_iter();
+    let value = args.next().unwrap();
+    let f = args.next().unwrap();
+    let old = meta_of(&value).map_or(MalType::Atom(MalAtom::Nil), to_mal);
+    let meta = from_mal(&apply(&f, std::iter::once(old).chain(args).collect())?)?;
+    replace_meta(value, meta)
+}
+
+pub fn vary_meta_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("vary-meta"), vary_meta)
+        .with_arglists("([x f & args])")
+        .with_doc("Returns x with the metadata (f (meta x) args...).")
+        .with_arity(Arity::Variadic(2))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![meta_fn(), with_meta_fn(), vary_meta_fn()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn meta() {
+        assert_eq!(
+            "[{:a 1} {:b true} {:c 1} {:d 1} {:e 1} {:f 1} nil nil]",
+            rep(
+                "[(meta (with-meta '(1 2) {:a 1})) (meta ^:b [1 2]) (meta ^{:c 1} {:k 1}) \
+                  (meta (with-meta 'x {:d 1})) (meta (with-meta + {:e 1})) \
+                  (meta (with-meta (fn* [x] x) {:f 1})) (meta [1]) (meta 1)]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[true true 1 true 3 {:a 2}]",
+            rep("(def! v ^{:a 1} [1 2]) \
+                 (def! calls (atom 0)) (def! f (memoize (fn* [x] (swap! calls inc)))) \
+                 [(= v [1 2]) (= (with-meta 'x {:a 1}) 'x) \
+                  (do (f v) (f [1 2]) @calls) (= (with-meta {:k 1} {:a 1}) {:k 1}) \
+                  ((with-meta (fn* [x] (+ x 1)) {:a 1}) 2) \
+                  (meta (vary-meta v assoc :a 2))]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[nil [1 2]]",
+            rep("[(meta (with-meta ^:a [] nil)) (with-meta [1 2] {:a 1})]").unwrap()
+        );
+        assert!(rep("(with-meta 1 {:a 1})").is_err());
+        assert!(rep("(with-meta [] 1)").is_err());
+    }
+}
@@ -0,0 +1,413 @@
+//! Multimethods, functions dispatching on any value computed from their
+//! args. `(defmulti area shape-of)` defines `area`, which calls `shape-of`
+//! with its args and then the method `(defmethod area :circle [c] ...)`
+//! added for the value that returns. Methods can be added from any file
+//! sharing the environment.
+//!
+//! A method also handles every value that `isa?` its dispatch value, where
+//! `(derive :square :rect)` makes `:square` one of `:rect`. Vectors of
+//! dispatch values match element by element. Of several matching methods
+//! the one for the most derived value is called, and if none is more
+//! derived than the others the call is an error. With no matching method
+//! the `:default` one is called, or the one given with `(defmulti name f
+//! :default value)`.
+//!
+//! The hierarchy `derive` builds belongs to the environment, so every
+//! multimethod defined in it sees the same one.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    eval::apply,
+    printer::pr_str,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType},
+};
+
+/// The parents of each keyword or symbol given one by `derive`.
+#[derive(Clone, Debug, Default)]
+pub struct Hierarchy(Rc<RefCell<HashMap<MalAtom, HashSet<MalAtom>>>>);
+
+impl Hierarchy {
+    pub fn new() -> Self {
+        Hierarchy::default()
+    }
+
+    /// Makes `parent` a parent of `child`, unless that would make a value
+    /// derive from itself.
+    pub fn derive(&self, child: MalAtom, parent: MalAtom) -> Result<(), MalError> {
+        if child == parent || self.ancestors(&parent).contains(&child) {
+            return Err(MalError::Normal(format!(
+                "Cyclic derivation: {} is already an ancestor of {}",
+                child, parent
+            )));
+        }
+        self.0.borrow_mut().entry(child).or_default().insert(parent);
+        Ok(())
+    }
+
+    pub fn underive(&self, child: &MalAtom, parent: &MalAtom) {
+        if let Some(parents) = self.0.borrow_mut().get_mut(child) {
+            parents.remove(parent);
+        }
+    }
+
+    pub fn parents(&self, child: &MalAtom) -> HashSet<MalAtom> {
+        self.0.borrow().get(child).cloned().unwrap_or_default()
+    }
+
+    /// The parents of `child`, their parents and so on.
+    pub fn ancestors(&self, child: &MalAtom) -> HashSet<MalAtom> {
+        let mut found = HashSet::new();
+        let mut next = vec![child.clone()];
+        while let Some(atom) = next.pop() {
+            for parent in self.parents(&atom) {
+                if found.insert(parent.clone()) {
+                    next.push(parent);
+                }
+            }
+        }
+        found
+    }
+
+    /// Everything with `parent` as an ancestor.
+    pub fn descendants(&self, parent: &MalAtom) -> HashSet<MalAtom> {
+        let children: Vec<MalAtom> = self.0.borrow().keys().cloned().collect();
+        children
+            .into_iter()
+            .filter(|child| self.ancestors(child).contains(parent))
+            .collect()
+    }
+
+    /// Whether `child` is equal to `parent`, derives from it, or is a vector
+    /// whose elements each `isa?` the one in the same place of `parent`.
+    pub fn isa(&self, child: &MalType, parent: &MalType) -> bool {
+        if child == parent {
+            return true;
+        }
+        match (child, parent) {
+            (MalType::Atom(child), MalType::Atom(parent)) => self.ancestors(child).contains(parent),
+            (MalType::Vector(children), MalType::Vector(parents)) => {
+                children.len() == parents.len()
+                    && children
+                        .iter()
+                        .zip(parents.iter())
+                        .all(|(child, parent)| self.isa(child, parent))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A function made by `defmulti`. Clones share their methods.
+#[derive(Clone)]
+pub struct MultiFn {
+    pub name: Symbol,
+    pub dispatch: Rc<MalType>,
+    /// The dispatch value of the method called when no other matches.
+    pub default: Rc<MalType>,
+    /// Each dispatch value and its method, in the order they were added.
+    pub methods: Rc<RefCell<Vec<(MalType, MalType)>>>,
+    hierarchy: Hierarchy,
+}
+
+impl MultiFn {
+    pub fn new(name: Symbol, dispatch: MalType, default: MalType, hierarchy: Hierarchy) -> Self {
+        MultiFn {
+            name,
+            dispatch: Rc::new(dispatch),
+            default: Rc::new(default),
+            methods: Rc::new(RefCell::new(Vec::new())),
+            hierarchy,
+        }
+    }
+
+    /// Adds or replaces the method for `value`.
+    pub fn add_method(&self, value: MalType, method: MalType) {
+        let mut methods = self.methods.borrow_mut();
+        match methods.iter_mut().find(|(v, _)| *v == value) {
+            Some(entry) => entry.1 = method,
+            None => methods.push((value, method)),
+        }
+    }
+
+    pub fn remove_method(&self, value: &MalType) {
+        self.methods.borrow_mut().retain(|(v, _)| v != value);
+    }
+
+    /// The method a call dispatching on `value` would use, if any.
+    pub fn find_method(&self, value: &MalType) -> Result<Option<MalType>, MalError> {
+        let methods = self.methods.borrow();
+        if let Some((_, method)) = methods.iter().find(|(v, _)| v == value) {
+            return Ok(Some(method.clone()));
+        }
+        let matching: Vec<&(MalType, MalType)> = methods
+            .iter()
+            .filter(|(v, _)| self.hierarchy.isa(value, v))
+            .collect();
+        let dominant = matching.iter().find(|(v, _)| {
+            matching
+                .iter()
+                .all(|(other, _)| self.hierarchy.isa(v, other))
+        });
+        match (dominant, matching.as_slice()) {
+            (Some((_, method)), _) => Ok(Some(method.clone())),
+            (None, [(a, _), (b, _), ..]) => Err(MalError::Normal(format!(
+                "Multiple methods in multimethod {} match dispatch value {}: {} and {}, \
+                 and neither is more derived",
+                self.name,
+                pr_str(value),
+                pr_str(a),
+                pr_str(b),
+            ))),
+            (None, _) => Ok(methods
+                .iter()
+                .find(|(v, _)| v == &*self.default)
+                .map(|(_, method)| method.clone())),
+        }
+    }
+
+    pub fn invoke(&self, args: Vec<MalType>) -> Result<MalType, MalError> {
+        let value = apply(&self.dispatch, args.clone())?;
+        match self.find_method(&value)? {
+            Some(method) => apply(&method, args),
+            None => Err(MalError::Normal(format!(
+                "No method in multimethod {} for dispatch value {}",
+                self.name,
+                pr_str(&value),
+            ))),
+        }
+    }
+}
+
+impl PartialEq for MultiFn {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.methods, &other.methods)
+    }
+}
+
+fn as_multi<'a>(name: &str, value: &'a MalType) -> Result<&'a MultiFn, MalError> {
+    match value {
+        MalType::MultiFn(multi) => Ok(multi),
+        other => Err(MalError::Normal(format!(
+            "{} needs a multimethod. Given {}",
+            name, other
+        ))),
+    }
+}
+
+fn hierarchy_key(name: &str, value: &MalType) -> Result<MalAtom, MalError> {
+    match value {
+        MalType::Atom(atom @ (MalAtom::Keyword(_) | MalAtom::Symbol(_))) => Ok(atom.clone()),
+        other => Err(MalError::Normal(format!(
+            "{} needs keywords or symbols. Given {}",
+            name, other
+        ))),
+    }
+}
+
+/// A list of `atoms` in printed order, or nil if there are none.
+fn atom_list(atoms: HashSet<MalAtom>) -> MalType {
+    if atoms.is_empty() {
+        return MalType::Atom(MalAtom::Nil);
+    }
+    let mut atoms: Vec<MalAtom> = atoms.into_iter().collect();
+    atoms.sort_by_cached_key(MalAtom::to_string);
+    MalType::List(atoms.into_iter().map(MalType::Atom).collect())
+}
+
+/// `(remove-method multi value)` removes the method for `value`.
+fn remove_method(args: Vec<MalType>) -> Result<MalType, MalError> {
+    as_multi("remove-method", &args[0])?.remove_method(&args[1]);
+    Ok(args[0].clone())
+}
+
+pub fn remove_method_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("remove-method"), remove_method)
+        .with_arglists("([multi dispatch-val])")
+        .with_doc("Removes the method of multi for dispatch-val.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(methods multi)` returns a list of `[value method]` pairs, one for each
+/// method.
+fn methods(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let multi = as_multi("methods", &args[0])?;
+    let methods = multi.methods.borrow();
+    Ok(MalType::List(
+        methods
+            .iter()
+            .map(|(value, method)| MalType::Vector(vec![value.clone(), method.clone()].into()))
+            .collect(),
+    ))
+}
+
+pub fn methods_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("methods"), methods)
+        .with_arglists("([multi])")
+        .with_doc("Returns a list of [dispatch-val method] pairs, one for each method of multi.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(get-method multi value)` returns the method a call dispatching on
+/// `value` would use, or nil.
+fn get_method(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(as_multi("get-method", &args[0])?
+        .find_method(&args[1])?
+        .unwrap_or(MalType::Atom(MalAtom::Nil)))
+}
+
+pub fn get_method_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("get-method"), get_method)
+        .with_arglists("([multi dispatch-val])")
+        .with_doc("Returns the method multi would call for dispatch-val, or nil if there is none.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// The functions working on `hierarchy`: `derive`, `underive`, `isa?`,
+/// `parents`, `ancestors` and `descendants`.
+pub fn hierarchy_fns(hierarchy: &Hierarchy) -> Vec<MalFunction> {
+    let h = hierarchy.clone();
+    let derive = MalFunction::new(Symbol::new("derive"), move |args| {
+        let child = hierarchy_key("derive", &args[0])?;
+        h.derive(child, hierarchy_key("derive", &args[1])?)?;
+        Ok(MalType::Atom(MalAtom::Nil))
+    });
+    let h = hierarchy.clone();
+    let underive = MalFunction::new(Symbol::new("underive"), move |args| {
+        h.underive(
+            &hierarchy_key("underive", &args[0])?,
+            &hierarchy_key("underive", &args[1])?,
+        );
+        Ok(MalType::Atom(MalAtom::Nil))
+    });
+    let h = hierarchy.clone();
+    let isa = MalFunction::new(Symbol::new("isa?"), move |args| {
+        Ok(MalType::Atom(MalAtom::Boolean(h.isa(&args[0], &args[1]))))
+    });
+    let h = hierarchy.clone();
+    let parents = MalFunction::new(Symbol::new("parents"), move |args| {
+        Ok(atom_list(h.parents(&hierarchy_key("parents", &args[0])?)))
+    });
+    let h = hierarchy.clone();
+    let ancestors = MalFunction::new(Symbol::new("ancestors"), move |args| {
+        Ok(atom_list(
+            h.ancestors(&hierarchy_key("ancestors", &args[0])?),
+        ))
+    });
+    let h = hierarchy.clone();
+    let descendants = MalFunction::new(Symbol::new("descendants"), move |args| {
+        Ok(atom_list(
+            h.descendants(&hierarchy_key("descendants", &args[0])?),
+        ))
+    });
+    vec![
+        derive
+            .with_arity(Arity::Fixed(2))
+            .with_arglists("([child parent])")
+            .with_doc("Makes the keyword or symbol parent a parent of child."),
+        underive
+            .with_arity(Arity::Fixed(2))
+            .with_arglists("([child parent])")
+            .with_doc("Makes parent no longer a parent of child."),
+        isa
+            .with_arity(Arity::Fixed(2))
+            .with_arglists("([child parent])")
+            .with_doc("Returns true if child equals parent or derives from it. Vectors are compared element by element."),
+        parents
+            .with_arity(Arity::Fixed(1))
+            .with_arglists("([x])")
+            .with_doc("Returns a list of the parents of x, or nil if it has none."),
+        ancestors
+            .with_arity(Arity::Fixed(1))
+            .with_arglists("([x])")
+            .with_doc("Returns a list of the parents of x, their parents and so on, or nil if it has none."),
+        descendants
+            .with_arity(Arity::Fixed(1))
+            .with_arglists("([x])")
+            .with_doc("Returns a list of everything deriving from x, or nil if there is nothing."),
+    ]
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![remove_method_fn(), methods_fn(), get_method_fn()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    const AREA: &str = "(defmulti area (fn* [s] (get s :shape))) \
+                        (defmethod area :square [s] (* (get s :side) (get s :side))) \
+                        (defmethod area :circle [{:keys [r]}] (* 3 r r)) \
+                        (defmethod area :default [s] :unknown) ";
+
+    #[test]
+    fn multimethods() {
+        assert_eq!(
+            "[4 3 :unknown]",
+            rep(&format!(
+                "{} [(area {{:shape :square :side 2}}) (area {{:shape :circle :r 1}}) \
+                  (area {{:shape :blob}})]",
+                AREA
+            ))
+            .unwrap()
+        );
+        assert_eq!(
+            "[2 true 16]",
+            rep(&format!(
+                "{} (remove-method area :default) \
+                 (defmulti area identity) \
+                 (defmethod area :circle [{{:keys [r]}}] (* 4 r r)) \
+                 [(count (methods area)) (= nil (get-method area :blob)) \
+                  (area {{:shape :circle :r 2}})]",
+                AREA
+            ))
+            .unwrap()
+        );
+        assert_eq!(
+            "No method in multimethod area for dispatch value :blob",
+            rep(&format!(
+                "{} (remove-method area :default) (area {{:shape :blob}})",
+                AREA
+            ))
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            ":fallback",
+            rep("(defmulti g identity :default :other) (defmethod g :other [_] :fallback) (g 1)")
+                .unwrap()
+        );
+        assert!(rep("(defmethod nope :a [x] x)").is_err());
+    }
+
+    #[test]
+    fn hierarchies() {
+        assert_eq!(
+            "[\"rect\" \"rect\" \"shape\" \"pair\" true true false (:rect) (:rect :shape) (:rect :square)]",
+            rep("(derive :rect :shape) (derive :square :rect) \
+                 (defmulti describe identity) \
+                 (defmethod describe :shape [_] \"shape\") \
+                 (defmethod describe :rect [_] \"rect\") \
+                 (defmethod describe [:shape :shape] [_] \"pair\") \
+                 [(describe :square) (describe :rect) (describe :shape) (describe [:square :rect]) \
+                  (isa? :square :shape) (isa? [:square :rect] [:shape :shape]) (isa? :shape :square) \
+                  (parents :square) (ancestors :square) (descendants :shape)]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[false nil]",
+            rep("(derive :a :b) (underive :a :b) [(isa? :a :b) (parents :a)]").unwrap()
+        );
+        assert!(rep("(derive :a :b) (derive :b :a)").is_err());
+        assert!(rep("(derive \"a\" :b)").is_err());
+        assert!(rep("(derive :a :x) (derive :a :y) (defmulti m identity) \
+             (defmethod m :x [_] 1) (defmethod m :y [_] 2) (m :a)")
+        .is_err());
+    }
+}
@@ -0,0 +1,282 @@
+//! Regular expressions. `(re-pattern s)` compiles a pattern; the other
+//! functions take a pattern or a string to compile as one.
+//!
+//! A match is returned as the matched string when the pattern has no
+//! groups, or else as a vector of the whole match followed by each group,
+//! with nil for groups that didn't take part.
+
+use regex::{Captures, Regex};
+
+use crate::{
+    eval::apply,
+    keyword::Keyword,
+    printer::print_str,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType},
+};
+
+fn compile(pattern: &str) -> Result<Regex, MalError> {
+    Regex::new(pattern).map_err(|err| MalError::Normal(format!("Invalid pattern: {}", err)))
+}
+
+pub fn as_pattern(arg: &MalType) -> Result<Regex, MalError> {
+    match arg {
+        MalType::Pattern(re) => Ok(re.clone()),
+        MalType::Atom(MalAtom::Str(s)) => compile(s),
+        other => Err(MalError::Normal(format!("`{:?}` is not a pattern", other))),
+    }
+}
+
+fn as_str(arg: &MalType) -> Result<&str, MalError> {
+    match arg {
+        MalType::Atom(MalAtom::Str(s)) => Ok(s),
+        other => Err(MalError::Normal(format!("`{:?}` is not a string", other))),
+    }
+}
+
+fn string(s: &str) -> MalType {
+    MalType::Atom(MalAtom::Str(s.to_string()))
+}
+
+fn match_value(caps: &Captures) -> MalType {
+    if caps.len() == 1 {
+        return string(&caps[0]);
+    }
+    MalType::Vector(
+        caps.iter()
+            .map(|group| group.map_or(MalType::Atom(MalAtom::Nil), |m| string(m.as_str())))
+            .collect(),
+    )
+}
+
+fn or_nil(value: Option<MalType>) -> MalType {
+    value.unwrap_or(MalType::Atom(MalAtom::Nil))
+}
+
+fn re_pattern(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Pattern(as_pattern(&args[0])?))
+}
+
+pub fn re_pattern_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("re-pattern"), re_pattern)
+        .with_arglists("([s])")
+        .with_doc("Returns the regex s.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(re-find re s)` returns the first match in `s`, or nil.
+fn re_find(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let re = as_pattern(&args[0])?;
+    Ok(or_nil(
+        re.captures(as_str(&args[1])?)
+            .map(|caps| match_value(&caps)),
+    ))
+}
+
+pub fn re_find_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("re-find"), re_find)
+        .with_arglists("([re s])")
+        .with_doc("Returns the first match of re in s, or nil. A match of a regex with groups is a vector of the whole match and each group.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(re-matches re s)` returns the match if it spans all of `s`, or nil.
+fn re_matches(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let re = compile(&format!("^(?:{})$", as_pattern(&args[0])?.as_str()))?;
+    Ok(or_nil(
+        re.captures(as_str(&args[1])?)
+            .map(|caps| match_value(&caps)),
+    ))
+}
+
+pub fn re_matches_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("re-matches"), re_matches)
+        .with_arglists("([re s])")
+        .with_doc("Returns the match of re if it spans all of s, or nil.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(re-seq re s)` returns a list of every match in `s`, or nil.
+fn re_seq(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let re = as_pattern(&args[0])?;
+    let matches: Vec<MalType> = re
+        .captures_iter(as_str(&args[1])?)
+        .map(|caps| match_value(&caps))
+        .collect();
+    Ok(if matches.is_empty() {
+        MalType::Atom(MalAtom::Nil)
+    } else {
+        MalType::List(matches.into())
+    })
+}
+
+pub fn re_seq_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("re-seq"), re_seq)
+        .with_arglists("([re s])")
+        .with_doc("Returns a list of every match of re in s, or nil if there are none.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(re-groups re s)` returns the named groups of the first match in `s` as
+/// a map from keywords, or nil if there's no match.
+fn re_groups(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let re = as_pattern(&args[0])?;
+    Ok(or_nil(re.captures(as_str(&args[1])?).map(|caps| {
+        MalType::Map(
+            re.capture_names()
+                .flatten()
+                .map(|name| {
+                    (
+                        MalAtom::Keyword(Keyword::new(name.to_string())),
+                        or_nil(caps.name(name).map(|m| string(m.as_str()))),
+                    )
+                })
+                .collect(),
+        )
+    })))
+}
+
+pub fn re_groups_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("re-groups"), re_groups)
+        .with_arglists("([re s])")
+        .with_doc("Returns a map from keywords to the named groups of the first match of re in s, or nil if there is none.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(replace s match replacement)` replaces every occurrence of `match`, a
+/// string or pattern. A pattern's replacement may be a string, which can
+/// refer to groups as `$1` or `$name`, or a function of each match.
+fn replace(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let s = as_str(&args[0])?;
+    let replaced = match (&args[1], &args[2]) {
+        (MalType::Atom(MalAtom::Str(from)), MalType::Atom(MalAtom::Str(to))) => s.replace(from, to),
+        (MalType::Pattern(re), MalType::Atom(MalAtom::Str(to))) => {
+            re.replace_all(s, to.as_str()).into_owned()
+        }
+        (MalType::Pattern(re), f) => {
+            let mut replaced = String::with_capacity(s.len());
+            let mut last = 0;
+            for caps in re.captures_iter(s) {
+                let m = caps.get(0).unwrap();
+                replaced.push_str(&s[last..m.start()]);
+                replaced.push_str(&print_str(&apply(f, vec![match_value(&caps)])?));
+                last = m.end();
+            }
+            replaced.push_str(&s[last..]);
+            replaced
+        }
+        (from, to) => {
+            return Err(MalError::Normal(format!(
+                "Can't replace `{:?}` with `{:?}`",
+                from, to
+            )))
+        }
+    };
+    Ok(MalType::Atom(MalAtom::Str(replaced)))
+}
+
+pub fn replace_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("replace"), replace)
+        .with_arglists("([s match replacement])")
+        .with_doc("Returns s with every match, a string or regex, replaced. For a regex, replacement may refer to groups as $1 or $name, or be a function of each match.")
+        .with_arity(Arity::Fixed(3))
+}
+
+/// `(split s sep)` splits `s` on a string or pattern, dropping trailing
+/// empty strings; `(split s sep limit)` returns at most `limit` parts.
+fn split(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let s = as_str(&args[0])?;
+    let re = match &args[1] {
+        MalType::Atom(MalAtom::Str(sep)) => compile(&regex::escape(sep))?,
+        other => as_pattern(other)?,
+    };
+    let parts: Vec<&str> = match args.get(2) {
+        Some(MalType::Atom(MalAtom::Integer(limit))) if *limit > 0 => {
+            re.splitn(s, *limit as usize).collect()
+        }
+        Some(other) => {
+            return Err(MalError::Normal(format!(
+                "`{:?}` is not a positive limit",
+                other
+            )))
+        }
+        None => {
+            let mut parts: Vec<&str> = re.split(s).collect();
+            while parts.len() > 1 && parts.last() == Some(&"") {
+                parts.pop();
+            }
+            parts
+        }
+    };
+    Ok(MalType::Vector(parts.into_iter().map(string).collect()))
+}
+
+pub fn split_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("split"), split)
+        .with_arglists("([s sep] [s sep limit])")
+        .with_doc("Returns a vector of the parts of s between each sep, a string or regex, without trailing empty strings, and at most limit parts.")
+        .with_arity(Arity::Range(2, 3))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        re_pattern_fn(),
+        re_find_fn(),
+        re_matches_fn(),
+        re_seq_fn(),
+        re_groups_fn(),
+        replace_fn(),
+        split_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn matching() {
+        assert_eq!(
+            "[#\"\\d+\" \"12\" nil [\"a1\" \"a\" \"1\"] [\"b\" \"b\" nil]]",
+            rep("(def! digits (re-pattern \"\\\\d+\")) \
+                 [digits (re-find digits \"ab12c3\") (re-find digits \"abc\") \
+                  (re-find \"([a-z])(\\\\d)\" \"a1\") (re-find \"(b)(\\\\d)?\" \"b\")]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[\"ab\" nil (\"1\" \"22\") nil]",
+            rep("[(re-matches \"a|ab\" \"ab\") (re-matches \"a\" \"ab\") \
+                  (re-seq \"\\\\d+\" \"1 22\") (re-seq \"x\" \"1 22\")]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[true nil]",
+            rep(
+                "[(= (re-groups \"(?P<y>\\\\d{4})-(?P<m>\\\\d+)\" \"on 2021-07\") {:y \"2021\" :m \"07\"}) \
+                  (re-groups \"(?P<y>x)\" \"abc\")]"
+            )
+            .unwrap()
+        );
+        assert!(rep("(re-pattern \"(\")").is_err());
+    }
+
+    #[test]
+    fn replace_and_split() {
+        assert_eq!(
+            "[\"a-b-c\" \"2021/07\" \"<1> <22>\" \"x.y\"]",
+            rep("[(replace \"a b c\" \" \" \"-\") \
+                  (replace \"07-2021\" (re-pattern \"(\\\\d+)-(\\\\d+)\") \"$2/$1\") \
+                  (replace \"1 22\" (re-pattern \"\\\\d+\") (fn* [m] (str \"<\" m \">\"))) \
+                  (replace \"x.y\" \".\" \".\")]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[[\"a\" \"b\" \"c\"] [\"a\" \"b,c\"] [\"a\" \"b\"] [\"\"]]",
+            rep(
+                "[(split \"a1b22c\" (re-pattern \"\\\\d+\")) (split \"a,b,c\" \",\" 2) \
+                  (split \"a.b..\" \".\") (split \"\" \",\")]"
+            )
+            .unwrap()
+        );
+    }
+}
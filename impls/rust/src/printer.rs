@@ -1,64 +1,228 @@
-use crate::types::{MalAtom, MalError, MalFunction, MalType};
-use std::{fmt, result};
+use crate::{
+    atom::Ref,
+    lazy::LazySeq,
+    multi::MultiFn,
+    record::Record,
+    transient::Transient,
+    types::{Arity, MalAtom, MalError, MalFunction, MalLambda, MalType, Method, Protocol},
+};
+use std::{cell::Cell, fmt, result};
+
+fn escape_str(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn print_atom(atom: &MalAtom, print_readably: bool) -> String {
+    match atom {
+        MalAtom::Symbol(s) => s.to_string(),
+        MalAtom::Keyword(k) => k.to_string(),
+        MalAtom::Integer(i) => i.to_string(),
+        MalAtom::Str(s) if print_readably => format!("\"{}\"", escape_str(s)),
+        MalAtom::Str(s) => s.clone(),
+        MalAtom::Boolean(b) => b.to_string(),
+        MalAtom::Nil => String::from("nil"),
+    }
+}
+
+fn print_seq<'a>(seq: impl IntoIterator<Item = &'a MalType>, print_readably: bool) -> String {
+    seq.into_iter()
+        .map(|i| print_form(i, print_readably))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+thread_local! {
+    static PRINT_LENGTH: Cell<Option<usize>> = const { Cell::new(Some(100)) };
+    static PRINT_READABLY: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Limits how many elements of a lazy seq are printed, so printing an
+/// infinite one ends. `None` prints every element. Other values are finite,
+/// so are always printed in full and can be read back.
+pub fn set_print_length(length: Option<usize>) {
+    PRINT_LENGTH.with(|limit| limit.set(length));
+}
+
+/// How many elements of a lazy seq are printed, or `None` for all of them.
+pub fn print_length() -> Option<usize> {
+    PRINT_LENGTH.with(Cell::get)
+}
+
+/// Whether `pr_str` escapes strings so they can be read back. Turning it
+/// off makes it print them as `print_str` does.
+pub fn set_print_readably(readably: bool) {
+    PRINT_READABLY.with(|flag| flag.set(readably));
+}
+
+/// Prints the elements of `seq` up to the print length, then `...` if there
+/// are more. An element that fails to realise is printed as its error.
+fn print_lazy_seq(seq: &LazySeq, print_readably: bool) -> String {
+    let limit = print_length().unwrap_or(usize::MAX);
+    let mut printed = Vec::new();
+    for item in seq.iter() {
+        if printed.len() == limit {
+            printed.push(String::from("..."));
+            break;
+        }
+        match item {
+            Ok(item) => printed.push(print_form(&item, print_readably)),
+            Err(err) => {
+                printed.push(format!("#<error {}>", err));
+                break;
+            }
+        }
+    }
+    printed.join(" ")
+}
+
+fn print_form(form: &MalType, print_readably: bool) -> String {
+    match form {
+        MalType::Atom(v) => print_atom(v, print_readably),
+        MalType::List(l) => format!("({})", print_seq(l, print_readably)),
+        MalType::Vector(v) => format!("[{}]", print_seq(v, print_readably)),
+        MalType::Map(m) => format!("{{{}}}", print_entries(m, print_readably)),
+        MalType::Function(func) => format!("[function {}]", func.name),
+        MalType::Lambda(_) => String::from("[function]"),
+        MalType::MultiFn(multi) => format!("[multimethod {}]", multi.name),
+        MalType::Protocol(protocol) => format!("[protocol {}]", protocol.name),
+        MalType::Ref(r) => format!("(atom {})", print_form(&r.get(), print_readably)),
+        MalType::Pattern(re) => format!("#\"{}\"", re.as_str()),
+        MalType::LazySeq(seq) => format!("({})", print_lazy_seq(seq, print_readably)),
+        MalType::Transient(_) => String::from("#<transient>"),
+        MalType::Record(r) => format!(
+            "#{}{{{}}}",
+            r.record_type.name,
+            print_entries(
+                r.ordered_entries().iter().map(|(k, v)| (k, v)),
+                print_readably
+            )
+        ),
+    }
+}
+
+fn print_entries<'a>(
+    entries: impl IntoIterator<Item = (&'a MalAtom, &'a MalType)>,
+    print_readably: bool,
+) -> String {
+    entries
+        .into_iter()
+        .map(|(k, v)| {
+            format!(
+                "{} {}",
+                print_atom(k, print_readably),
+                print_form(v, print_readably)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
 
 impl fmt::Display for MalAtom {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            MalAtom::Symbol(s) => write!(f, "{}", s),
-            MalAtom::Keyword(k) => write!(f, "{}", k),
-            MalAtom::Integer(i) => write!(f, "{}", i),
-            MalAtom::Str(s) => write!(f, "{}", s),
-            MalAtom::Boolean(b) => write!(f, "{}", b),
-            MalAtom::Nil => write!(f, "nil"),
-        }
+        write!(f, "{}", print_atom(self, true))
     }
 }
 
 impl fmt::Display for MalType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            MalType::Atom(v) => write!(f, "{}", v),
-            MalType::List(l) => write!(
-                f,
-                "({})",
-                l.iter()
-                    .map(|i| i.to_string())
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            ),
-            MalType::Vector(v) => write!(
-                f,
-                "[{}]",
-                v.iter()
-                    .map(|i| i.to_string())
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            ),
-            MalType::Map(m) => write!(
-                f,
-                "{{{}}}",
-                m.iter()
-                    .map(|(k, v)| { format!("{} {}", k.to_string(), v.to_string()) })
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            ),
-            MalType::Function(func) => write!(f, "[function {}]", func.name,),
-        }
+        write!(f, "{}", print_form(self, true))
     }
 }
 
+/// Prints `code` so that reading the result gives back an equal value,
+/// unless printing readably has been turned off.
 pub fn pr_str(code: &MalType) -> String {
-    code.to_string()
+    print_form(code, PRINT_READABLY.with(Cell::get))
+}
+
+/// Prints `code` for humans, as `str` and `println` do: strings are written
+/// without quotes or escapes.
+pub fn print_str(code: &MalType) -> String {
+    print_form(code, false)
 }
 
 impl fmt::Debug for MalFunction {
     fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
         f.debug_struct("Function")
             .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl fmt::Debug for MalLambda {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("Lambda")
+            .field("name", &self.name)
+            .field("methods", &self.methods)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("Method")
+            .field("params", &self.params)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+impl fmt::Debug for MultiFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("MultiFn").field("name", &self.name).finish()
+    }
+}
+
+impl fmt::Debug for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("Protocol")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl fmt::Debug for LazySeq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("LazySeq")
+            .field("realized", &self.is_realized())
             .finish()
     }
 }
 
+impl fmt::Debug for Ref {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_tuple("Ref").field(&self.get()).finish()
+    }
+}
+
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("Record")
+            .field("type", &self.record_type.name)
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Transient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.write_str("Transient")
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Fixed(n) => write!(f, "{}", n),
+            Arity::Range(min, max) => write!(f, "{} to {}", min, max),
+            Arity::Variadic(min) => write!(f, "{} or more", min),
+        }
+    }
+}
+
 impl fmt::Display for MalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -1,21 +1,60 @@
+use std::{fmt, rc::Rc};
+
 use crate::{
     keyword::Keyword,
+    list::List,
     symbol::Symbol,
-    types::{MalAtom, MalError, MalType},
+    types::{MalAtom, MalError, MalType, Map},
 };
 use pest::{error, iterators::Pair, Parser};
-use std::collections::HashMap;
 
 pub static MAL_HISTORY: &str = ".mal-history";
 
+/// Where a list was read from: the file, if it was read from one, and the
+/// line and column of its opening paren.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 #[derive(Parser)]
 #[grammar = "parser/grammar.pest"]
 pub struct MalParser;
 
+fn unescape_str(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                Some('r') => unescaped.push('\r'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push(c),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
 fn parse_atom(pair: Pair<Rule>) -> MalAtom {
     match pair.as_rule() {
         Rule::number => MalAtom::Integer(pair.as_str().parse().unwrap()),
-        Rule::string => MalAtom::Str(pair.as_str().to_string()),
+        Rule::string => MalAtom::Str(unescape_str(pair.as_str())),
         Rule::symbol => MalAtom::Symbol(Symbol::new(pair.as_str())),
         Rule::keyword => MalAtom::Keyword(Keyword::new(
             pair.as_str().strip_prefix(':').unwrap().to_string(),
@@ -26,25 +65,70 @@ fn parse_atom(pair: Pair<Rule>) -> MalAtom {
     }
 }
 
-fn parse_value(pair: Pair<Rule>) -> Option<MalType> {
+fn parse_value(pair: Pair<Rule>, file: Option<&Rc<str>>) -> Option<MalType> {
     match pair.as_rule() {
-        Rule::list => Some(MalType::List(
-            pair.into_inner().filter_map(parse_value).collect(),
-        )),
+        Rule::list => {
+            let (line, column) = pair.as_span().start_pos().line_col();
+            let location = Location {
+                file: file.cloned(),
+                line,
+                column,
+            };
+            Some(MalType::List(
+                pair.into_inner()
+                    .filter_map(|p| parse_value(p, file))
+                    .collect::<List>()
+                    .with_location(location),
+            ))
+        }
         Rule::vector => Some(MalType::Vector(
-            pair.into_inner().filter_map(parse_value).collect(),
+            pair.into_inner()
+                .filter_map(|p| parse_value(p, file))
+                .collect(),
         )),
         Rule::map => Some(MalType::Map({
-            let mut hm = HashMap::new();
+            let mut hm = Map::new();
             for p in pair.into_inner().collect::<Vec<Pair<Rule>>>().chunks(2) {
                 let k = parse_atom(p[0].clone());
-                let v = parse_value(p[1].clone());
+                let v = parse_value(p[1].clone(), file);
                 if let Some(v) = v {
                     hm.insert(k, v);
                 }
             }
-            hm
+            hm.into()
         })),
+        Rule::quote | Rule::quasiquote | Rule::unquote | Rule::splice_unquote | Rule::deref => {
+            let name = match pair.as_rule() {
+                Rule::quote => "quote",
+                Rule::quasiquote => "quasiquote",
+                Rule::unquote => "unquote",
+                Rule::splice_unquote => "splice-unquote",
+                _ => "deref",
+            };
+            Some(MalType::List(
+                std::iter::once(MalType::Atom(MalAtom::Symbol(Symbol::new(name))))
+                    .chain(pair.into_inner().filter_map(|p| parse_value(p, file)))
+                    .collect(),
+            ))
+        }
+        Rule::with_meta => {
+            let mut inner = pair.into_inner().filter_map(|p| parse_value(p, file));
+            // `^:a x` is short for `^{:a true} x`.
+            let meta = match inner.next().unwrap() {
+                MalType::Atom(key @ MalAtom::Keyword(_)) => MalType::Map(
+                    std::iter::once((key, MalType::Atom(MalAtom::Boolean(true)))).collect(),
+                ),
+                meta => meta,
+            };
+            Some(MalType::List(
+                vec![
+                    MalType::Atom(MalAtom::Symbol(Symbol::new("with-meta"))),
+                    inner.next().unwrap(),
+                    meta,
+                ]
+                .into(),
+            ))
+        }
         Rule::number | Rule::string | Rule::symbol | Rule::keyword | Rule::boolean | Rule::nil => {
             Some(MalType::Atom(parse_atom(pair)))
         }
@@ -58,8 +142,8 @@ fn parse_error(err: error::Error<Rule>) -> MalError {
         "{}{}",
         if (err_str.matches('(').count() != err_str.matches(')').count())
             || (err_str.matches('[').count() != err_str.matches(']').count())
-            || ((err_str.matches('"').count() % 2) != 0)
-            || ((err_str.matches('\\').count() % 2) != 0)
+            || !err_str.matches('"').count().is_multiple_of(2)
+            || !err_str.matches('\\').count().is_multiple_of(2)
         {
             " (EOF)"
         } else {
@@ -70,13 +154,23 @@ fn parse_error(err: error::Error<Rule>) -> MalError {
 }
 
 pub fn read_str(input: &str) -> Result<Vec<MalType>, MalError> {
+    read_forms(input, None)
+}
+
+/// Reads the source of the file at `path`, so the lists read know where
+/// they came from.
+pub fn read_file_str(input: &str, path: &str) -> Result<Vec<MalType>, MalError> {
+    read_forms(input, Some(&Rc::from(path)))
+}
+
+fn read_forms(input: &str, file: Option<&Rc<str>>) -> Result<Vec<MalType>, MalError> {
     match MalParser::parse(Rule::values, input) {
         Ok(pairs) => Ok(pairs
             .filter_map(|p| {
                 if p.as_rule() == Rule::EOI {
                     None
                 } else {
-                    parse_value(p)
+                    parse_value(p, file)
                 }
             })
             .collect()),
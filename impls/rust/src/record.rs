@@ -0,0 +1,206 @@
+//! Records, maps with a type of their own for protocols to dispatch on.
+//! `(defrecord Point [x y])` defines the type `Point`, whose value is the
+//! keyword `find-type` returns for its records, and the constructors
+//! `(->Point 1 2)` and `(map->Point {:x 1 :y 2})`.
+//!
+//! A record behaves as a map of its fields: `get`, `assoc`, `keys`, `count`,
+//! destructuring and the rest all work on it, and `assoc` keeps its type.
+//! Dissociating one of its fields leaves a plain map. It prints as
+//! `#Point{:x 1 :y 2}`, its fields in the order they were declared, and is
+//! only equal to a record of the same type with the same entries.
+
+use std::rc::Rc;
+
+use crate::{
+    keyword::Keyword,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType, Map},
+};
+
+/// A type defined by `defrecord`.
+pub struct RecordType {
+    pub name: Symbol,
+    /// The declared fields, in order.
+    pub fields: Vec<Keyword>,
+}
+
+impl RecordType {
+    /// The keyword naming the type, which protocols dispatch on.
+    pub fn keyword(&self) -> Keyword {
+        Keyword::new(self.name.get_name())
+    }
+
+    pub fn has_field(&self, key: &MalAtom) -> bool {
+        match key {
+            MalAtom::Keyword(k) => self.fields.contains(k),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Record {
+    pub record_type: Rc<RecordType>,
+    pub entries: Map,
+}
+
+impl Record {
+    pub fn new(record_type: Rc<RecordType>, entries: Map) -> Self {
+        Record {
+            record_type,
+            entries,
+        }
+    }
+
+    /// The declared fields in order, then any other entries.
+    pub fn ordered_entries(&self) -> Vec<(MalAtom, MalType)> {
+        let fields = self
+            .record_type
+            .fields
+            .iter()
+            .map(|field| MalAtom::Keyword(field.clone()));
+        let extra = self
+            .entries
+            .keys()
+            .filter(|key| !self.record_type.has_field(key))
+            .cloned();
+        fields
+            .chain(extra)
+            .filter_map(|key| self.entries.get(&key).cloned().map(|value| (key, value)))
+            .collect()
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        self.record_type.name == other.record_type.name && self.entries == other.entries
+    }
+}
+
+/// The constructor `->Name`, taking a value for each field in order.
+pub fn positional_constructor(record_type: &Rc<RecordType>) -> MalFunction {
+    let record_type = record_type.clone();
+    let name = Symbol::new(&format!("->{}", record_type.name));
+    let arity = Arity::Fixed(record_type.fields.len());
+    MalFunction::new(name, move |args| {
+        let entries = record_type
+            .fields
+            .iter()
+            .map(|field| MalAtom::Keyword(field.clone()))
+            .zip(args)
+            .collect();
+        Ok(MalType::Record(Record::new(record_type.clone(), entries)))
+    })
+    .with_arity(arity)
+}
+
+/// The constructor `map->Name`, taking a map of the fields. Missing fields
+/// are nil and extra keys are kept.
+pub fn map_constructor(record_type: &Rc<RecordType>) -> MalFunction {
+    let record_type = record_type.clone();
+    let name = Symbol::new(&format!("map->{}", record_type.name));
+    MalFunction::new(name.clone(), move |args| {
+        let mut entries: Map = record_type
+            .fields
+            .iter()
+            .map(|field| (MalAtom::Keyword(field.clone()), MalType::Atom(MalAtom::Nil)))
+            .collect();
+        match &args[0] {
+            MalType::Map(m) => entries.extend(m.clone()),
+            MalType::Record(r) => entries.extend(r.entries.clone()),
+            other => {
+                return Err(MalError::Normal(format!(
+                    "{} needs a map. Given {}",
+                    name, other
+                )))
+            }
+        }
+        Ok(MalType::Record(Record::new(record_type.clone(), entries)))
+    })
+    .with_arity(Arity::Fixed(1))
+}
+
+fn is_record(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Boolean(matches!(
+        args[0],
+        MalType::Record(_)
+    ))))
+}
+
+pub fn is_record_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("record?"), is_record)
+        .with_arglists("([x])")
+        .with_doc("Returns true if x is a record.")
+        .with_arity(Arity::Fixed(1))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![is_record_fn()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn records() {
+        assert_eq!(
+            "[#Point{:x 1 :y 2} #Point{:x 1 :y nil :z 3} :Point true true false]",
+            rep("(defrecord Point [x y]) \
+                 [(->Point 1 2) (map->Point {:x 1 :z 3}) (find-type (->Point 1 2)) \
+                  (record? (->Point 1 2)) (= (->Point 1 2) (map->Point {:x 1 :y 2})) \
+                  (= (->Point 1 2) {:x 1 :y 2})]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[1 true 2 #Point{:x 5 :y 2 :z 3} {:y 2} (1 2) 3 true]",
+            rep("(defrecord Point [x y]) \
+                 (def! p (->Point 1 2)) \
+                 [(get p :x) (contains? p :y) (count p) (assoc p :x 5 :z 3) (dissoc p :x) \
+                  (let* [{:keys [x y]} p] (list x y)) (get (update p :y inc) :y) (map? p)]")
+            .unwrap()
+        );
+        assert!(rep("(defrecord Point [x y]) (->Point 1)").is_err());
+        assert!(rep("(defrecord Point [x 1])").is_err());
+    }
+
+    #[test]
+    fn protocols() {
+        assert_eq!(
+            "[\"circle 2\" 12 \"list\" 3 \":mal/vector\" true false]",
+            rep("(defprotocol Shape [area [this]] [describe [this]]) \
+                 (defrecord Circle [r] \
+                   Shape \
+                   (area [_] (* 3 r r)) \
+                   (describe [this] (str \"circle \" (get this :r)))) \
+                 (defrecord Square [side]) \
+                 (extend-type Square Shape (area [s] (* (get s :side) (get s :side)))) \
+                 (extend-protocol Shape \
+                   :mal/list (describe [_] \"list\") (area [l] (count l)) \
+                   :mal/vector (describe [v] (str (find-type v)))) \
+                 [(describe (->Circle 2)) (area (->Circle 2)) (describe (list 1)) \
+                  (area (list 1 2 3)) (describe []) \
+                  (satisfies? Shape (->Square 1)) (satisfies? Shape {})]")
+            .unwrap()
+        );
+        assert_eq!(
+            "No implementation of method describe of protocol Shape for type :Square",
+            rep("(defprotocol Shape [area [this]] [describe [this]]) \
+                 (defrecord Square [side]) \
+                 (extend-type Square Shape (area [s] 1)) \
+                 (describe (->Square 1))")
+            .unwrap_err()
+            .to_string()
+        );
+        assert!(rep("(extend-type :mal/list 1 (f [x] x))").is_err());
+        assert_eq!(
+            "[true false]",
+            rep("(defprotocol Named [name-of [this]]) \
+                 (extend-type :mal/vector Named (name-of [_] \"vector\")) \
+                 (def! before (satisfies? Named [])) \
+                 (defprotocol Named [name-of [this]]) \
+                 [before (satisfies? Named [])]")
+            .unwrap()
+        );
+    }
+}
@@ -0,0 +1,988 @@
+//! The sequence library. Functions taking a collection accept anything
+//! `lazy::iter` does, so lists, vectors, maps, strings, nil and lazy seqs all
+//! behave as sequences. As in Clojure, `map`, `filter`, `take` and the like
+//! return lazy seqs, realising their input only as far as they're used; the
+//! rest return lists. The associative functions (`get`, `assoc` and those
+//! built on them) work on maps and, by index, vectors, and edit their result
+//! in place as transients do rather than copying it once per key.
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use crate::{
+    built_ins::as_seq,
+    convert::FromMal,
+    eval::apply,
+    lazy::{self, LazySeq, SeqIter},
+    record::Record,
+    symbol::Symbol,
+    transient::Editable,
+    types::{
+        by_value, Arity, ByValue, List, MalAtom, MalError, MalFunction, MalType, Map, WithMeta,
+    },
+};
+
+fn nil() -> MalType {
+    MalType::Atom(MalAtom::Nil)
+}
+
+fn lazy_seq<I>(iter: I) -> MalType
+where
+    I: Iterator<Item = Result<MalType, MalError>> + 'static,
+{
+    MalType::LazySeq(LazySeq::from_iter(iter))
+}
+
+fn truthy(value: Result<MalType, MalError>) -> Result<bool, MalError> {
+    value.map(|value| value.is_truthy())
+}
+
+pub fn map_key(key: &MalType) -> Result<MalAtom, MalError> {
+    match key {
+        MalType::Atom(key) => Ok(key.clone()),
+        other => Err(MalError::Normal(format!(
+            "`{:?}` can't be a map key",
+            other
+        ))),
+    }
+}
+
+/// A count argument, where negative counts are treated as zero.
+fn count_arg(arg: &MalType) -> Result<usize, MalError> {
+    Ok(isize::from_mal(arg.clone())?.max(0) as usize)
+}
+
+fn positive_arg(name: &str, arg: &MalType) -> Result<usize, MalError> {
+    match isize::from_mal(arg.clone())? {
+        n if n > 0 => Ok(n as usize),
+        n => Err(MalError::Normal(format!(
+            "{} needs a positive size, not {}",
+            name, n
+        ))),
+    }
+}
+
+/// Lazily yields an element of each of `colls` in turn: `[a0 b0]`,
+/// `[a1 b1]`... until the shortest runs out.
+fn zip(
+    colls: &[MalType],
+) -> Result<impl Iterator<Item = Result<Vec<MalType>, MalError>>, MalError> {
+    let mut iters = colls
+        .iter()
+        .map(lazy::iter)
+        .collect::<Result<Vec<SeqIter>, _>>()?;
+    Ok(std::iter::from_fn(move || {
+        iters
+            .iter_mut()
+            .map(Iterator::next)
+            .collect::<Option<Result<Vec<_>, _>>>()
+    }))
+}
+
+/// `(map f xs ys...)` calls `f` with an element of each collection.
+fn map(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let f = args[0].clone();
+    Ok(lazy_seq(zip(&args[1..])?.map(move |xs| apply(&f, xs?))))
+}
+
+pub fn map_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("map"), map)
+        .with_arglists("([f coll & colls])")
+        .with_doc("Returns a lazy seq of f called with an element of each coll in turn, until the shortest runs out.")
+        .with_arity(Arity::Variadic(2))
+}
+
+/// `(mapcat f xs ys...)` concatenates the sequences `map` would return.
+fn mapcat(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let f = args[0].clone();
+    let mut results = zip(&args[1..])?.map(move |xs| lazy::iter(&apply(&f, xs?)?));
+    let mut current: Option<SeqIter> = None;
+    Ok(lazy_seq(std::iter::from_fn(move || loop {
+        if let Some(item) = current.as_mut().and_then(Iterator::next) {
+            return Some(item);
+        }
+        match results.next()? {
+            Ok(iter) => current = Some(iter),
+            Err(err) => return Some(Err(err)),
+        }
+    })))
+}
+
+pub fn mapcat_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("mapcat"), mapcat)
+        .with_arglists("([f coll & colls])")
+        .with_doc("Returns a lazy seq concatenating the results of map.")
+        .with_arity(Arity::Variadic(2))
+}
+
+fn filter_by(args: &[MalType], keep: bool) -> Result<MalType, MalError> {
+    let pred = args[0].clone();
+    Ok(lazy_seq(lazy::iter(&args[1])?.filter_map(move |x| {
+        let x = match x {
+            Ok(x) => x,
+            err => return Some(err),
+        };
+        match truthy(apply(&pred, vec![x.clone()])) {
+            Ok(matched) if matched == keep => Some(Ok(x)),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        }
+    })))
+}
+
+/// `(filter pred xs)` keeps the elements `pred` is truthy for.
+fn filter(args: Vec<MalType>) -> Result<MalType, MalError> {
+    filter_by(&args, true)
+}
+
+pub fn filter_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("filter"), filter)
+        .with_arglists("([pred coll])")
+        .with_doc("Returns a lazy seq of the elements of coll pred is truthy for.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(remove pred xs)` drops the elements `pred` is truthy for.
+fn remove(args: Vec<MalType>) -> Result<MalType, MalError> {
+    filter_by(&args, false)
+}
+
+pub fn remove_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("remove"), remove)
+        .with_arglists("([pred coll])")
+        .with_doc("Returns a lazy seq of the elements of coll pred isn't truthy for.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(range)` counts up from 0 forever. `(range end)`, `(range start end)`
+/// and `(range start end step)` stop before `end`.
+fn range(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let numbers = args
+        .into_iter()
+        .map(isize::from_mal)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (start, end, step) = match numbers.as_slice() {
+        [] => (0, isize::MAX, 1),
+        [end] => (0, *end, 1),
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (*start, *end, *step),
+        _ => unreachable!(),
+    };
+    if step == 0 {
+        return Err(MalError::Normal("range step can't be 0".to_string()));
+    }
+    let numbers = std::iter::successors(Some(start), move |n| n.checked_add(step))
+        .take_while(move |n| (step > 0 && *n < end) || (step < 0 && *n > end));
+    Ok(lazy_seq(
+        numbers.map(|n| Ok(MalType::Atom(MalAtom::Integer(n)))),
+    ))
+}
+
+pub fn range_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("range"), range)
+        .with_arglists("([] [end] [start end] [start end step])")
+        .with_doc("Returns a lazy seq of numbers from start, or 0, up to but not including end, by step, or 1. With no end it never ends.")
+        .with_arity(Arity::Range(0, 3))
+}
+
+fn take(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let n = count_arg(&args[0])?;
+    Ok(lazy_seq(lazy::iter(&args[1])?.take(n)))
+}
+
+pub fn take_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("take"), take)
+        .with_arglists("([n coll])")
+        .with_doc("Returns a lazy seq of the first n elements of coll.")
+        .with_arity(Arity::Fixed(2))
+}
+
+fn drop(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let n = count_arg(&args[0])?;
+    let mut xs = lazy::iter(&args[1])?;
+    let mut skipped = false;
+    Ok(lazy_seq(std::iter::from_fn(move || {
+        if !skipped {
+            skipped = true;
+            for _ in 0..n {
+                if let Err(err) = xs.next()? {
+                    return Some(Err(err));
+                }
+            }
+        }
+        xs.next()
+    })))
+}
+
+pub fn drop_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("drop"), drop)
+        .with_arglists("([n coll])")
+        .with_doc("Returns a lazy seq of the elements of coll after the first n.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// The elements of `xs` while `pred` is truthy for them.
+fn take_while(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let pred = args[0].clone();
+    let mut xs = lazy::iter(&args[1])?;
+    let mut done = false;
+    Ok(lazy_seq(std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let x = match xs.next()? {
+            Ok(x) => x,
+            err => return Some(err),
+        };
+        match truthy(apply(&pred, vec![x.clone()])) {
+            Ok(true) => Some(Ok(x)),
+            Ok(false) => {
+                done = true;
+                None
+            }
+            Err(err) => Some(Err(err)),
+        }
+    })))
+}
+
+pub fn take_while_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("take-while"), take_while)
+        .with_arglists("([pred coll])")
+        .with_doc("Returns a lazy seq of the elements of coll while pred is truthy for them.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// The elements of `xs` from the first `pred` isn't truthy for.
+fn drop_while(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let pred = args[0].clone();
+    let mut xs = lazy::iter(&args[1])?;
+    let mut dropping = true;
+    Ok(lazy_seq(std::iter::from_fn(move || loop {
+        let x = match xs.next()? {
+            Ok(x) => x,
+            err => return Some(err),
+        };
+        if !dropping {
+            return Some(Ok(x));
+        }
+        match truthy(apply(&pred, vec![x.clone()])) {
+            Ok(true) => {}
+            Ok(false) => {
+                dropping = false;
+                return Some(Ok(x));
+            }
+            Err(err) => return Some(Err(err)),
+        }
+    })))
+}
+
+pub fn drop_while_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("drop-while"), drop_while)
+        .with_arglists("([pred coll])")
+        .with_doc(
+            "Returns a lazy seq of the elements of coll from the first pred isn't truthy for.",
+        )
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(partition n xs)` lazily splits `xs` into lists of `n`, dropping any
+/// shorter list left at the end; `(partition n step xs)` starts each list
+/// `step` elements after the last.
+fn partition(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let n = positive_arg("partition", &args[0])?;
+    let (step, xs) = match args.as_slice() {
+        [_, xs] => (n, xs),
+        [_, step, xs] => (positive_arg("partition", step)?, xs),
+        _ => unreachable!(),
+    };
+    let mut xs = lazy::iter(xs)?;
+    let mut window: Vec<MalType> = Vec::with_capacity(n);
+    let mut skip = 0;
+    Ok(lazy_seq(std::iter::from_fn(move || {
+        while window.len() < n {
+            match xs.next()? {
+                Err(err) => return Some(Err(err)),
+                Ok(_) if skip > 0 => skip -= 1,
+                Ok(x) => window.push(x),
+            }
+        }
+        let part = MalType::List(window.iter().cloned().collect());
+        if step < n {
+            window.drain(..step);
+        } else {
+            window.clear();
+            skip = step - n;
+        }
+        Some(Ok(part))
+    })))
+}
+
+pub fn partition_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("partition"), partition)
+        .with_arglists("([n coll] [n step coll])")
+        .with_doc("Returns a lazy seq of lists of n elements, each starting step, or n, elements after the last. A shorter list left at the end is dropped.")
+        .with_arity(Arity::Range(2, 3))
+}
+
+/// `(group-by f xs)` maps each result of `f` to a vector of the elements
+/// that returned it, in order.
+fn group_by(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut groups: HashMap<MalAtom, Vec<MalType>> = HashMap::new();
+    for x in as_seq(&args[1])? {
+        let key = map_key(&apply(&args[0], vec![x.clone()])?)?;
+        groups.entry(key).or_default().push(x);
+    }
+    Ok(MalType::Map(
+        groups
+            .into_iter()
+            .map(|(key, group)| (key, MalType::Vector(group.into())))
+            .collect(),
+    ))
+}
+
+pub fn group_by_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("group-by"), group_by)
+        .with_arglists("([f coll])")
+        .with_doc("Returns a map from each result of f to a vector of the elements of coll that returned it.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(frequencies xs)` maps each distinct element to how often it occurs.
+fn frequencies(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut counts: HashMap<MalAtom, isize> = HashMap::new();
+    for x in as_seq(&args[0])? {
+        *counts.entry(map_key(&x)?).or_insert(0) += 1;
+    }
+    Ok(MalType::Map(
+        counts
+            .into_iter()
+            .map(|(x, n)| (x, MalType::Atom(MalAtom::Integer(n))))
+            .collect(),
+    ))
+}
+
+pub fn frequencies_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("frequencies"), frequencies)
+        .with_arglists("([coll])")
+        .with_doc("Returns a map from each distinct element of coll to how often it occurs.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// Orders values of the same type: numbers, strings, keywords, symbols and
+/// booleans, with nil before everything, and sequences element by element.
+pub fn compare(a: &MalType, b: &MalType) -> Result<Ordering, MalError> {
+    use MalAtom::*;
+    match (a, b) {
+        (MalType::Atom(Nil), MalType::Atom(Nil)) => Ok(Ordering::Equal),
+        (MalType::Atom(Nil), _) => Ok(Ordering::Less),
+        (_, MalType::Atom(Nil)) => Ok(Ordering::Greater),
+        (MalType::Atom(Integer(a)), MalType::Atom(Integer(b))) => Ok(a.cmp(b)),
+        (MalType::Atom(Str(a)), MalType::Atom(Str(b))) => Ok(a.cmp(b)),
+        (MalType::Atom(Keyword(a)), MalType::Atom(Keyword(b))) => Ok(a.name.cmp(&b.name)),
+        (MalType::Atom(Symbol(a)), MalType::Atom(Symbol(b))) => Ok(a.name.cmp(&b.name)),
+        (MalType::Atom(Boolean(a)), MalType::Atom(Boolean(b))) => Ok(a.cmp(b)),
+        (a, b) => match (a.items(), b.items()) {
+            (Some(mut xs), Some(mut ys)) => loop {
+                match (xs.next(), ys.next()) {
+                    (Some(x), Some(y)) => match compare(x, y)? {
+                        Ordering::Equal => {}
+                        unequal => return Ok(unequal),
+                    },
+                    (x, y) => return Ok(x.is_some().cmp(&y.is_some())),
+                }
+            },
+            _ => Err(MalError::Normal(format!(
+                "Can't compare `{:?}` with `{:?}`",
+                a, b
+            ))),
+        },
+    }
+}
+
+/// Stably sorts `xs` by their `keys`, the first error comparing them ending
+/// the sort.
+fn sort_by_keys(xs: Vec<MalType>, keys: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut keyed: Vec<(MalType, MalType)> = keys.into_iter().zip(xs).collect();
+    let mut error = None;
+    keyed.sort_by(|(a, _), (b, _)| {
+        if error.is_some() {
+            return Ordering::Equal;
+        }
+        compare(a, b).unwrap_or_else(|err| {
+            error = Some(err);
+            Ordering::Equal
+        })
+    });
+    match error {
+        Some(err) => Err(err),
+        None => Ok(MalType::List(keyed.into_iter().map(|(_, x)| x).collect())),
+    }
+}
+
+fn sort(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let xs = as_seq(&args[0])?;
+    sort_by_keys(xs.clone(), xs)
+}
+
+pub fn sort_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("sort"), sort)
+        .with_arglists("([coll])")
+        .with_doc("Returns a list of the elements of coll in order.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(sort-by f xs)` sorts `xs` by what `f` returns for each.
+fn sort_by(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let xs = as_seq(&args[1])?;
+    let keys = xs
+        .iter()
+        .map(|x| apply(&args[0], vec![x.clone()]))
+        .collect::<Result<_, _>>()?;
+    sort_by_keys(xs, keys)
+}
+
+pub fn sort_by_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("sort-by"), sort_by)
+        .with_arglists("([f coll])")
+        .with_doc("Returns a list of the elements of coll ordered by what f returns for each.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(interleave xs ys...)` takes an element of each in turn, until the
+/// shortest runs out.
+fn interleave(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut rows = zip(&args)?;
+    let mut row = Vec::new().into_iter();
+    Ok(lazy_seq(std::iter::from_fn(move || loop {
+        if let Some(x) = row.next() {
+            return Some(Ok(x));
+        }
+        match rows.next()? {
+            Ok(next) => row = next.into_iter(),
+            Err(err) => return Some(Err(err)),
+        }
+    })))
+}
+
+pub fn interleave_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("interleave"), interleave)
+        .with_arglists("([coll & colls])")
+        .with_doc(
+            "Returns a lazy seq of an element of each coll in turn, until the shortest runs out.",
+        )
+        .with_arity(Arity::Variadic(1))
+}
+
+/// `(distinct xs)` lazily keeps the first of each equal element. Functions
+/// and the like, equal to nothing, are always kept.
+fn distinct(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut seen = HashSet::new();
+    Ok(lazy_seq(lazy::iter(&args[0])?.filter(move |x| match x {
+        Ok(x) if by_value(x) => seen.insert(ByValue(x.clone())),
+        _ => true,
+    })))
+}
+
+pub fn distinct_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("distinct"), distinct)
+        .with_arglists("([coll])")
+        .with_doc("Returns a lazy seq of the elements of coll without repeats of equal ones.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(zipmap ks vs)` maps each of `ks` to the element of `vs` at its index.
+fn zipmap(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut map = Editable::Map(Map::new());
+    for kv in zip(&args)? {
+        let mut kv = kv?.into_iter();
+        map.assoc(&kv.next().unwrap(), kv.next().unwrap())?;
+    }
+    Ok(map.into_value())
+}
+
+pub fn zipmap_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("zipmap"), zipmap)
+        .with_arglists("([keys vals])")
+        .with_doc("Returns a map from each of keys to the element of vals in the same place.")
+        .with_arity(Arity::Fixed(2))
+}
+
+fn reverse(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::List(as_seq(&args[0])?.into_iter().rev().collect()))
+}
+
+pub fn reverse_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("reverse"), reverse)
+        .with_arglists("([coll])")
+        .with_doc("Returns a list of the elements of coll in reverse order.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(some pred xs)` returns the first truthy result of `pred`, or nil.
+fn some(args: Vec<MalType>) -> Result<MalType, MalError> {
+    for x in lazy::iter(&args[1])? {
+        let result = apply(&args[0], vec![x?])?;
+        if result.is_truthy() {
+            return Ok(result);
+        }
+    }
+    Ok(nil())
+}
+
+pub fn some_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("some"), some)
+        .with_arglists("([pred coll])")
+        .with_doc("Returns the first truthy result of pred for the elements of coll, or nil if there is none.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `(every? pred xs)` stops at the first element `pred` isn't truthy for.
+fn every(args: Vec<MalType>) -> Result<MalType, MalError> {
+    for x in lazy::iter(&args[1])? {
+        if !truthy(apply(&args[0], vec![x?]))? {
+            return Ok(MalType::Atom(MalAtom::Boolean(false)));
+        }
+    }
+    Ok(MalType::Atom(MalAtom::Boolean(true)))
+}
+
+pub fn every_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("every?"), every)
+        .with_arglists("([pred coll])")
+        .with_doc("Returns true if pred is truthy for every element of coll.")
+        .with_arity(Arity::Fixed(2))
+}
+
+fn last(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(as_seq(&args[0])?.pop().unwrap_or_else(nil))
+}
+
+pub fn last_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("last"), last)
+        .with_arglists("([coll])")
+        .with_doc("Returns the last element of coll, or nil if it's empty.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(butlast xs)` returns all but the last element, or nil if that's none.
+fn butlast(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut xs = as_seq(&args[0])?;
+    xs.pop();
+    Ok(if xs.is_empty() {
+        nil()
+    } else {
+        MalType::List(xs.into())
+    })
+}
+
+pub fn butlast_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("butlast"), butlast)
+        .with_arglists("([coll])")
+        .with_doc("Returns a list of all but the last element of coll, or nil if there are none.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// The value at `key` in a map, or at index `key` in a sequence or string.
+fn lookup(coll: &MalType, key: &MalType) -> Option<MalType> {
+    match (coll, key) {
+        (
+            MalType::Map(WithMeta { value: m, .. }) | MalType::Record(Record { entries: m, .. }),
+            MalType::Atom(key),
+        ) => m.get(key).cloned(),
+        (MalType::Vector(v), MalType::Atom(MalAtom::Integer(i))) if *i >= 0 => {
+            v.get(*i as usize).cloned()
+        }
+        (MalType::List(l), MalType::Atom(MalAtom::Integer(i))) if *i >= 0 => {
+            l.get(*i as usize).cloned()
+        }
+        (MalType::Atom(MalAtom::Str(s)), MalType::Atom(MalAtom::Integer(i))) if *i >= 0 => s
+            .chars()
+            .nth(*i as usize)
+            .map(|c| MalType::Atom(MalAtom::Str(c.to_string()))),
+        _ => None,
+    }
+}
+
+/// `(get coll key)` or `(get coll key default)`, where `default` is nil if
+/// not given.
+fn get(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(lookup(&args[0], &args[1]).unwrap_or_else(|| args.get(2).cloned().unwrap_or_else(nil)))
+}
+
+pub fn get_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("get"), get)
+        .with_arglists("([coll key] [coll key not-found])")
+        .with_doc("Returns the value at key in a map, or at index key in a vector or string, or not-found, or nil, if there is none.")
+        .with_arity(Arity::Range(2, 3))
+}
+
+fn contains(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Boolean(
+        lookup(&args[0], &args[1]).is_some(),
+    )))
+}
+
+pub fn contains_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("contains?"), contains)
+        .with_arglists("([coll key])")
+        .with_doc("Returns true if coll has a value at key, an index for vectors and strings.")
+        .with_arity(Arity::Fixed(2))
+}
+
+/// `coll` with `key` set to `value`. Nil is an empty map, and a vector may
+/// be extended by setting the index one past its end.
+fn assoc_one(coll: MalType, key: &MalType, value: MalType) -> Result<MalType, MalError> {
+    let mut coll = Editable::new(coll)?;
+    coll.assoc(key, value)?;
+    Ok(coll.into_value())
+}
+
+/// `(assoc coll k v & kvs)`.
+fn assoc(args: Vec<MalType>) -> Result<MalType, MalError> {
+    if !(args.len() - 1).is_multiple_of(2) {
+        return Err(MalError::Normal(
+            "assoc needs a value for every key".to_string(),
+        ));
+    }
+    let mut args = args.into_iter();
+    let mut coll = Editable::new(args.next().unwrap())?;
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        coll.assoc(&key, value)?;
+    }
+    Ok(coll.into_value())
+}
+
+pub fn assoc_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("assoc"), assoc)
+        .with_arglists("([coll key val & kvs])")
+        .with_doc("Returns coll with each key set to its val. A vector may grow by one index past its end.")
+        .with_arity(Arity::Variadic(3))
+}
+
+/// `(dissoc m & ks)`.
+fn dissoc(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match &args[0] {
+        MalType::Map(_) | MalType::Record(_) => {
+            let mut m = Editable::new(args[0].clone())?;
+            for key in &args[1..] {
+                m.dissoc(key)?;
+            }
+            Ok(m.into_value())
+        }
+        MalType::Atom(MalAtom::Nil) => Ok(nil()),
+        other => Err(MalError::Normal(format!("`{:?}` is not a map", other))),
+    }
+}
+
+pub fn dissoc_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("dissoc"), dissoc)
+        .with_arglists("([map & keys])")
+        .with_doc("Returns map without keys.")
+        .with_arity(Arity::Variadic(1))
+}
+
+/// The keys or values of a map as a list, nil if there are none. Both
+/// iterate the map in the same order.
+fn entries(arg: &MalType, part: fn(&Map) -> List) -> Result<MalType, MalError> {
+    match arg {
+        MalType::Map(WithMeta { value: m, .. }) | MalType::Record(Record { entries: m, .. })
+            if !m.is_empty() =>
+        {
+            Ok(MalType::List(part(m)))
+        }
+        MalType::Map(_) | MalType::Record(_) | MalType::Atom(MalAtom::Nil) => Ok(nil()),
+        other => Err(MalError::Normal(format!("`{:?}` is not a map", other))),
+    }
+}
+
+/// `(keys m)` returns a list of the keys of `m`, or nil if it's empty.
+fn keys(args: Vec<MalType>) -> Result<MalType, MalError> {
+    entries(&args[0], |m| m.keys().cloned().map(MalType::Atom).collect())
+}
+
+pub fn keys_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("keys"), keys)
+        .with_arglists("([map])")
+        .with_doc("Returns a list of the keys of map, or nil if it's empty.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(vals m)` returns a list of the values of `m`, in the order `keys`
+/// does, or nil if it's empty.
+fn vals(args: Vec<MalType>) -> Result<MalType, MalError> {
+    entries(&args[0], |m| m.values().cloned().collect())
+}
+
+pub fn vals_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("vals"), vals)
+        .with_arglists("([map])")
+        .with_doc(
+            "Returns a list of the values of map in the order of its keys, or nil if it's empty.",
+        )
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(update coll key f & args)` sets `key` to `(f old args...)`.
+fn update(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let old = lookup(&args[0], &args[1]).unwrap_or_else(nil);
+    let mut f_args = vec![old];
+    f_args.extend_from_slice(&args[3..]);
+    assoc_one(args[0].clone(), &args[1], apply(&args[2], f_args)?)
+}
+
+pub fn update_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("update"), update)
+        .with_arglists("([coll key f & args])")
+        .with_doc("Returns coll with the value at key replaced by (f old args...).")
+        .with_arity(Arity::Variadic(3))
+}
+
+/// `(get-in coll ks)` or `(get-in coll ks default)` looks up each of `ks`
+/// in the value found for the last.
+fn get_in(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut value = args[0].clone();
+    for key in as_seq(&args[1])? {
+        match lookup(&value, &key) {
+            Some(found) => value = found,
+            None => return Ok(args.get(2).cloned().unwrap_or_else(nil)),
+        }
+    }
+    Ok(value)
+}
+
+pub fn get_in_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("get-in"), get_in)
+        .with_arglists("([coll ks] [coll ks not-found])")
+        .with_doc("Returns the value found by looking up each of ks in turn, or not-found, or nil, if there is none.")
+        .with_arity(Arity::Range(2, 3))
+}
+
+/// `coll` with the value at the path `ks` replaced by `f` of the old one,
+/// creating maps for missing levels.
+fn update_path(
+    coll: MalType,
+    ks: &[MalType],
+    f: &mut dyn FnMut(MalType) -> Result<MalType, MalError>,
+) -> Result<MalType, MalError> {
+    match ks {
+        [] => f(coll),
+        [key, rest @ ..] => {
+            let child = lookup(&coll, key).unwrap_or_else(nil);
+            let updated = update_path(child, rest, f)?;
+            assoc_one(coll, key, updated)
+        }
+    }
+}
+
+/// `(assoc-in coll ks v)`.
+fn assoc_in(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut args = args.into_iter();
+    let (coll, ks, value) = (
+        args.next().unwrap(),
+        as_seq(&args.next().unwrap())?,
+        args.next().unwrap(),
+    );
+    let mut value = Some(value);
+    update_path(coll, &ks, &mut |_| Ok(value.take().unwrap()))
+}
+
+pub fn assoc_in_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("assoc-in"), assoc_in)
+        .with_arglists("([coll ks val])")
+        .with_doc("Returns coll with the value at the path ks set to val, creating maps for missing levels.")
+        .with_arity(Arity::Fixed(3))
+}
+
+/// `(update-in coll ks f & args)`.
+fn update_in(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let ks = as_seq(&args[1])?;
+    update_path(args[0].clone(), &ks, &mut |old| {
+        let mut f_args = vec![old];
+        f_args.extend_from_slice(&args[3..]);
+        apply(&args[2], f_args)
+    })
+}
+
+pub fn update_in_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("update-in"), update_in)
+        .with_arglists("([coll ks f & args])")
+        .with_doc("Returns coll with the value at the path ks replaced by (f old args...), creating maps for missing levels.")
+        .with_arity(Arity::Variadic(3))
+}
+
+/// `(merge & ms)` combines maps, later keys winning. Nils are skipped.
+fn merge(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut merged: Option<Editable> = None;
+    for arg in args {
+        match arg {
+            MalType::Map(_) | MalType::Record(_) => match merged.as_mut() {
+                Some(merged) => merged.conj(arg)?,
+                None => merged = Some(Editable::new(arg)?),
+            },
+            MalType::Atom(MalAtom::Nil) => {}
+            other => return Err(MalError::Normal(format!("`{:?}` is not a map", other))),
+        }
+    }
+    Ok(merged.map_or_else(nil, Editable::into_value))
+}
+
+pub fn merge_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("merge"), merge)
+        .with_arglists("([& maps])")
+        .with_doc("Returns a map of the entries of each map, later keys winning, or nil if there are none.")
+        .with_arity(Arity::Variadic(0))
+}
+
+/// `(select-keys m ks)` returns a map of only the entries of `m` in `ks`.
+fn select_keys(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut selected = Editable::Map(Map::new());
+    for key in as_seq(&args[1])? {
+        if let Some(value) = lookup(&args[0], &key) {
+            selected.assoc(&key, value)?;
+        }
+    }
+    Ok(selected.into_value())
+}
+
+pub fn select_keys_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("select-keys"), select_keys)
+        .with_arglists("([map keys])")
+        .with_doc("Returns a map of the entries of map whose key is in keys.")
+        .with_arity(Arity::Fixed(2))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        map_fn(),
+        mapcat_fn(),
+        filter_fn(),
+        remove_fn(),
+        range_fn(),
+        take_fn(),
+        drop_fn(),
+        take_while_fn(),
+        drop_while_fn(),
+        partition_fn(),
+        group_by_fn(),
+        frequencies_fn(),
+        sort_fn(),
+        sort_by_fn(),
+        interleave_fn(),
+        distinct_fn(),
+        zipmap_fn(),
+        reverse_fn(),
+        some_fn(),
+        every_fn(),
+        last_fn(),
+        butlast_fn(),
+        get_fn(),
+        contains_fn(),
+        assoc_fn(),
+        dissoc_fn(),
+        keys_fn(),
+        vals_fn(),
+        update_fn(),
+        get_in_fn(),
+        assoc_in_fn(),
+        update_in_fn(),
+        merge_fn(),
+        select_keys_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn sequences() {
+        assert_eq!(
+            "[(2 3 4) (5 7) (2 4) (1 1) (0 1 2) (3 1) (1 2) (3) (1 2) (3 1)]",
+            rep(
+                "[(map inc [1 2 3]) (map + [1 2 3] '(4 5)) (filter (fn* [x] (= 0 (- x (* 2 (/ x 2))))) [1 2 3 4]) \
+                  (remove (fn* [x] (> x 1)) [1 2 3 2 1 2 3]) (range 3) (range 3 0 -2) (take 2 [1 2 3]) \
+                  (drop 2 [1 2 3]) (take-while (fn* [x] (< x 3)) [1 2 3 1]) (drop-while (fn* [x] (< x 3)) [1 2 3 1])]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[((1 2) (3 4)) ((1 2) (2 3) (3 4)) (1 1 2 2) (1 :a 2 :b) (1 2 3) (3 2 1) 3 (1 2) nil nil]",
+            rep(
+                "[(partition 2 [1 2 3 4 5]) (partition 2 1 [1 2 3 4]) (mapcat (fn* [x] [x x]) [1 2]) \
+                  (interleave [1 2 3] [:a :b]) (distinct [1 2 1 3 2]) (reverse [1 2 3]) (last [1 2 3]) \
+                  (butlast [1 2 3]) (butlast [1]) (last [])]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[(\"a\" \"b\" \"c\") (1 2 3 10) ([2 :a] [1 :b]) (\"b\" \"ccc\") 6 true false nil]",
+            rep(
+                "[(sort \"cab\") (sort [10 2 3 1]) (sort-by (fn* [e] (nth e 1)) [[2 :a] [1 :b]]) \
+                  (sort-by count [\"ccc\" \"b\"]) (reduce + (map (fn* [e] (nth e 1)) {:a 1 :b 2 :c 3})) \
+                  (every? (fn* [x] (> x 0)) [1 2]) (every? (fn* [x] (> x 1)) [1 2]) (some (fn* [x] (> x 5)) [1 2])]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[true true true 4]",
+            rep(
+                "[(= (group-by count [\"a\" \"bb\" \"c\"]) {1 [\"a\" \"c\"] 2 [\"bb\"]}) \
+                  (= (frequencies \"abca\") {\"a\" 2 \"b\" 1 \"c\" 1}) \
+                  (= (zipmap [:a :b :c] [1 2]) {:a 1 :b 2}) (some (fn* [x] (if (> x 3) x)) [1 4 5])]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[(1 2 3) (1 [2]) 2 20000]",
+            rep(
+                "[(take 3 (distinct (cycle [1 2 3 4]))) (distinct (list 1 [2] '(2) 1)) \
+                  (count (distinct [inc inc])) (count (distinct (range 20000)))]"
+            )
+            .unwrap()
+        );
+        assert!(rep("(sort [1 \"a\"])").is_err());
+        assert!(rep("(range 1 2 0)").is_err());
+        assert!(rep("(partition 0 [1])").is_err());
+    }
+
+    #[test]
+    fn associative() {
+        assert_eq!(
+            "[1 nil :x \"b\" 2 true false [1 :z] [1 2 3]]",
+            rep(
+                "[(get {:a 1} :a) (get {:a 1} :b) (get [1] 5 :x) (get \"abc\" 1) (get [1 2] 1) \
+                  (contains? {:a nil} :a) (contains? [1] 1) (assoc [1 2] 1 :z) (assoc [1 2] 2 3)]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[true true true true true true true]",
+            rep("(def! m {:a {:b 1}}) \
+                 [(= (assoc m :c 2 :d 3) {:a {:b 1} :c 2 :d 3}) (= (dissoc m :a) {}) \
+                  (= (update m :n (fn* [n] (if n n 0))) {:a {:b 1} :n 0}) \
+                  (= (get-in m [:a :b]) 1) (= (assoc-in m [:a :c :d] 2) {:a {:b 1 :c {:d 2}}}) \
+                  (= (update-in m [:a :b] + 10) {:a {:b 11}}) \
+                  (= (merge m nil {:a 1 :z 2}) {:a 1 :z 2})]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[(:a) (1) nil true nil :none]",
+            rep(
+                "[(keys {:a 1}) (vals {:a 1}) (keys {}) (= (select-keys {:a 1 :b 2} [:a :c]) {:a 1}) \
+                  (merge) (get-in {:a 1} [:a :b] :none)]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[5 :x 100000 [0 1] [:y 1]]",
+            rep("(def! big (zipmap (range 100000) (range 100000))) \
+                 (def! v [0 1]) \
+                 [(get big 5) (get (assoc big 5 :x) 5) (count (assoc big 5 :x)) v (assoc v 0 :y)]")
+            .unwrap()
+        );
+        assert!(rep("(assoc [1] 3 2)").is_err());
+        assert!(rep("(assoc {} :a)").is_err());
+    }
+}
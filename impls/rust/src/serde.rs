@@ -0,0 +1,1100 @@
+//! Serde support: `MalType` implements `Serialize` and `Deserialize`, and
+//! `to_mal`/`from_mal` convert any serde-compatible value to and from a mal
+//! value directly.
+//!
+//! Maps with keyword keys become objects keyed by the keyword's name, and
+//! string keys of objects are read back as keywords. Keywords and symbols
+//! elsewhere are written as their names, vectors and lists as arrays and nil
+//! as null. Functions, protocols and atoms have no data representation and
+//! fail to serialize, as does a map with two keys written as the same
+//! string, like `:a` and `"a"`. Mal has no floats, so a float is read as
+//! the integer it equals, and one with a fractional part fails.
+//!
+//! Formats that aren't human-readable, like bincode, can't read a value
+//! without knowing its type in advance, so mal values are written to them as
+//! an enum with a variant for each type (see `VARIANTS`). That also keeps
+//! keywords and symbols apart from strings, and lists from vectors.
+
+use std::{collections::HashMap, convert::TryFrom, error, fmt};
+
+use ::serde::{
+    de::{
+        self,
+        value::{MapDeserializer, SeqDeserializer},
+        DeserializeOwned, IntoDeserializer, VariantAccess as _, Visitor,
+    },
+    forward_to_deserialize_any,
+    ser::{self, Impossible, SerializeMap as _, SerializeSeq as _},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    keyword::Keyword,
+    printer,
+    record::Record,
+    symbol::Symbol,
+    types::{MalAtom, MalError, MalType, Map, WithMeta},
+};
+
+impl error::Error for MalError {}
+
+impl ser::Error for MalError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        MalError::Normal(msg.to_string())
+    }
+}
+
+impl de::Error for MalError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        MalError::Normal(msg.to_string())
+    }
+}
+
+fn key(name: &str) -> MalAtom {
+    MalAtom::Keyword(Keyword::new(name.to_string()))
+}
+
+/// A float as the integer it equals, if it's a whole number in range.
+fn whole_float<E: de::Error>(num: f64) -> Result<MalType, E> {
+    if num.fract() == 0.0 && num >= isize::MIN as f64 && num < isize::MAX as f64 {
+        Ok(MalType::Atom(MalAtom::Integer(num as isize)))
+    } else {
+        Err(E::custom(format!("{} is not an integer", num)))
+    }
+}
+
+/// The variants of the enum mal values are written as to formats that
+/// aren't human-readable, in order.
+const VARIANTS: &[&str] = &[
+    "nil", "boolean", "integer", "string", "keyword", "symbol", "list", "vector", "map",
+];
+
+fn variant_index(name: &str) -> u32 {
+    VARIANTS.iter().position(|v| *v == name).unwrap() as u32
+}
+
+fn serialize_variant<S: Serializer, T: Serialize + ?Sized>(
+    serializer: S,
+    name: &'static str,
+    value: &T,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_newtype_variant("MalType", variant_index(name), name, value)
+}
+
+fn integer<E: ser::Error, N: fmt::Display + Copy>(num: N) -> Result<MalType, E>
+where
+    isize: TryFrom<N>,
+{
+    isize::try_from(num)
+        .map(|num| MalType::Atom(MalAtom::Integer(num)))
+        .map_err(|_| E::custom(format!("{} is out of range for a mal integer", num)))
+}
+
+impl Serialize for MalAtom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return match self {
+                MalAtom::Nil => {
+                    serializer.serialize_unit_variant("MalType", variant_index("nil"), "nil")
+                }
+                MalAtom::Boolean(b) => serialize_variant(serializer, "boolean", b),
+                MalAtom::Integer(num) => serialize_variant(serializer, "integer", &(*num as i64)),
+                MalAtom::Str(s) => serialize_variant(serializer, "string", s),
+                MalAtom::Keyword(k) => serialize_variant(serializer, "keyword", &k.name),
+                MalAtom::Symbol(s) => serialize_variant(serializer, "symbol", &s.name),
+            };
+        }
+        match self {
+            MalAtom::Nil => serializer.serialize_unit(),
+            MalAtom::Boolean(b) => serializer.serialize_bool(*b),
+            MalAtom::Integer(num) => serializer.serialize_i64(*num as i64),
+            MalAtom::Str(s) => serializer.serialize_str(s),
+            MalAtom::Keyword(k) => serializer.serialize_str(&k.name),
+            MalAtom::Symbol(s) => serializer.serialize_str(&s.name),
+        }
+    }
+}
+
+/// The string `key` is written as, if it's a string, keyword or symbol.
+fn name(key: &MalAtom) -> Option<&str> {
+    match key {
+        MalAtom::Str(s) => Some(s),
+        MalAtom::Keyword(k) => Some(&k.name),
+        MalAtom::Symbol(s) => Some(&s.name),
+        _ => None,
+    }
+}
+
+fn serialize_items<'a, S: Serializer>(
+    serializer: S,
+    len: usize,
+    items: impl IntoIterator<Item = &'a MalType>,
+) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(len))?;
+    for item in items {
+        seq.serialize_element(item)?;
+    }
+    seq.end()
+}
+
+/// A mal value as the enum formats that aren't human-readable are written,
+/// with maps as sequences of key and value pairs.
+fn serialize_tagged<S: Serializer>(value: &MalType, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        MalType::Atom(atom) => atom.serialize(serializer),
+        MalType::List(items) => serialize_variant(serializer, "list", &items.to_vec()),
+        MalType::Vector(items) => {
+            serialize_variant(serializer, "vector", &items.iter().collect::<Vec<_>>())
+        }
+        MalType::LazySeq(lazy) => {
+            let items = lazy.to_vec_limited().map_err(ser::Error::custom)?;
+            serialize_variant(serializer, "list", &items)
+        }
+        MalType::Map(WithMeta { value: entries, .. }) | MalType::Record(Record { entries, .. }) => {
+            serialize_variant(serializer, "map", &entries.iter().collect::<Vec<_>>())
+        }
+        other => Err(ser::Error::custom(format!(
+            "`{:?}` can't be serialized",
+            other
+        ))),
+    }
+}
+
+impl Serialize for MalType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serialize_tagged(self, serializer);
+        }
+        match self {
+            MalType::Atom(atom) => atom.serialize(serializer),
+            MalType::List(items) => serialize_items(serializer, items.len(), items),
+            MalType::Vector(items) => serialize_items(serializer, items.len(), items),
+            MalType::LazySeq(lazy) => {
+                let items = lazy.to_vec_limited().map_err(ser::Error::custom)?;
+                MalType::List(items.into()).serialize(serializer)
+            }
+            MalType::Map(WithMeta { value: entries, .. })
+            | MalType::Record(Record { entries, .. }) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                let mut written = HashMap::new();
+                for (k, v) in entries {
+                    if let Some(other) = name(k).and_then(|name| written.insert(name, k)) {
+                        // In order, as the entries aren't.
+                        let mut keys =
+                            [other, k].map(|key| printer::pr_str(&MalType::Atom(key.clone())));
+                        keys.sort();
+                        return Err(ser::Error::custom(format!(
+                            "Map keys {} and {} are both written as {:?}",
+                            keys[0],
+                            keys[1],
+                            name(k).unwrap_or_default(),
+                        )));
+                    }
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+            other => Err(ser::Error::custom(format!(
+                "`{:?}` can't be serialized",
+                other
+            ))),
+        }
+    }
+}
+
+struct MalVisitor;
+
+impl<'de> Visitor<'de> for MalVisitor {
+    type Value = MalType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a mal value")
+    }
+
+    fn visit_unit<E>(self) -> Result<MalType, E> {
+        Ok(MalType::Atom(MalAtom::Nil))
+    }
+
+    fn visit_none<E>(self) -> Result<MalType, E> {
+        Ok(MalType::Atom(MalAtom::Nil))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<MalType, D::Error> {
+        MalType::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<MalType, E> {
+        Ok(MalType::Atom(MalAtom::Boolean(b)))
+    }
+
+    fn visit_i64<E: de::Error>(self, num: i64) -> Result<MalType, E> {
+        isize::try_from(num)
+            .map(|num| MalType::Atom(MalAtom::Integer(num)))
+            .map_err(|_| E::custom(format!("{} is out of range for a mal integer", num)))
+    }
+
+    fn visit_u64<E: de::Error>(self, num: u64) -> Result<MalType, E> {
+        isize::try_from(num)
+            .map(|num| MalType::Atom(MalAtom::Integer(num)))
+            .map_err(|_| E::custom(format!("{} is out of range for a mal integer", num)))
+    }
+
+    fn visit_f64<E: de::Error>(self, num: f64) -> Result<MalType, E> {
+        whole_float(num)
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<MalType, E> {
+        Ok(MalType::Atom(MalAtom::Str(s.to_string())))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<MalType, E> {
+        Ok(MalType::Atom(MalAtom::Str(s)))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<MalType, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(MalType::Vector(items.into()))
+    }
+
+    /// String keys are read as keywords; other keys must be atoms.
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<MalType, A::Error> {
+        let mut entries = Map::new();
+        while let Some((k, v)) = map.next_entry::<MalType, MalType>()? {
+            let k = match k {
+                MalType::Atom(MalAtom::Str(name)) => key(&name),
+                MalType::Atom(atom) => atom,
+                other => {
+                    return Err(de::Error::custom(format!(
+                        "`{:?}` can't be a map key",
+                        other
+                    )))
+                }
+            };
+            entries.insert(k, v);
+        }
+        Ok(MalType::Map(entries.into()))
+    }
+
+    /// Reads the enum formats that aren't human-readable are written as.
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<MalType, A::Error> {
+        let (Variant(name), variant) = data.variant()?;
+        Ok(match name {
+            "nil" => {
+                variant.unit_variant()?;
+                MalType::Atom(MalAtom::Nil)
+            }
+            "boolean" => MalType::Atom(MalAtom::Boolean(variant.newtype_variant()?)),
+            "integer" => self.visit_i64(variant.newtype_variant()?)?,
+            "string" => MalType::Atom(MalAtom::Str(variant.newtype_variant()?)),
+            "keyword" => MalType::Atom(MalAtom::Keyword(Keyword::new(variant.newtype_variant()?))),
+            "symbol" => MalType::Atom(MalAtom::Symbol(Symbol::new(
+                &variant.newtype_variant::<String>()?,
+            ))),
+            "list" => MalType::List(variant.newtype_variant::<Vec<MalType>>()?.into()),
+            "vector" => MalType::Vector(variant.newtype_variant::<Vec<MalType>>()?.into()),
+            _ => {
+                let mut entries = Map::new();
+                for (k, v) in variant.newtype_variant::<Vec<(MalType, MalType)>>()? {
+                    match k {
+                        MalType::Atom(k) => entries.insert(k, v),
+                        other => {
+                            return Err(de::Error::custom(format!(
+                                "`{:?}` can't be a map key",
+                                other
+                            )))
+                        }
+                    };
+                }
+                MalType::Map(entries.into())
+            }
+        })
+    }
+}
+
+/// The name of one of `VARIANTS`, read from its name or index.
+struct Variant(&'static str);
+
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_identifier(VariantVisitor)
+    }
+}
+
+struct VariantVisitor;
+
+impl<'de> Visitor<'de> for VariantVisitor {
+    type Value = Variant;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a mal type")
+    }
+
+    fn visit_u64<E: de::Error>(self, index: u64) -> Result<Variant, E> {
+        VARIANTS
+            .get(index as usize)
+            .map(|name| Variant(name))
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(index), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Variant, E> {
+        VARIANTS
+            .iter()
+            .find(|variant| **variant == name)
+            .map(|name| Variant(name))
+            .ok_or_else(|| E::unknown_variant(name, VARIANTS))
+    }
+}
+
+impl<'de> Deserialize<'de> for MalType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(MalVisitor)
+        } else {
+            deserializer.deserialize_enum("MalType", VARIANTS, MalVisitor)
+        }
+    }
+}
+
+/// Converts a serializable value to a mal value. Structs become maps with
+/// keyword keys, unit enum variants keywords and other variants maps from
+/// the variant keyword to its contents.
+pub fn to_mal<T: Serialize + ?Sized>(value: &T) -> Result<MalType, MalError> {
+    value.serialize(MalSerializer)
+}
+
+/// Converts a mal value to any deserializable type.
+pub fn from_mal<T: DeserializeOwned>(value: MalType) -> Result<T, MalError> {
+    T::deserialize(value)
+}
+
+/// A serializer building mal values, used by `to_mal`.
+pub struct MalSerializer;
+
+impl Serializer for MalSerializer {
+    type Ok = MalType;
+    type Error = MalError;
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVariant<SerializeVec>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeVariant<SerializeMap>;
+
+    fn serialize_bool(self, v: bool) -> Result<MalType, MalError> {
+        Ok(MalType::Atom(MalAtom::Boolean(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<MalType, MalError> {
+        integer(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<MalType, MalError> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<MalType, MalError> {
+        whole_float(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<MalType, MalError> {
+        Ok(MalType::Atom(MalAtom::Str(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<MalType, MalError> {
+        Ok(MalType::Atom(MalAtom::Str(v.to_string())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<MalType, MalError> {
+        Ok(MalType::Vector(
+            v.iter()
+                .map(|b| MalType::Atom(MalAtom::Integer(isize::from(*b))))
+                .collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<MalType, MalError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<MalType, MalError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<MalType, MalError> {
+        Ok(MalType::Atom(MalAtom::Nil))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<MalType, MalError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<MalType, MalError> {
+        Ok(MalType::Atom(key(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<MalType, MalError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<MalType, MalError> {
+        let mut map = Map::new();
+        map.insert(key(variant), to_mal(value)?);
+        Ok(MalType::Map(map.into()))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, MalError> {
+        Ok(SerializeVec(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, MalError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, MalError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeVec>, MalError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, MalError> {
+        Ok(SerializeMap {
+            entries: Map::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, MalError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeMap>, MalError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+pub struct SerializeVec(Vec<MalType>);
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = MalType;
+    type Error = MalError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        self.0.push(to_mal(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, MalError> {
+        Ok(MalType::Vector(self.0.into()))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = MalType;
+    type Error = MalError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<MalType, MalError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = MalType;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<MalType, MalError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeMap {
+    entries: Map,
+    key: Option<MalAtom>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = MalType;
+    type Error = MalError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), MalError> {
+        self.key = Some(key.serialize(MapKeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| MalError::Normal("Map value serialized before its key".to_string()))?;
+        self.entries.insert(key, to_mal(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, MalError> {
+        Ok(MalType::Map(self.entries.into()))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = MalType;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        field: &'static str,
+        value: &T,
+    ) -> Result<(), MalError> {
+        self.entries.insert(key(field), to_mal(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, MalError> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// Collects a tuple or struct variant's contents, then wraps them in a map
+/// from the variant keyword.
+pub struct SerializeVariant<T> {
+    variant: &'static str,
+    inner: T,
+}
+
+impl<T> SerializeVariant<T> {
+    fn wrap(variant: &'static str, value: MalType) -> MalType {
+        let mut map = Map::new();
+        map.insert(key(variant), value);
+        MalType::Map(map.into())
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeVec> {
+    type Ok = MalType;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        self.inner.serialize_element(value)
+    }
+
+    fn end(self) -> Result<MalType, MalError> {
+        Ok(Self::wrap(self.variant, self.inner.end()?))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap> {
+    type Ok = MalType;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        field: &'static str,
+        value: &T,
+    ) -> Result<(), MalError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, field, value)
+    }
+
+    fn end(self) -> Result<MalType, MalError> {
+        Ok(Self::wrap(self.variant, self.inner.end()?))
+    }
+}
+
+/// Serializes map keys, which must be atoms. String keys become keywords.
+struct MapKeySerializer;
+
+impl MapKeySerializer {
+    fn not_atom() -> MalError {
+        MalError::Normal("Map keys must be strings, keywords, integers or booleans".to_string())
+    }
+
+    fn atom(value: MalType) -> Result<MalAtom, MalError> {
+        match value {
+            MalType::Atom(atom) => Ok(atom),
+            _ => Err(Self::not_atom()),
+        }
+    }
+}
+
+impl Serializer for MapKeySerializer {
+    type Ok = MalAtom;
+    type Error = MalError;
+    type SerializeSeq = Impossible<MalAtom, MalError>;
+    type SerializeTuple = Impossible<MalAtom, MalError>;
+    type SerializeTupleStruct = Impossible<MalAtom, MalError>;
+    type SerializeTupleVariant = Impossible<MalAtom, MalError>;
+    type SerializeMap = Impossible<MalAtom, MalError>;
+    type SerializeStruct = Impossible<MalAtom, MalError>;
+    type SerializeStructVariant = Impossible<MalAtom, MalError>;
+
+    fn serialize_bool(self, v: bool) -> Result<MalAtom, MalError> {
+        Ok(MalAtom::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<MalAtom, MalError> {
+        Self::atom(integer(v)?)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<MalAtom, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<MalAtom, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_char(self, v: char) -> Result<MalAtom, MalError> {
+        Ok(key(&v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<MalAtom, MalError> {
+        Ok(key(v))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<MalAtom, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_none(self) -> Result<MalAtom, MalError> {
+        Ok(MalAtom::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<MalAtom, MalError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<MalAtom, MalError> {
+        Ok(MalAtom::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<MalAtom, MalError> {
+        Ok(MalAtom::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<MalAtom, MalError> {
+        Ok(key(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<MalAtom, MalError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<MalAtom, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, MalError> {
+        Err(Self::not_atom())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, MalError> {
+        Err(Self::not_atom())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, MalError> for MalType {
+    type Deserializer = MalType;
+
+    fn into_deserializer(self) -> MalType {
+        self
+    }
+}
+
+impl<'de> IntoDeserializer<'de, MalError> for MalAtom {
+    type Deserializer = MalType;
+
+    fn into_deserializer(self) -> MalType {
+        MalType::Atom(self)
+    }
+}
+
+fn visit_items<'de, V: Visitor<'de>>(
+    items: impl IntoIterator<Item = MalType>,
+    visitor: V,
+) -> Result<V::Value, MalError> {
+    let mut seq = SeqDeserializer::new(items.into_iter());
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+}
+
+/// Reads a value as a serde data model value: keywords and symbols as their
+/// names, lists and vectors as sequences and maps as maps.
+impl<'de> Deserializer<'de> for MalType {
+    type Error = MalError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, MalError> {
+        match self {
+            MalType::Atom(MalAtom::Nil) => visitor.visit_unit(),
+            MalType::Atom(MalAtom::Boolean(b)) => visitor.visit_bool(b),
+            MalType::Atom(MalAtom::Integer(num)) => visitor.visit_i64(num as i64),
+            MalType::Atom(MalAtom::Str(s)) => visitor.visit_string(s),
+            MalType::Atom(MalAtom::Keyword(k)) => visitor.visit_string(k.name),
+            MalType::Atom(MalAtom::Symbol(s)) => visitor.visit_string(s.name),
+            MalType::LazySeq(lazy) => MalType::List(lazy.to_vec()?.into()).deserialize_any(visitor),
+            MalType::List(items) => visit_items(items, visitor),
+            MalType::Vector(items) => visit_items(items, visitor),
+            MalType::Map(WithMeta { value: entries, .. })
+            | MalType::Record(Record { entries, .. }) => {
+                let mut map = MapDeserializer::new(entries.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            other => Err(de::Error::custom(format!(
+                "`{:?}` can't be deserialized",
+                other
+            ))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, MalError> {
+        match self {
+            MalType::Atom(MalAtom::Nil) => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, MalError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are read from keywords or strings, other variants from
+    /// a map with a single entry from the variant to its contents.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, MalError> {
+        match self {
+            MalType::Atom(MalAtom::Keyword(Keyword { name }))
+            | MalType::Atom(MalAtom::Str(name)) => visitor.visit_enum(name.into_deserializer()),
+            MalType::Map(entries) if entries.len() == 1 => {
+                let (variant, value) = entries.into_iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            other => Err(de::Error::custom(format!(
+                "`{:?}` is not an enum variant",
+                other
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct EnumDeserializer {
+    variant: MalAtom,
+    value: MalType,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = MalError;
+    type Variant = MalType;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, MalType), MalError> {
+        let variant = seed.deserialize(MalType::Atom(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for MalType {
+    type Error = MalError;
+
+    fn unit_variant(self) -> Result<(), MalError> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, MalError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, MalError> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, MalError> {
+        self.deserialize_any(visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ::serde::{Deserialize, Serialize};
+
+    use crate::{
+        built_ins::plus_fn,
+        reader::read_str,
+        serde::{from_mal, to_mal},
+        types::{MalError, MalType},
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Point,
+        Circle(i64),
+        Rect { width: i64, height: i64 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        title: String,
+        shapes: Vec<Shape>,
+        scale: Option<u32>,
+        layers: HashMap<String, bool>,
+    }
+
+    fn read(input: &str) -> MalType {
+        read_str(input).unwrap().remove(0)
+    }
+
+    #[test]
+    fn json() {
+        let value = read("{:a [1 \"two\" nil true] :b {:c :d}}");
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(
+            serde_json::json!({"a": [1, "two", null, true], "b": {"c": "d"}}),
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        );
+        assert_eq!(
+            read("{:a [1 \"two\" nil true] :b {:c \"d\"}}"),
+            serde_json::from_str::<MalType>(&json).unwrap()
+        );
+        assert!(serde_json::to_string(&MalType::Function(plus_fn())).is_err());
+        assert_eq!(
+            read("[2 -3]"),
+            serde_json::from_str::<MalType>("[2.0, -3.0]").unwrap()
+        );
+        assert!(serde_json::from_str::<MalType>("1.5").is_err());
+        assert!(serde_json::from_str::<MalType>("1e300").is_err());
+    }
+
+    #[test]
+    fn bincode() {
+        let value = read("{:a [1 \"two\" nil true] \"b\" (c :d {1 ()}) :e -5}");
+        let bytes = bincode::serialize(&value).unwrap();
+        let round_trip: MalType = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(value, round_trip);
+        // Unlike JSON, bincode keeps keywords, symbols and lists apart from
+        // strings and vectors.
+        assert_eq!(
+            read("[c (c) \"c\"]").to_string(),
+            bincode::deserialize::<MalType>(&bincode::serialize(&read("[c (c) \"c\"]")).unwrap())
+                .unwrap()
+                .to_string()
+        );
+        assert!(bincode::serialize(&MalType::Function(plus_fn())).is_err());
+        assert!(bincode::deserialize::<MalType>(&[9, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn structs() {
+        let mut layers = HashMap::new();
+        layers.insert(String::from("ink"), true);
+        let drawing = Drawing {
+            title: String::from("shapes"),
+            shapes: vec![
+                Shape::Point,
+                Shape::Circle(2),
+                Shape::Rect {
+                    width: 3,
+                    height: 4,
+                },
+            ],
+            scale: None,
+            layers,
+        };
+        let value = read(
+            "{:title \"shapes\" :shapes [:Point {:Circle 2} {:Rect {:width 3 :height 4}}] \
+             :scale nil :layers {:ink true}}",
+        );
+        assert_eq!(value, to_mal(&drawing).unwrap());
+        assert_eq!(drawing, from_mal::<Drawing>(value).unwrap());
+        match from_mal::<Drawing>(read("{:title 1}")) {
+            Err(MalError::Normal(err)) => {
+                assert_eq!("invalid type: integer `1`, expected a string", err)
+            }
+            other => panic!("expected a type error, got {:?}", other),
+        }
+    }
+}
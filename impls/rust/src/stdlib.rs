@@ -0,0 +1,696 @@
+//! Native versions of the mal libraries under `lib`, compiled into every
+//! REPL environment. Macros from those libraries (`->`, `->>`, `bool-and`,
+//! `bool-or` and `defprotocol`) are special forms in `eval`.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    built_ins::{self, as_seq},
+    dynamic,
+    env::Env,
+    eval::apply,
+    keyword::Keyword,
+    meta,
+    printer::pr_str,
+    reader::read_str,
+    symbol::Symbol,
+    types::{by_value, Args, Arity, MalAtom, MalError, MalFunction, MalType, Protocol},
+};
+
+/// Library files in `lib` whose contents are provided natively by this
+/// module.
+const BUNDLED_LIBS: [&str; 8] = [
+    "load-file-once",
+    "trivial",
+    "reducers",
+    "threading",
+    "memoize",
+    "protocols",
+    "pprint",
+    "equality",
+];
+
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The name of the bundled library at `path`, if `path` is one of the
+/// library files in this repo's `lib` directory.
+pub fn bundled(path: &str) -> Option<&'static str> {
+    let path = fs::canonicalize(path).ok()?;
+    let lib_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../lib");
+    BUNDLED_LIBS.iter().copied().find(|name| {
+        fs::canonicalize(lib_dir.join(format!("{}.mal", name))).is_ok_and(|lib| lib == path)
+    })
+}
+
+/// Does what loading the bundled library `name` would do in `env`, where
+/// everything it defines is already native. Only `equality.mal` does more
+/// than define things: it replaces `=` with one that compares sequences and
+/// maps by value if the current `=` doesn't.
+pub fn load_bundled(name: &str, env: &mut Env) -> Result<MalType, MalError> {
+    if name == "equality" && !equality_correct(&env.get(&Symbol::new("="))?)? {
+        env.set(Symbol::new("="), MalType::Function(built_ins::equal_fn()));
+        dynamic::write_out("equality.mal: Replaced = with the native implementation\n")?;
+    }
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+/// Whether `equal` compares sequences and maps by value, as `equality.mal`
+/// checks.
+fn equality_correct(equal: &MalType) -> Result<bool, MalError> {
+    let cases = [
+        ("{:a 1} {:a 1}", true),
+        ("{:a 1} {:a 1 :b 2}", false),
+        ("[:a :b] (:a :b)", true),
+        ("[:a :b] [:a :b :c]", false),
+    ];
+    for (args, expected) in cases {
+        match apply(equal, read_str(args)?) {
+            Ok(result) if result.is_truthy() == expected => {}
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+fn parse_number(name: &str, args: Vec<MalType>) -> Result<isize, MalError> {
+    match args.as_slice() {
+        [MalType::Atom(MalAtom::Integer(num))] => Ok(*num),
+        [arg] => Err(MalError::Normal(format!("`{:?}` is not a number", arg))),
+        _ => Err(MalError::Normal(format!(
+            "Wrong number of args for {}. Need 1, received {}",
+            name,
+            args.len(),
+        ))),
+    }
+}
+
+fn inc(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Integer(
+        parse_number("inc", args)? + 1,
+    )))
+}
+
+pub fn inc_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("inc"), inc)
+        .with_arglists("([x])")
+        .with_doc("Returns x plus one.")
+}
+
+fn dec(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Integer(
+        parse_number("dec", args)? - 1,
+    )))
+}
+
+pub fn dec_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("dec"), dec)
+        .with_arglists("([x])")
+        .with_doc("Returns x minus one.")
+}
+
+fn is_zero(args: Vec<MalType>) -> Result<MalType, MalError> {
+    Ok(MalType::Atom(MalAtom::Boolean(
+        parse_number("zero?", args)? == 0,
+    )))
+}
+
+pub fn is_zero_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("zero?"), is_zero)
+        .with_arglists("([x])")
+        .with_doc("Returns true if x is zero.")
+}
+
+fn identity(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.len() {
+        1 => Ok(args.into_iter().next().unwrap()),
+        _ => Err(MalError::Normal(format!(
+            "Wrong number of args for identity. Need 1, received {}",
+            args.len(),
+        ))),
+    }
+}
+
+pub fn identity_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("identity"), identity)
+        .with_arglists("([x])")
+        .with_doc("Returns x.")
+}
+
+/// `(gensym)` returns a new symbol like `G__12`, and `(gensym "x")` one like
+/// `x12`.
+fn gensym(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let prefix = match args.first() {
+        None => "G__",
+        Some(MalType::Atom(MalAtom::Str(prefix))) => prefix,
+        Some(other) => {
+            return Err(MalError::Normal(format!(
+                "gensym needs a string prefix. Given {}",
+                other
+            )))
+        }
+    };
+    Ok(MalType::Atom(MalAtom::Symbol(new_gensym(prefix))))
+}
+
+/// A symbol no other has been named, `prefix` followed by a number from a
+/// counter shared by every environment.
+pub fn new_gensym(prefix: &str) -> Symbol {
+    let id = GENSYM_COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+    Symbol::new(&format!("{}{}", prefix, id))
+}
+
+pub fn gensym_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("gensym"), gensym)
+        .with_arglists("([] [prefix])")
+        .with_doc("Returns a new symbol, named prefix followed by a number, or G__ and a number if there's no prefix.")
+        .with_arity(Arity::Range(0, 1))
+}
+
+/// `(reduce f init xs)` folds from the left; `(reduce f xs)` uses the first
+/// element of `xs` as `init`.
+fn reduce(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let (f, init, xs) = match args.as_slice() {
+        [f, init, xs] => (f, init.clone(), as_seq(xs)?),
+        [f, xs] => {
+            let mut xs = as_seq(xs)?;
+            if xs.is_empty() {
+                return apply(f, Vec::new());
+            }
+            let init = xs.remove(0);
+            (f, init, xs)
+        }
+        _ => {
+            return Err(MalError::Normal(format!(
+                "Wrong number of args for reduce. Need 2 or 3, received {}",
+                args.len(),
+            )))
+        }
+    };
+    xs.into_iter()
+        .try_fold(init, |acc, x| apply(f, vec![acc, x]))
+}
+
+pub fn reduce_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("reduce"), reduce)
+        .with_arglists("([f coll] [f init coll])")
+        .with_doc("Returns the result of calling f with init, or the first element of coll, and each element in turn, passing each result to the next call.")
+}
+
+/// `(foldr f init xs)` folds from the right, calling `(f x acc)`.
+fn foldr(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [f, init, xs] => as_seq(xs)?
+            .into_iter()
+            .rev()
+            .try_fold(init.clone(), |acc, x| apply(f, vec![x, acc])),
+        _ => Err(MalError::Normal(format!(
+            "Wrong number of args for foldr. Need 3, received {}",
+            args.len(),
+        ))),
+    }
+}
+
+pub fn foldr_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("foldr"), foldr)
+        .with_arglists("([f init coll])")
+        .with_doc("Folds coll from the right, calling (f x acc) for each element with acc starting as init.")
+}
+
+/// Wraps a function in a closure caching its results by argument. Calls with
+/// args only equal to themselves by identity, like functions, aren't cached.
+fn memoize(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let func = args.into_iter().next().unwrap();
+    let name = match &func {
+        MalType::Function(native) => native.name.clone(),
+        MalType::Lambda(_) => Symbol::new("memoized"),
+        non_fn => {
+            return Err(MalError::Normal(format!(
+                "`{:?}` is not a function",
+                non_fn
+            )))
+        }
+    };
+    let memo: RefCell<HashMap<Args, MalType>> = RefCell::new(HashMap::new());
+    Ok(MalType::Function(MalFunction::new(name, move |args| {
+        if !args.iter().all(by_value) {
+            return apply(&func, args);
+        }
+        let key = Args(args.clone());
+        if let Some(result) = memo.borrow().get(&key) {
+            return Ok(result.clone());
+        }
+        let result = apply(&func, args)?;
+        memo.borrow_mut().insert(key, result.clone());
+        Ok(result)
+    })))
+}
+
+pub fn memoize_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("memoize"), memoize)
+        .with_arglists("([f])")
+        .with_doc("Returns a function calling f, which caches the result of each call by its args.")
+        .with_arity(Arity::Fixed(1))
+}
+
+/// Maps a value to the keyword naming its type, used for protocol dispatch.
+/// A keyword under `:type` in the value's metadata overrides the default,
+/// except for a macro.
+pub fn find_type(obj: &MalType) -> Keyword {
+    if let MalType::Lambda(lambda) = obj {
+        if lambda.is_macro {
+            return Keyword::new(String::from("mal/macro"));
+        }
+    }
+    let type_key = MalAtom::Keyword(Keyword::new(String::from("type")));
+    if let Some(MalType::Atom(MalAtom::Keyword(type_name))) = meta::meta_of(obj)
+        .and_then(|meta| meta.as_ref())
+        .and_then(|meta| meta.get(&type_key))
+    {
+        return type_name.clone();
+    }
+    let name = match obj {
+        MalType::Atom(MalAtom::Symbol(_)) => "mal/symbol",
+        MalType::Atom(MalAtom::Keyword(_)) => "mal/keyword",
+        MalType::Atom(MalAtom::Nil) => "mal/nil",
+        MalType::Atom(MalAtom::Boolean(_)) => "mal/boolean",
+        MalType::Atom(MalAtom::Integer(_)) => "mal/number",
+        MalType::Atom(MalAtom::Str(_)) => "mal/string",
+        MalType::List(_) => "mal/list",
+        MalType::Vector(_) => "mal/vector",
+        MalType::Map(_) => "mal/map",
+        MalType::Function(_) | MalType::Lambda(_) | MalType::MultiFn(_) => "mal/function",
+        MalType::Protocol(_) => "mal/protocol",
+        MalType::Ref(_) => "mal/atom",
+        MalType::Pattern(_) => "mal/pattern",
+        MalType::LazySeq(_) => "mal/lazy-seq",
+        MalType::Transient(_) => "mal/transient",
+        MalType::Record(r) => return r.record_type.keyword(),
+    };
+    Keyword::new(name.to_string())
+}
+
+/// Builds the function for a protocol method, dispatching on the type of its
+/// first arg.
+pub fn protocol_method(name: Symbol, protocol: &Protocol) -> MalFunction {
+    let protocol = protocol.clone();
+    let method_key = MalAtom::Keyword(Keyword::new(name.get_name()));
+    MalFunction::new(name.clone(), move |args| {
+        let type_name = find_type(&args[0]);
+        let method_impl = protocol
+            .impls
+            .borrow()
+            .get(&type_name)
+            .and_then(|methods| methods.get(&method_key).cloned());
+        match method_impl {
+            Some(method_impl) => apply(&method_impl, args),
+            None => Err(MalError::Normal(format!(
+                "No implementation of method {} of protocol {} for type {}",
+                name, protocol.name, type_name,
+            ))),
+        }
+    })
+    .with_arity(Arity::Variadic(1))
+}
+
+fn find_type_native(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [obj] => Ok(MalType::Atom(MalAtom::Keyword(find_type(obj)))),
+        _ => Err(MalError::Normal(format!(
+            "Wrong number of args for find-type. Need 1, received {}",
+            args.len(),
+        ))),
+    }
+}
+
+pub fn find_type_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("find-type"), find_type_native)
+        .with_arglists("([x])")
+        .with_doc("Returns the keyword naming the type of x that protocols dispatch on, which a :type keyword in its metadata overrides.")
+}
+
+/// `(extend type protocol methods & more)` registers the method map for
+/// `type` on each protocol given.
+fn extend(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let type_name = match args.first() {
+        Some(MalType::Atom(MalAtom::Keyword(k))) => k.clone(),
+        non_kw => {
+            return Err(MalError::Normal(format!(
+                "First arg to extend must be a keyword. Given {:?}",
+                non_kw,
+            )))
+        }
+    };
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return Err(MalError::Normal(String::from(
+            "extend needs a type followed by protocol and method map pairs",
+        )));
+    }
+    for pair in args[1..].chunks(2) {
+        match (&pair[0], &pair[1]) {
+            (MalType::Protocol(protocol), MalType::Map(methods)) => {
+                protocol
+                    .impls
+                    .borrow_mut()
+                    .insert(type_name.clone(), methods.value.clone());
+            }
+            (non_protocol, MalType::Map(_)) => {
+                return Err(MalError::Normal(format!(
+                    "`{:?}` is not a protocol",
+                    non_protocol
+                )))
+            }
+            (_, non_map) => {
+                return Err(MalError::Normal(format!(
+                    "Protocol methods must be a map. Given {:?}",
+                    non_map,
+                )))
+            }
+        }
+    }
+    Ok(MalType::Atom(MalAtom::Nil))
+}
+
+pub fn extend_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("extend"), extend)
+        .with_arglists("([type protocol methods & more])")
+        .with_doc("Implements each protocol for the type keyword with its map of method names to functions.")
+}
+
+fn satisfies(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [MalType::Protocol(protocol), obj] => Ok(MalType::Atom(MalAtom::Boolean(
+            protocol.impls.borrow().contains_key(&find_type(obj)),
+        ))),
+        [non_protocol, _] => Err(MalError::Normal(format!(
+            "`{:?}` is not a protocol",
+            non_protocol
+        ))),
+        _ => Err(MalError::Normal(format!(
+            "Wrong number of args for satisfies?. Need 2, received {}",
+            args.len(),
+        ))),
+    }
+}
+
+pub fn satisfies_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("satisfies?"), satisfies)
+        .with_arglists("([protocol x])")
+        .with_doc("Returns true if protocol is implemented for the type of x.")
+}
+
+/// Lays out a sequence one element per line, aligned after its opening
+/// bracket.
+fn pprint_seq<'a>(seq: impl IntoIterator<Item = &'a MalType>, indent: usize) -> String {
+    let xindent = indent + 1;
+    seq.into_iter()
+        .enumerate()
+        .map(|(idx, x)| {
+            if idx == 0 {
+                pprint_form(x, 0)
+            } else {
+                format!("\n{}{}", " ".repeat(xindent), pprint_form(x, xindent))
+            }
+        })
+        .collect()
+}
+
+/// Lays out a map one entry per line, with values aligned after the width of
+/// the first key.
+fn pprint_map(map: &[(&MalAtom, &MalType)], indent: usize) -> String {
+    let kindent = indent + 1;
+    let kwidth = map
+        .first()
+        .map_or(0, |(k, _)| k.to_string().chars().count());
+    let vindent = 1 + kwidth + kindent;
+    map.iter()
+        .enumerate()
+        .map(|(idx, (k, v))| {
+            if idx == 0 {
+                format!("{} {}", k, pprint_form(v, vindent))
+            } else {
+                format!("\n{}{} {}", " ".repeat(kindent), k, pprint_form(v, vindent))
+            }
+        })
+        .collect()
+}
+
+pub fn pprint_form(obj: &MalType, indent: usize) -> String {
+    match obj {
+        MalType::List(l) => format!("({})", pprint_seq(l, indent)),
+        MalType::Vector(v) => format!("[{}]", pprint_seq(v, indent)),
+        MalType::Map(m) => format!("{{{}}}", pprint_map(&m.iter().collect::<Vec<_>>(), indent)),
+        MalType::Record(r) => {
+            let name = format!("#{}", r.record_type.name);
+            let entries = r.ordered_entries();
+            let entries: Vec<_> = entries.iter().map(|(k, v)| (k, v)).collect();
+            format!("{}{{{}}}", name, pprint_map(&entries, indent + name.len()))
+        }
+        _ => pr_str(obj),
+    }
+}
+
+fn pprint(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match args.as_slice() {
+        [obj] => {
+            dynamic::write_out(&format!("{}\n", pprint_form(obj, 0)))?;
+            Ok(MalType::Atom(MalAtom::Nil))
+        }
+        _ => Err(MalError::Normal(format!(
+            "Wrong number of args for pprint. Need 1, received {}",
+            args.len(),
+        ))),
+    }
+}
+
+pub fn pprint_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("pprint"), pprint)
+        .with_arglists("([x])")
+        .with_doc("Writes x to *out* with each element of a collection on its own line.")
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        inc_fn(),
+        dec_fn(),
+        is_zero_fn(),
+        identity_fn(),
+        gensym_fn(),
+        reduce_fn(),
+        foldr_fn(),
+        memoize_fn(),
+        find_type_fn(),
+        extend_fn(),
+        satisfies_fn(),
+        pprint_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{env::Env, interpreter::Interpreter, reader::read_str, test_util::rep_in};
+    use std::fs;
+
+    use super::pprint_form;
+
+    fn run_lib_tests(path: &str) {
+        let mut mal = Interpreter::new();
+        let tests = fs::read_to_string(path).expect("Something went wrong reading the file");
+        let mut inputs: Vec<String> = Vec::new();
+        for line in tests.lines() {
+            if let Some(expected) = line.strip_prefix(";=>") {
+                let results: Vec<String> = inputs
+                    .iter()
+                    .map(|i| {
+                        rep_in(i, &mut mal)
+                            .unwrap_or_else(|err| panic!("{} for input: {:?}", err, i))
+                    })
+                    .collect();
+                assert_eq!(
+                    Some(&expected.to_string()),
+                    results.last(),
+                    "for input: {:?}",
+                    inputs,
+                );
+                inputs.clear();
+            } else if !line.starts_with(';') && !line.trim().is_empty() {
+                inputs.push(line.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn trivial_lib() {
+        run_lib_tests("tests/lib/trivial.mal");
+    }
+
+    #[test]
+    fn reducers_lib() {
+        run_lib_tests("tests/lib/reducers.mal");
+    }
+
+    #[test]
+    fn threading_lib() {
+        run_lib_tests("tests/lib/threading.mal");
+    }
+
+    #[test]
+    fn load_file_once_lib() {
+        run_lib_tests("tests/lib/load-file-once.mal");
+    }
+
+    #[test]
+    fn equality_lib() {
+        run_lib_tests("tests/lib/equality.mal");
+    }
+
+    #[test]
+    fn protocols_lib() {
+        run_lib_tests("tests/lib/protocols.mal");
+    }
+
+    #[test]
+    fn equality_replacement() {
+        let mut mal = Interpreter::new();
+        assert_eq!(
+            "[\"equality.mal: Replaced = with the native implementation\\n\" true]",
+            rep_in(
+                "(def! = (fn* [a b] false)) (def! out (atom \"\")) \
+                 (binding [*out* out] (load-file \"../lib/equality.mal\")) \
+                 [@out (= [1] '(1))]",
+                &mut mal
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn memoize_lib() {
+        run_lib_tests("tests/lib/memoize.mal");
+    }
+
+    #[test]
+    fn pprint_lib() {
+        run_lib_tests("tests/lib/pprint.mal");
+    }
+
+    #[test]
+    fn bundled_paths() {
+        let mut mal = Interpreter::new();
+        assert_eq!(
+            "nil",
+            rep_in("(load-file \"../lib/trivial.mal\")", &mut mal).unwrap()
+        );
+        let dir = std::env::temp_dir().join("mal_bundled_paths");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("trivial.mal");
+        fs::write(&path, "(def! loaded true)").unwrap();
+        rep_in(
+            &format!("(load-file {:?})", path.to_str().unwrap()),
+            &mut mal,
+        )
+        .unwrap();
+        assert_eq!("true", rep_in("loaded", &mut mal).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(rep_in("(load-file \"/nonexistent/trivial.mal\")", &mut mal).is_err());
+        // Without the built-ins, the library file itself is loaded, and
+        // fails for want of them.
+        assert!(rep_in(
+            "(load-file \"../lib/trivial.mal\")",
+            &mut Interpreter::with_env(Env::new())
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn bool_and_or() {
+        let mut mal = Interpreter::new();
+        assert_eq!(
+            "[true false true false]",
+            rep_in(
+                "[(bool-and) (bool-and 1 nil (undefined)) (bool-or nil 1) (bool-or nil false)]",
+                &mut mal
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn cond_and_when() {
+        let mut mal = Interpreter::new();
+        assert_eq!(
+            "[2 nil nil [1 2]]",
+            rep_in(
+                "[(cond false 1 true 2 (undefined) 3) (cond false 1) (when false (undefined)) \
+                 (when true 1 [1 2])]",
+                &mut mal
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn memoize() {
+        let mut mal = Interpreter::new();
+        rep_in(
+            "(def! fib (fn* [n] (if (<= n 1) n (+ (fib (- n 1)) (fib (- n 2))))))",
+            &mut mal,
+        )
+        .unwrap();
+        rep_in("(def! fib (memoize fib))", &mut mal).unwrap();
+        assert_eq!("1548008755920", rep_in("(fib 60)", &mut mal).unwrap());
+        rep_in("(def! call-with-1 (memoize (fn* [f] (f 1))))", &mut mal).unwrap();
+        assert_eq!(
+            "[2 0]",
+            rep_in(
+                "[(call-with-1 (fn* [x] (+ x 1))) (call-with-1 (fn* [x] (- x 1)))]",
+                &mut mal
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn protocols() {
+        let mut mal = Interpreter::new();
+        rep_in("(defprotocol p1 [m0 [this]] [ma [this a]])", &mut mal).unwrap();
+        rep_in("(defprotocol p2)", &mut mal).unwrap();
+        assert_eq!("false", rep_in("(satisfies? p1 [1])", &mut mal).unwrap());
+        rep_in(
+            "(extend :mal/vector p1 {:m0 (fn* [this] (str \"v0\" this)) :ma (fn* [this a] (str \"va\" this a))} p2 {})",
+            &mut mal,
+        ).unwrap();
+        assert_eq!("true", rep_in("(satisfies? p1 [1])", &mut mal).unwrap());
+        assert_eq!("true", rep_in("(satisfies? p2 [1])", &mut mal).unwrap());
+        assert_eq!("false", rep_in("(satisfies? p1 '(1))", &mut mal).unwrap());
+        assert_eq!("\"v0[1]\"", rep_in("(m0 [1])", &mut mal).unwrap());
+        assert_eq!(
+            "\"va[1]blue\"",
+            rep_in("(ma [1] \"blue\")", &mut mal).unwrap()
+        );
+        assert_eq!(":mal/function", rep_in("(find-type m0)", &mut mal).unwrap());
+        assert_eq!(
+            "[:t1 :t1 \"v0[1]\"]",
+            rep_in(
+                "[(find-type ^{:type :t1} '(1)) (find-type (with-meta + {:type :t1})) \
+                  (m0 ^{:type \"not a keyword\"} [1])]",
+                &mut mal
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn pprint() {
+        let form = &read_str("(7 8 {:def {:jkl [789 \"ten\"]}} 9 [11 [13 14]])").unwrap()[0];
+        assert_eq!(
+            "(7\n 8\n {:def {:jkl [789\n              \"ten\"]}}\n 9\n [11\n  [13\n   14]])",
+            pprint_form(form, 0)
+        );
+    }
+}
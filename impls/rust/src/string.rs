@@ -0,0 +1,369 @@
+//! String built-ins. Indexes and lengths count Unicode scalar values, as
+//! `count` does for strings, not bytes. Splitting and replacing, which also
+//! take patterns, are in `pattern`.
+
+use std::{fmt::Write, iter::Peekable, str::Chars};
+
+use crate::{
+    built_ins::as_seq,
+    convert::FromMal,
+    printer::print_str,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType},
+};
+
+fn string(s: String) -> MalType {
+    MalType::Atom(MalAtom::Str(s))
+}
+
+fn nil() -> MalType {
+    MalType::Atom(MalAtom::Nil)
+}
+
+/// The byte offset of the `index`th char of `s`, which may be one past the
+/// last char.
+fn byte_offset(s: &str, index: isize) -> Result<usize, MalError> {
+    let out_of_bounds = || {
+        MalError::Normal(format!(
+            "String index {} out of bounds for string of length {}",
+            index,
+            s.chars().count(),
+        ))
+    };
+    if index < 0 {
+        return Err(out_of_bounds());
+    }
+    s.char_indices()
+        .map(|(offset, _)| offset)
+        .chain(Some(s.len()))
+        .nth(index as usize)
+        .ok_or_else(out_of_bounds)
+}
+
+/// `(subs s start)` or `(subs s start end)`.
+fn subs(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut args = args.into_iter();
+    let s = String::from_mal(args.next().unwrap())?;
+    let start = byte_offset(&s, isize::from_mal(args.next().unwrap())?)?;
+    let end = match args.next() {
+        Some(end) => byte_offset(&s, isize::from_mal(end)?)?,
+        None => s.len(),
+    };
+    if end < start {
+        return Err(MalError::Normal(format!(
+            "subs end {} is before its start",
+            s[..end].chars().count()
+        )));
+    }
+    Ok(string(s[start..end].to_string()))
+}
+
+pub fn subs_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("subs"), subs)
+        .with_arglists("([s start] [s start end])")
+        .with_doc(
+            "Returns the chars of s from index start up to but not including end, or its end.",
+        )
+        .with_arity(Arity::Range(2, 3))
+}
+
+/// `(join xs)` or `(join sep xs)` prints each of `xs` as `str` does.
+fn join(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let (sep, xs) = match args.as_slice() {
+        [xs] => (String::new(), xs),
+        [sep, xs] => (String::from_mal(sep.clone())?, xs),
+        _ => unreachable!(),
+    };
+    Ok(string(
+        as_seq(xs)?
+            .iter()
+            .map(print_str)
+            .collect::<Vec<String>>()
+            .join(&sep),
+    ))
+}
+
+pub fn join_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("join"), join)
+        .with_arglists("([coll] [sep coll])")
+        .with_doc("Returns the elements of coll printed as str does, separated by sep.")
+        .with_arity(Arity::Range(1, 2))
+}
+
+fn str_fn(name: &str, f: fn(&str) -> String) -> MalFunction {
+    MalFunction::typed(Symbol::new(name), move |s: String| f(&s))
+}
+
+fn predicate_fn(name: &str, f: fn(&str, &str) -> bool) -> MalFunction {
+    MalFunction::typed(Symbol::new(name), move |s: String, sub: String| f(&s, &sub))
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn char_index(s: &str, byte_offset: usize) -> MalType {
+    MalType::Atom(MalAtom::Integer(s[..byte_offset].chars().count() as isize))
+}
+
+/// `(index-of s sub)` or `(index-of s sub from)`, nil if `sub` isn't found.
+fn index_of(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut args = args.into_iter();
+    let s = String::from_mal(args.next().unwrap())?;
+    let sub = String::from_mal(args.next().unwrap())?;
+    let from = match args.next() {
+        Some(from) => byte_offset(&s, isize::from_mal(from)?)?,
+        None => 0,
+    };
+    Ok(s[from..]
+        .find(&sub)
+        .map_or_else(nil, |offset| char_index(&s, from + offset)))
+}
+
+pub fn index_of_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("index-of"), index_of)
+        .with_arglists("([s sub] [s sub from])")
+        .with_doc("Returns the index of the first sub in s, starting from index from, or nil if there is none.")
+        .with_arity(Arity::Range(2, 3))
+}
+
+fn last_index_of(s: String, sub: String) -> MalType {
+    s.rfind(&sub)
+        .map_or_else(nil, |offset| char_index(&s, offset))
+}
+
+/// `(char-at s i)` returns the `i`th char as a one char string.
+fn char_at(s: String, index: isize) -> Result<String, MalError> {
+    let start = byte_offset(&s, index)?;
+    s[start..].chars().next().map(String::from).ok_or_else(|| {
+        MalError::Normal(format!(
+            "String index {} out of bounds for string of length {}",
+            index,
+            s.chars().count(),
+        ))
+    })
+}
+
+/// A `%` directive of `format`: flags, width, precision and conversion.
+struct Spec {
+    left_align: bool,
+    zero_pad: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+fn parse_number(chars: &mut Peekable<Chars>) -> usize {
+    let mut n = 0;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = n * 10 + digit as usize;
+        chars.next();
+    }
+    n
+}
+
+fn parse_spec(chars: &mut Peekable<Chars>) -> Result<Spec, MalError> {
+    let mut spec = Spec {
+        left_align: false,
+        zero_pad: false,
+        width: 0,
+        precision: None,
+        conversion: 's',
+    };
+    while let Some(flag @ ('-' | '0')) = chars.peek().copied() {
+        spec.left_align |= flag == '-';
+        spec.zero_pad |= flag == '0';
+        chars.next();
+    }
+    spec.width = parse_number(chars);
+    if chars.peek() == Some(&'.') {
+        chars.next();
+        spec.precision = Some(parse_number(chars));
+    }
+    spec.conversion = chars
+        .next()
+        .ok_or_else(|| MalError::Normal("format string ends in %".to_string()))?;
+    Ok(spec)
+}
+
+fn integer_arg(spec: &Spec, arg: &MalType) -> Result<isize, MalError> {
+    match arg {
+        MalType::Atom(MalAtom::Integer(num)) => Ok(*num),
+        other => Err(MalError::Normal(format!(
+            "`{:?}` is not a number, as %{} needs",
+            other, spec.conversion
+        ))),
+    }
+}
+
+fn pad(spec: &Spec, text: String) -> String {
+    let len = text.chars().count();
+    if len >= spec.width {
+        return text;
+    }
+    let fill = spec.width - len;
+    if spec.left_align {
+        format!("{}{}", text, " ".repeat(fill))
+    } else if spec.zero_pad && spec.conversion != 's' {
+        match text.strip_prefix('-') {
+            Some(digits) => format!("-{}{}", "0".repeat(fill), digits),
+            None => format!("{}{}", "0".repeat(fill), text),
+        }
+    } else {
+        format!("{}{}", " ".repeat(fill), text)
+    }
+}
+
+/// `(format fmt args...)` supports `%s`, printing any value as `str` does,
+/// `%d`, `%x` and `%f` for integers, and `%%`. Each may have the flags `-`
+/// to left align or `0` to pad numbers with zeros, a width and, for `%s`
+/// and `%f`, a precision.
+fn format(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let mut args = args.into_iter();
+    let fmt = String::from_mal(args.next().unwrap())?;
+    let mut formatted = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            formatted.push(c);
+            continue;
+        }
+        let spec = parse_spec(&mut chars)?;
+        if spec.conversion == '%' {
+            formatted.push('%');
+            continue;
+        }
+        let arg = args.next().ok_or_else(|| {
+            MalError::Normal(format!("Not enough args for format string {:?}", fmt))
+        })?;
+        let text = match spec.conversion {
+            's' => {
+                let text = print_str(&arg);
+                match spec.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                }
+            }
+            'd' => integer_arg(&spec, &arg)?.to_string(),
+            'x' => format!("{:x}", integer_arg(&spec, &arg)?),
+            'f' => format!(
+                "{:.*}",
+                spec.precision.unwrap_or(6),
+                integer_arg(&spec, &arg)? as f64
+            ),
+            other => {
+                return Err(MalError::Normal(format!(
+                    "Unknown format conversion %{}",
+                    other
+                )))
+            }
+        };
+        write!(formatted, "{}", pad(&spec, text)).unwrap();
+    }
+    Ok(string(formatted))
+}
+
+pub fn format_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("format"), format)
+        .with_arglists("([fmt & args])")
+        .with_doc("Returns fmt with each % directive replaced by the next arg: %s for any value, %d, %x and %f for integers and %% for a %. Flags, width and precision are as in printf.")
+        .with_arity(Arity::Variadic(1))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        subs_fn(),
+        join_fn(),
+        str_fn("trim", |s| s.trim().to_string())
+            .with_arglists("([s])")
+            .with_doc("Returns s without whitespace at either end."),
+        str_fn("triml", |s| s.trim_start().to_string())
+            .with_arglists("([s])")
+            .with_doc("Returns s without whitespace at its start."),
+        str_fn("trimr", |s| s.trim_end().to_string())
+            .with_arglists("([s])")
+            .with_doc("Returns s without whitespace at its end."),
+        str_fn("upper-case", str::to_uppercase)
+            .with_arglists("([s])")
+            .with_doc("Returns s in upper case."),
+        str_fn("lower-case", str::to_lowercase)
+            .with_arglists("([s])")
+            .with_doc("Returns s in lower case."),
+        str_fn("capitalize", capitalize)
+            .with_arglists("([s])")
+            .with_doc("Returns s with its first char in upper case and the rest in lower case."),
+        predicate_fn("starts-with?", |s, sub| s.starts_with(sub))
+            .with_arglists("([s sub])")
+            .with_doc("Returns true if s starts with sub."),
+        predicate_fn("ends-with?", |s, sub| s.ends_with(sub))
+            .with_arglists("([s sub])")
+            .with_doc("Returns true if s ends with sub."),
+        predicate_fn("includes?", |s, sub| s.contains(sub))
+            .with_arglists("([s sub])")
+            .with_doc("Returns true if s contains sub."),
+        index_of_fn(),
+        MalFunction::typed(Symbol::new("last-index-of"), last_index_of)
+            .with_arglists("([s sub])")
+            .with_doc("Returns the index of the last sub in s, or nil if there is none."),
+        MalFunction::typed(Symbol::new("char-at"), char_at)
+            .with_arglists("([s i])")
+            .with_doc("Returns the char of s at index i as a string."),
+        format_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            "[\"éa\" \"b\" 4 \"a,:b,1\" \"ab\" \"x \" \" x\"]",
+            rep(
+                "[(subs \"héab\" 1 3) (subs \"héab\" 3) (count \"héab\") (join \",\" [\"a\" :b 1]) \
+                  (join '(\"a\" \"b\")) (triml \" x \") (trimr \" x \")]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[\"ÉCOLE\" \"école\" \"Hello\" true false true]",
+            rep(
+                "[(upper-case \"école\") (lower-case \"ÉCOLE\") (capitalize \"hELLO\") \
+                  (starts-with? \"abc\" \"ab\") (ends-with? \"abc\" \"b\") (includes? \"abc\" \"b\")]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "[2 2 nil 4 \"é\"]",
+            rep(
+                "[(index-of \"héab\" \"a\") (index-of \"éaéa\" \"é\" 1) (index-of \"abc\" \"d\") \
+                  (last-index-of \"abcab\" \"b\") (char-at \"héab\" 1)]"
+            )
+            .unwrap()
+        );
+        assert!(rep("(subs \"abc\" 2 4)").is_err());
+        assert!(rep("(subs \"abc\" 2 1)").is_err());
+        assert!(rep("(char-at \"abc\" 3)").is_err());
+        assert!(rep("(upper-case 1)").is_err());
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(
+            "\"a=:k   |  42|-0042|ff|3.00|abc|100%\"",
+            rep("(format \"a=%-5s|%4d|%05d|%x|%.2f|%.3s|%d%%\" :k 42 -42 255 3 \"abcdef\" 100)")
+                .unwrap()
+        );
+        assert!(rep("(format \"%d\" \"a\")").is_err());
+        assert!(rep("(format \"%d %d\" 1)").is_err());
+        assert!(rep("(format \"%q\" 1)").is_err());
+    }
+}
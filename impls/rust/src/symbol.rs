@@ -1,22 +1,48 @@
-use std::fmt;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+use crate::types::Meta;
+
+#[derive(Clone, Debug)]
 pub struct Symbol {
     pub name: String,
+    pub meta: Meta,
 }
 
 impl Symbol {
     pub fn new(name: &str) -> Self {
         Symbol {
             name: name.to_string(),
+            meta: None,
         }
     }
 
+    pub fn with_meta(self, meta: Meta) -> Self {
+        Symbol { meta, ..self }
+    }
+
     pub fn get_name(&self) -> String {
         self.name.clone()
     }
 }
 
+/// Symbols are equal by name, whatever their metadata.
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
+}
+
 impl fmt::Display for Symbol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.name)
@@ -32,7 +58,8 @@ mod tests {
         assert_eq!(
             "a",
             Symbol {
-                name: String::from("a")
+                name: String::from("a"),
+                meta: None,
             }
             .to_string()
         );
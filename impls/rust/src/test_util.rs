@@ -0,0 +1,32 @@
+//! Helpers for the unit tests, which evaluate mal source and compare what
+//! its value prints as.
+
+use crate::{
+    interpreter::Interpreter,
+    printer::pr_str,
+    types::{MalAtom, MalError, MalType},
+};
+
+/// Evaluates `input` in a new interpreter, printing the value of its last
+/// form.
+pub fn rep(input: &str) -> Result<String, MalError> {
+    rep_in(input, &mut Interpreter::new())
+}
+
+/// Evaluates `input` in `mal`, printing the value of its last form, so a
+/// test can go on to evaluate more in the same environment.
+pub fn rep_in(input: &str, mal: &mut Interpreter) -> Result<String, MalError> {
+    mal.eval_str(input).map(|value| pr_str(&value))
+}
+
+/// What evaluating `input` in a new REPL environment writes to `*out*`.
+pub fn out(input: &str) -> Result<String, MalError> {
+    let input = format!(
+        "(def! out (atom \"\")) (binding [*out* out] {}) (deref out)",
+        input
+    );
+    match Interpreter::new().eval_str(&input)? {
+        MalType::Atom(MalAtom::Str(out)) => Ok(out),
+        other => panic!("*out* holds {}", other),
+    }
+}
@@ -0,0 +1,332 @@
+//! Transients, vectors and maps changed in place to build them up quickly.
+//! `(transient coll)` makes one from a vector or map, `conj!`, `assoc!`,
+//! `dissoc!` and `pop!` change it and return it, and `(persistent! t)`
+//! returns the finished collection. Using a transient after `persistent!` is
+//! an error.
+//!
+//! Persistent collections copy a node only when it's shared, so a transient
+//! copies each node its changes touch once and edits it in place from then
+//! on. `persistent!` hands the collection back without copying anything.
+
+use std::{cell::RefCell, mem, rc::Rc};
+
+use crate::{
+    record::{Record, RecordType},
+    seq::map_key,
+    symbol::Symbol,
+    types::{Arity, MalAtom, MalError, MalFunction, MalType, Map, Vector, WithMeta},
+};
+
+/// A vector or map being edited in place. The sequence library builds its
+/// results with these directly, and edits records as maps that keep their
+/// type.
+pub enum Editable {
+    Vector(Vector),
+    Map(Map),
+    Record(Rc<RecordType>, Map),
+}
+
+impl Editable {
+    /// Starts editing `coll`, where nil is an empty map.
+    pub fn new(coll: MalType) -> Result<Self, MalError> {
+        match coll {
+            MalType::Vector(v) => Ok(Editable::Vector(v.value)),
+            MalType::Map(m) => Ok(Editable::Map(m.value)),
+            MalType::Record(r) => Ok(Editable::Record(r.record_type, r.entries)),
+            MalType::Atom(MalAtom::Nil) => Ok(Editable::Map(Map::new())),
+            other => Err(MalError::Normal(format!(
+                "`{:?}` is not a vector or map",
+                other
+            ))),
+        }
+    }
+
+    /// Adds `x` to the end of a vector. A map takes a `[key value]` pair or
+    /// every entry of another map.
+    pub fn conj(&mut self, x: MalType) -> Result<(), MalError> {
+        match (self, x) {
+            (Editable::Vector(v), x) => v.push_back(x),
+            (
+                Editable::Map(m) | Editable::Record(_, m),
+                MalType::Map(WithMeta { value: entries, .. })
+                | MalType::Record(Record { entries, .. }),
+            ) => m.extend(entries),
+            (Editable::Map(m) | Editable::Record(_, m), entry) => {
+                match entry.items().map(Iterator::collect::<Vec<_>>) {
+                    Some(kv) if kv.len() == 2 => {
+                        m.insert(map_key(kv[0])?, kv[1].clone());
+                    }
+                    _ => {
+                        return Err(MalError::Normal(format!(
+                            "`{:?}` is not a map entry",
+                            entry
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets `key` to `value`. A vector may be extended by setting the index
+    /// one past its end.
+    pub fn assoc(&mut self, key: &MalType, value: MalType) -> Result<(), MalError> {
+        match (self, key) {
+            (Editable::Map(m) | Editable::Record(_, m), key) => {
+                m.insert(map_key(key)?, value);
+            }
+            (Editable::Vector(v), MalType::Atom(MalAtom::Integer(i)))
+                if *i >= 0 && *i as usize <= v.len() =>
+            {
+                if *i as usize == v.len() {
+                    v.push_back(value);
+                } else {
+                    v.set(*i as usize, value);
+                }
+            }
+            (Editable::Vector(v), key) => {
+                return Err(MalError::Normal(format!(
+                    "assoc index `{:?}` out of bounds for vector of length {}",
+                    key,
+                    v.len()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes `key`. A record without one of its fields becomes a map.
+    pub fn dissoc(&mut self, key: &MalType) -> Result<(), MalError> {
+        let key = map_key(key)?;
+        match self {
+            Editable::Map(m) => {
+                m.remove(&key);
+                Ok(())
+            }
+            Editable::Record(record_type, m) => {
+                m.remove(&key);
+                if record_type.has_field(&key) {
+                    *self = Editable::Map(mem::take(m));
+                }
+                Ok(())
+            }
+            Editable::Vector(_) => {
+                Err(MalError::Normal(String::from("Can't dissoc from a vector")))
+            }
+        }
+    }
+
+    /// Removes the last element of a vector.
+    pub fn pop(&mut self) -> Result<(), MalError> {
+        match self {
+            Editable::Vector(v) => match v.pop_back() {
+                Some(_) => Ok(()),
+                None => Err(MalError::Normal(String::from("Can't pop an empty vector"))),
+            },
+            Editable::Map(_) | Editable::Record(..) => {
+                Err(MalError::Normal(String::from("Can't pop a map")))
+            }
+        }
+    }
+
+    pub fn into_value(self) -> MalType {
+        match self {
+            Editable::Vector(v) => MalType::Vector(v.into()),
+            Editable::Map(m) => MalType::Map(m.into()),
+            Editable::Record(record_type, m) => MalType::Record(Record::new(record_type, m)),
+        }
+    }
+}
+
+/// A collection being edited, made by `transient`. It's emptied by
+/// `persistent!`, after which it can't be used. A transient is only equal to
+/// itself.
+#[derive(Clone)]
+pub struct Transient(Rc<RefCell<Option<Editable>>>);
+
+impl Transient {
+    pub fn new(coll: Editable) -> Self {
+        Transient(Rc::new(RefCell::new(Some(coll))))
+    }
+
+    /// Applies `change` to the collection, for the function `name`.
+    fn edit(
+        &self,
+        name: &str,
+        change: impl FnOnce(&mut Editable) -> Result<(), MalError>,
+    ) -> Result<(), MalError> {
+        match self.0.borrow_mut().as_mut() {
+            Some(coll) => change(coll),
+            None => Err(used_after_persistent(name)),
+        }
+    }
+
+    /// Takes the collection out, leaving the transient unusable.
+    pub fn persistent(&self) -> Result<MalType, MalError> {
+        self.0
+            .borrow_mut()
+            .take()
+            .map(Editable::into_value)
+            .ok_or_else(|| used_after_persistent("persistent!"))
+    }
+}
+
+impl PartialEq for Transient {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+fn used_after_persistent(name: &str) -> MalError {
+    MalError::Normal(format!("{} called on a transient after persistent!", name))
+}
+
+fn as_transient<'a>(name: &str, value: &'a MalType) -> Result<&'a Transient, MalError> {
+    match value {
+        MalType::Transient(t) => Ok(t),
+        other => Err(MalError::Normal(format!(
+            "{} needs a transient. Given {}",
+            name, other
+        ))),
+    }
+}
+
+fn transient(args: Vec<MalType>) -> Result<MalType, MalError> {
+    match &args[0] {
+        MalType::Vector(_) | MalType::Map(_) => Ok(MalType::Transient(Transient::new(
+            Editable::new(args[0].clone())?,
+        ))),
+        other => Err(MalError::Normal(format!(
+            "transient needs a vector or map. Given {}",
+            other
+        ))),
+    }
+}
+
+pub fn transient_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("transient"), transient)
+        .with_arglists("([coll])")
+        .with_doc(
+            "Returns a transient copy of the vector or map coll, which can be edited in place.",
+        )
+        .with_arity(Arity::Fixed(1))
+}
+
+/// `(conj! t x & xs)`.
+fn conj(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let t = as_transient("conj!", &args[0])?;
+    t.edit("conj!", |coll| {
+        args[1..].iter().try_for_each(|x| coll.conj(x.clone()))
+    })?;
+    Ok(args[0].clone())
+}
+
+pub fn conj_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("conj!"), conj)
+        .with_arglists("([t x & xs])")
+        .with_doc("Adds each x to the transient t, returning t.")
+        .with_arity(Arity::Variadic(2))
+}
+
+/// `(assoc! t k v & kvs)`.
+fn assoc(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let t = as_transient("assoc!", &args[0])?;
+    if !(args.len() - 1).is_multiple_of(2) {
+        return Err(MalError::Normal(
+            "assoc! needs a value for every key".to_string(),
+        ));
+    }
+    t.edit("assoc!", |coll| {
+        args[1..]
+            .chunks(2)
+            .try_for_each(|kv| coll.assoc(&kv[0], kv[1].clone()))
+    })?;
+    Ok(args[0].clone())
+}
+
+pub fn assoc_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("assoc!"), assoc)
+        .with_arglists("([t key val & kvs])")
+        .with_doc("Sets each key of the transient t to its val, returning t.")
+        .with_arity(Arity::Variadic(3))
+}
+
+/// `(dissoc! t k & ks)`.
+fn dissoc(args: Vec<MalType>) -> Result<MalType, MalError> {
+    let t = as_transient("dissoc!", &args[0])?;
+    t.edit("dissoc!", |coll| {
+        args[1..].iter().try_for_each(|key| coll.dissoc(key))
+    })?;
+    Ok(args[0].clone())
+}
+
+pub fn dissoc_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("dissoc!"), dissoc)
+        .with_arglists("([t key & keys])")
+        .with_doc("Removes each key from the transient t, returning t.")
+        .with_arity(Arity::Variadic(2))
+}
+
+fn pop(args: Vec<MalType>) -> Result<MalType, MalError> {
+    as_transient("pop!", &args[0])?.edit("pop!", Editable::pop)?;
+    Ok(args[0].clone())
+}
+
+pub fn pop_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("pop!"), pop)
+        .with_arglists("([t])")
+        .with_doc("Removes the last element of the transient vector t, returning t.")
+        .with_arity(Arity::Fixed(1))
+}
+
+fn persistent(args: Vec<MalType>) -> Result<MalType, MalError> {
+    as_transient("persistent!", &args[0])?.persistent()
+}
+
+pub fn persistent_fn() -> MalFunction {
+    MalFunction::new(Symbol::new("persistent!"), persistent)
+        .with_arglists("([t])")
+        .with_doc("Returns the value of the transient t, after which t can't be used.")
+        .with_arity(Arity::Fixed(1))
+}
+
+pub fn all() -> Vec<MalFunction> {
+    vec![
+        transient_fn(),
+        conj_fn(),
+        assoc_fn(),
+        dissoc_fn(),
+        pop_fn(),
+        persistent_fn(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::rep;
+
+    #[test]
+    fn transients() {
+        assert_eq!(
+            "[[1 2] [1 :x 3 4] [1]]",
+            rep("(def! v [1 2]) \
+                 (def! t (transient v)) \
+                 [v (persistent! (conj! (assoc! t 1 :x) 3 4)) \
+                  (persistent! (pop! (transient [1 2])))]")
+            .unwrap()
+        );
+        assert_eq!(
+            "[true true]",
+            rep("(def! t (transient {:a 1 :b 2})) \
+                 (dissoc! t :a) (assoc! t :c 3) (conj! t [:d 4] {:e 5}) \
+                 [(= (persistent! t) {:b 2 :c 3 :d 4 :e 5}) (= t t)]")
+            .unwrap()
+        );
+        assert!(rep("(def! t (transient [])) (persistent! t) (conj! t 1)").is_err());
+        assert!(rep("(def! t (transient [])) (persistent! t) (persistent! t)").is_err());
+        assert!(rep("(pop! (transient []))").is_err());
+        assert!(rep("(dissoc! (transient [1]) 0)").is_err());
+        assert!(rep("(transient (list 1))").is_err());
+        assert!(rep("(conj! [] 1)").is_err());
+    }
+}
@@ -1,14 +1,179 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    iter::FromIterator,
+    mem,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
 
-use crate::{keyword::Keyword, symbol::Symbol};
+use regex::Regex;
 
+pub use crate::list::List;
+use crate::{
+    atom::Ref,
+    env::Env,
+    keyword::Keyword,
+    lazy::{self, LazySeq},
+    list,
+    multi::MultiFn,
+    record::Record,
+    symbol::Symbol,
+    transient::Transient,
+};
+
+/// A persistent vector: an RRB tree sharing structure between versions, so
+/// `conj`, `assoc` and slicing are O(log n) rather than copies.
+pub type Vector = im_rc::Vector<MalType>;
+
+/// A persistent hash map: a HAMT sharing structure between versions, so
+/// `assoc` and `dissoc` are O(log n) rather than copies.
+pub type Map = im_rc::HashMap<MalAtom, MalType>;
+
+/// A map of metadata, attached to a value without being part of it: `==`
+/// and hashing ignore it.
+pub type Meta = Option<Rc<Map>>;
+
+/// A collection with metadata. It derefs to the collection, so it's used as
+/// one, and a collection converts into it with no metadata.
+#[derive(Clone, Default)]
+pub struct WithMeta<T> {
+    pub value: T,
+    pub meta: Meta,
+}
+
+impl<T> WithMeta<T> {
+    pub fn with_meta(self, meta: Meta) -> Self {
+        WithMeta { meta, ..self }
+    }
+}
+
+impl<T> Deref for WithMeta<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for WithMeta<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for WithMeta<T> {
+    fn from(value: T) -> Self {
+        WithMeta { value, meta: None }
+    }
+}
+
+impl From<Vec<MalType>> for WithMeta<Vector> {
+    fn from(items: Vec<MalType>) -> Self {
+        WithMeta::from(Vector::from(items))
+    }
+}
+
+impl<T: FromIterator<A>, A> FromIterator<A> for WithMeta<T> {
+    fn from_iter<I: IntoIterator<Item = A>>(iter: I) -> Self {
+        WithMeta::from(T::from_iter(iter))
+    }
+}
+
+impl<T: IntoIterator> IntoIterator for WithMeta<T> {
+    type Item = T::Item;
+    type IntoIter = T::IntoIter;
+
+    fn into_iter(self) -> T::IntoIter {
+        self.value.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a WithMeta<T>
+where
+    &'a T: IntoIterator,
+{
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.value.into_iter()
+    }
+}
+
+impl<T: PartialEq> PartialEq for WithMeta<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for WithMeta<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// Collections are persistent, so cloning a value is cheap and shares it.
 #[derive(Clone, Debug)]
 pub enum MalType {
     Atom(MalAtom),
-    List(Vec<MalType>),
-    Vector(Vec<MalType>),
-    Map(HashMap<MalAtom, MalType>),
+    List(List),
+    Vector(WithMeta<Vector>),
+    Map(WithMeta<Map>),
     Function(MalFunction),
+    Lambda(MalLambda),
+    Protocol(Protocol),
+    /// A mutable reference, made by `atom`.
+    Ref(Ref),
+    /// A compiled regular expression, made by `re-pattern`.
+    Pattern(Regex),
+    /// A sequence realised on demand, made by `lazy-seq` and the lazy
+    /// sequence functions.
+    LazySeq(LazySeq),
+    /// A vector or map edited in place, made by `transient`.
+    Transient(Transient),
+    /// A map with a type made by `defrecord`.
+    Record(Record),
+    /// A function dispatching to methods by value, made by `defmulti`.
+    MultiFn(MultiFn),
+}
+
+impl MalType {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(
+            self,
+            MalType::Atom(MalAtom::Nil) | MalType::Atom(MalAtom::Boolean(false))
+        )
+    }
+
+    /// Iterates over the elements of a list or vector, or returns `None` for
+    /// any other value.
+    pub fn items(&self) -> Option<Items<'_>> {
+        match self {
+            MalType::List(l) => Some(Items::List(l.iter())),
+            MalType::Vector(v) => Some(Items::Vector(v.iter())),
+            _ => None,
+        }
+    }
+}
+
+/// The elements of a list or vector, from `MalType::items`.
+pub enum Items<'a> {
+    List(list::Iter<'a>),
+    Vector(im_rc::vector::Iter<'a, MalType>),
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = &'a MalType;
+
+    fn next(&mut self) -> Option<&'a MalType> {
+        match self {
+            Items::List(iter) => iter.next(),
+            Items::Vector(iter) => iter.next(),
+        }
+    }
 }
 
 impl PartialEq for MalType {
@@ -17,12 +182,87 @@ impl PartialEq for MalType {
             (MalType::Atom(s), MalType::Atom(o)) => s == o,
             (MalType::List(s), MalType::List(o)) => s == o,
             (MalType::Vector(s), MalType::Vector(o)) => s == o,
+            (MalType::List(l), MalType::Vector(v)) | (MalType::Vector(v), MalType::List(l)) => {
+                l.len() == v.len() && l.iter().eq(v.iter())
+            }
             (MalType::Map(s), MalType::Map(o)) => s == o,
+            (MalType::Record(s), MalType::Record(o)) => s == o,
+            (MalType::Ref(s), MalType::Ref(o)) => s == o,
+            (MalType::Transient(s), MalType::Transient(o)) => s == o,
+            (MalType::MultiFn(s), MalType::MultiFn(o)) => s == o,
+            (MalType::Pattern(s), MalType::Pattern(o)) => s.as_str() == o.as_str(),
+            (MalType::LazySeq(_), MalType::List(_) | MalType::Vector(_) | MalType::LazySeq(_))
+            | (MalType::List(_) | MalType::Vector(_), MalType::LazySeq(_)) => {
+                lazy::seq_eq(self, other)
+            }
             _ => false,
         }
     }
 }
 
+/// Hashes agree with `==`: lists and vectors with the same items hash
+/// alike, maps whatever their order, and values only equal to themselves,
+/// like functions and atoms, by their kind alone.
+impl Hash for MalType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            MalType::Atom(atom) => atom.hash(state),
+            MalType::List(_) | MalType::Vector(_) => {
+                state.write_u8(0);
+                self.items().unwrap().for_each(|item| item.hash(state));
+            }
+            MalType::LazySeq(seq) => {
+                state.write_u8(0);
+                seq.iter().flatten().for_each(|item| item.hash(state));
+            }
+            MalType::Map(m) => hash_entries(m, state),
+            MalType::Record(r) => {
+                r.record_type.name.hash(state);
+                hash_entries(&r.entries, state);
+            }
+            other => mem::discriminant(other).hash(state),
+        }
+    }
+}
+
+/// Hashes the entries of a map whatever their order.
+fn hash_entries<H: Hasher>(m: &Map, state: &mut H) {
+    m.iter()
+        .map(|entry| {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            hasher.finish()
+        })
+        .fold(0, u64::wrapping_add)
+        .hash(state)
+}
+
+/// The args of a memoized call, compared and hashed by value.
+#[derive(PartialEq, Hash)]
+pub struct Args(pub Vec<MalType>);
+
+/// Only args `by_value` are cached, so `==` is an equivalence on the keys.
+impl Eq for Args {}
+
+/// A value compared and hashed by value, as `distinct` tracks what it's seen.
+#[derive(PartialEq, Hash)]
+pub struct ByValue(pub MalType);
+
+/// Only values `by_value` are wrapped, so `==` is an equivalence on them.
+impl Eq for ByValue {}
+
+/// Whether `value` is equal to itself, which functions and other values
+/// with no equality of their own aren't.
+pub fn by_value(value: &MalType) -> bool {
+    match value {
+        MalType::Atom(_) | MalType::Ref(_) | MalType::Pattern(_) => true,
+        MalType::List(_) | MalType::Vector(_) => value.items().unwrap().all(by_value),
+        MalType::Map(m) => m.values().all(by_value),
+        MalType::Record(r) => r.entries.values().all(by_value),
+        _ => false,
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum MalAtom {
     Symbol(Symbol),
@@ -33,22 +273,160 @@ pub enum MalAtom {
     Nil,
 }
 
+/// The number of args a native function accepts, checked before it's called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    Fixed(usize),
+    Range(usize, usize),
+    Variadic(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Fixed(n) => count == n,
+            Arity::Range(min, max) => (min..=max).contains(&count),
+            Arity::Variadic(min) => count >= min,
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(Vec<MalType>) -> Result<MalType, MalError>;
+
+/// A function implemented in Rust. Plain `fn`s and closures over host state
+/// are both accepted.
 #[derive(Clone)]
 pub struct MalFunction {
     pub name: Symbol,
-    f: fn(Vec<MalType>) -> Result<MalType, MalError>,
+    pub arity: Arity,
+    /// The printed param vectors of each arity, like `([x] [x & more])`.
+    pub arglists: Option<Rc<str>>,
+    pub doc: Option<Rc<str>>,
+    pub meta: Meta,
+    f: Rc<NativeFn>,
 }
 
 impl MalFunction {
-    pub fn new(name: Symbol, f: fn(Vec<MalType>) -> Result<MalType, MalError>) -> Self {
-        MalFunction { name, f }
+    pub fn new<F>(name: Symbol, f: F) -> Self
+    where
+        F: Fn(Vec<MalType>) -> Result<MalType, MalError> + 'static,
+    {
+        MalFunction {
+            name,
+            arity: Arity::Variadic(0),
+            arglists: None,
+            doc: None,
+            meta: None,
+            f: Rc::new(f),
+        }
+    }
+
+    pub fn with_arity(mut self, arity: Arity) -> Self {
+        self.arity = arity;
+        self
+    }
+
+    pub fn with_arglists(mut self, arglists: &str) -> Self {
+        self.arglists = Some(arglists.into());
+        self
+    }
+
+    pub fn with_doc(mut self, doc: &str) -> Self {
+        self.doc = Some(doc.into());
+        self
+    }
+
+    pub fn with_meta(mut self, meta: Meta) -> Self {
+        self.meta = meta;
+        self
     }
 
     pub fn invoke(&self, args: Vec<MalType>) -> Result<MalType, MalError> {
+        if !self.arity.accepts(args.len()) {
+            return Err(MalError::Normal(format!(
+                "Wrong number of args for {}. Need {}, received {}",
+                self.name,
+                self.arity,
+                args.len(),
+            )));
+        }
         (self.f)(args)
     }
 }
 
+/// One arity of a user function. A `&` before the last of its params
+/// collects any args past the others.
+#[derive(Clone)]
+pub struct Method {
+    /// Binding forms, destructured as the elements of a vector form are.
+    pub params: Vec<MalType>,
+    pub arity: Arity,
+    pub body: Rc<Vec<MalType>>,
+}
+
+impl Method {
+    pub fn new(params: Vec<MalType>, body: Vec<MalType>) -> Self {
+        let arity = match params
+            .iter()
+            .position(|param| matches!(param, MalType::Atom(MalAtom::Symbol(s)) if s.name == "&"))
+        {
+            Some(fixed) => Arity::Variadic(fixed),
+            None => Arity::Fixed(params.len()),
+        };
+        Method {
+            params,
+            arity,
+            body: Rc::new(body),
+        }
+    }
+}
+
+/// A user function created by `fn*`, closing over the environment it was
+/// defined in. A call runs the method taking exactly that many args or,
+/// failing that, the variadic one.
+#[derive(Clone)]
+pub struct MalLambda {
+    /// The name given to `fn*` or, failing that, the one it was `def!`ed as.
+    pub name: Option<Symbol>,
+    pub methods: Rc<Vec<Method>>,
+    pub env: Env,
+    /// Whether it was defined by `defmacro!`, so calls to it are expanded
+    /// with their args unevaluated.
+    pub is_macro: bool,
+    pub meta: Meta,
+}
+
+impl MalLambda {
+    pub fn new(name: Option<Symbol>, methods: Vec<Method>, env: Env) -> Self {
+        MalLambda {
+            name,
+            methods: Rc::new(methods),
+            env,
+            is_macro: false,
+            meta: None,
+        }
+    }
+}
+
+/// Maps a type keyword (see `stdlib::find_type`) to that type's method
+/// implementations, keyed by method keyword.
+type ProtocolImpls = HashMap<Keyword, Map>;
+
+#[derive(Clone)]
+pub struct Protocol {
+    pub name: Symbol,
+    pub impls: Rc<RefCell<ProtocolImpls>>,
+}
+
+impl Protocol {
+    pub fn new(name: Symbol) -> Self {
+        Protocol {
+            name,
+            impls: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum MalError {
     Normal(String),